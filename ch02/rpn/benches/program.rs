@@ -0,0 +1,38 @@
+use std::time::Instant;
+
+use rpn::{Env, Evaluator};
+
+const FORMULA: &str =
+    "x x * 3 x * + 1 - x 0 > if sqrt else neg then 6.1 5.2 4.3 * + 3.4 2.5 / 1.6 * - *";
+const RUNS: usize = 200_000;
+
+fn main() {
+    let mut evaluator: Evaluator = Evaluator::new();
+    let program = evaluator.compile(FORMULA).unwrap();
+
+    let start = Instant::now();
+    let mut string_sum = 0.0;
+    for i in 0..RUNS {
+        evaluator.env_mut().set("x", i as f64);
+        string_sum += evaluator.eval(FORMULA).unwrap();
+    }
+    let string_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut program_sum = 0.0;
+    let mut env = Env::new();
+    for i in 0..RUNS {
+        env.set("x", i as f64);
+        program_sum += program.run(&env).unwrap();
+    }
+    let program_time = start.elapsed();
+
+    assert_eq!(string_sum, program_sum);
+    println!("{} runs of {:?}", RUNS, FORMULA);
+    println!("Evaluator::eval: {:?}", string_time);
+    println!("Program::run:    {:?}", program_time);
+    println!(
+        "speedup:         {:.1}x",
+        string_time.as_secs_f64() / program_time.as_secs_f64()
+    );
+}
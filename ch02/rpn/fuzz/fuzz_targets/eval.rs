@@ -0,0 +1,31 @@
+//! Feeds arbitrary input to every number type and the converters; run with
+//! `cargo fuzz run eval -- -timeout=5`. Failing is fine, panicking is not,
+//! and neither is running past the timeout: nesting, steps and exact number
+//! sizes are all bounded.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rpn::number::{Decimal, Rational};
+use rpn::{infix, Evaluator, Expr, Interval, Time, Units, Value, Word, WordSize};
+
+fuzz_target!(|data: &[u8]| {
+    let exp = match std::str::from_utf8(data) {
+        Ok(exp) => exp,
+        Err(_) => return,
+    };
+    let _ = Evaluator::<f64>::new().eval(exp);
+    let _ = Evaluator::<i64>::new().eval(exp);
+    let _ = Evaluator::<Rational>::new().eval(exp);
+    let _ = Evaluator::<Decimal>::new().eval(exp);
+    let _ = Evaluator::with_operators(Value::operators()).eval(exp);
+    let _ = Units::builtin().evaluator().eval(exp);
+    let _ = Evaluator::with_operators(Interval::operators()).eval(exp);
+    let _ = Evaluator::with_operators(Expr::operators()).eval(exp);
+    let _ = Evaluator::with_operators(Time::operators()).eval(exp);
+    let _ = Word::evaluator(WordSize::new(16, false).unwrap(), 16).eval(exp);
+    let _ = Evaluator::<f64>::new().compile(exp);
+    let _ = Evaluator::<f64>::new().trace(exp, Some(64));
+    let _ = Evaluator::<f64>::new().load(exp);
+    let _ = infix::to_rpn(exp);
+    let _ = infix::to_infix(exp);
+});
@@ -0,0 +1,107 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::number::Number;
+
+/// Named values an expression can read with `x` or `x @` and write with `x !`.
+///
+/// Constants can be read like variables but not overwritten by expressions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Env<N> {
+    values: HashMap<String, N>,
+    constants: HashSet<String>,
+}
+
+impl<N: Number> Env<N> {
+    /// Creates an environment holding the constants `pi` and `e`, as far as `N` can represent them.
+    pub fn new() -> Self {
+        let mut env = Self::empty();
+        for &(name, value) in &[("pi", std::f64::consts::PI), ("e", std::f64::consts::E)] {
+            if let Some(value) = N::from_f64(value) {
+                env.define_constant(name, value);
+            }
+        }
+        env
+    }
+
+    /// Creates an environment without any names.
+    pub fn empty() -> Self {
+        Self {
+            values: HashMap::new(),
+            constants: HashSet::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&N> {
+        self.values.get(name)
+    }
+
+    /// Sets the variable `name`, returning `false` if it is a constant.
+    pub fn set(&mut self, name: &str, value: N) -> bool {
+        if self.is_constant(name) {
+            return false;
+        }
+        self.values.insert(name.to_string(), value);
+        true
+    }
+
+    /// Defines (or redefines) a read-only name.
+    pub fn define_constant(&mut self, name: &str, value: N) {
+        self.values.insert(name.to_string(), value);
+        self.constants.insert(name.to_string());
+    }
+
+    pub fn is_constant(&self, name: &str) -> bool {
+        self.constants.contains(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<N> {
+        self.constants.remove(name);
+        self.values.remove(name)
+    }
+
+    /// Returns the names and values in alphabetical order.
+    pub fn iter(&self) -> Vec<(&str, &N)> {
+        let mut entries: Vec<(&str, &N)> =
+            self.values.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+}
+
+impl<N: Number> Default for Env<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tells whether `token` can name a variable: a letter or `_` followed by letters, digits or `_`.
+pub fn is_identifier(token: &str) -> bool {
+    let mut chars = token.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_identifier, Env};
+
+    #[test]
+    fn constants_are_read_only() {
+        let mut env = Env::<f64>::new();
+        assert_eq!(env.get("pi"), Some(&std::f64::consts::PI));
+        assert!(!env.set("e", 3.0));
+        assert!(env.set("x", 3.0));
+        assert_eq!(env.get("x"), Some(&3.0));
+        assert!(Env::<i64>::new().get("pi").is_none());
+    }
+
+    #[test]
+    fn identifiers() {
+        assert!(is_identifier("rate_2"));
+        assert!(!is_identifier("2x"));
+        assert!(!is_identifier("+"));
+        assert!(!is_identifier(""));
+    }
+}
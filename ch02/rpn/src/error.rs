@@ -0,0 +1,306 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::token::{render_caret, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpnError {
+    UnknownToken {
+        token: String,
+        offset: usize,
+    },
+    StackUnderflow {
+        operator: String,
+    },
+    EmptyStack,
+    LeftoverValues {
+        count: usize,
+    },
+    Arithmetic {
+        operator: String,
+        error: ArithError,
+    },
+    UndefinedVariable {
+        name: String,
+    },
+    ConstantAssignment {
+        name: String,
+    },
+    UnterminatedDefinition {
+        offset: usize,
+    },
+    InvalidWordName {
+        name: String,
+        offset: usize,
+    },
+    RecursionLimit {
+        word: String,
+        depth: usize,
+    },
+    /// More blocks are open at once than the evaluator allows.
+    NestingLimit {
+        word: String,
+        depth: usize,
+        offset: usize,
+    },
+    /// The evaluation ran more steps than the evaluator allows.
+    StepLimit {
+        steps: u64,
+    },
+    UnmatchedControl {
+        word: String,
+        offset: usize,
+    },
+    NotAnInteger {
+        operator: String,
+    },
+    NotCompilable {
+        token: String,
+        offset: usize,
+    },
+    BranchDepthMismatch {
+        offset: usize,
+    },
+    /// Tracing stopped before running step `step` (counted from 1).
+    Breakpoint {
+        step: usize,
+    },
+}
+
+impl RpnError {
+    /// A stable name for the kind of error, e.g. `stack_underflow`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RpnError::UnknownToken { .. } => "unknown_token",
+            RpnError::StackUnderflow { .. } | RpnError::EmptyStack => "stack_underflow",
+            RpnError::LeftoverValues { .. } => "leftover_values",
+            RpnError::Arithmetic { .. } => "arithmetic",
+            RpnError::UndefinedVariable { .. } => "undefined_variable",
+            RpnError::ConstantAssignment { .. } => "constant_assignment",
+            RpnError::UnterminatedDefinition { .. } => "unterminated_definition",
+            RpnError::InvalidWordName { .. } => "invalid_word_name",
+            RpnError::RecursionLimit { .. } => "recursion_limit",
+            RpnError::NestingLimit { .. } => "nesting_limit",
+            RpnError::StepLimit { .. } => "step_limit",
+            RpnError::UnmatchedControl { .. } => "unmatched_control",
+            RpnError::NotAnInteger { .. } => "not_an_integer",
+            RpnError::NotCompilable { .. } => "not_compilable",
+            RpnError::BranchDepthMismatch { .. } => "branch_depth_mismatch",
+            RpnError::Breakpoint { .. } => "breakpoint",
+        }
+    }
+
+    /// Tells whether the expression is malformed, as opposed to failing while it runs.
+    pub fn is_parse_error(&self) -> bool {
+        matches!(
+            self,
+            RpnError::UnknownToken { .. }
+                | RpnError::UnterminatedDefinition { .. }
+                | RpnError::InvalidWordName { .. }
+                | RpnError::UnmatchedControl { .. }
+                | RpnError::NotCompilable { .. }
+                | RpnError::BranchDepthMismatch { .. }
+        )
+    }
+}
+
+/// An error together with the span of the token that caused it, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub error: RpnError,
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    /// Renders the error followed by the offending line of `source` with the
+    /// token underlined by `^`.
+    pub fn render(&self, source: &str) -> String {
+        match self.span {
+            Some(span) => format!("{}\n{}", self.error, render_caret(source, span)),
+            None => self.error.to_string(),
+        }
+    }
+}
+
+impl From<RpnError> for Diagnostic {
+    fn from(error: RpnError) -> Self {
+        Self { error, span: None }
+    }
+}
+
+/// A failure to convert an infix expression, see [`crate::infix::to_rpn`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum InfixError {
+    MismatchedParen { offset: usize },
+    UnexpectedToken { token: String, offset: usize },
+    UnexpectedEnd,
+}
+
+impl InfixError {
+    /// Returns where in the infix expression the error is.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            InfixError::MismatchedParen { offset } => Some(Span::new(*offset, offset + 1)),
+            InfixError::UnexpectedToken { token, offset } => {
+                Some(Span::new(*offset, offset + token.len()))
+            }
+            InfixError::UnexpectedEnd => None,
+        }
+    }
+}
+
+/// A failure to restore a session, see [`crate::session::restore`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionError {
+    MissingHeader,
+    UnsupportedVersion { version: u32 },
+    UnknownEntry { line: usize, entry: String },
+    InvalidEntry { line: usize, error: RpnError },
+}
+
+/// A failure of an operation on numbers, see [`crate::number::Number`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithError {
+    Overflow,
+    DivisionByZero,
+    /// The result exists but the number type cannot hold it, e.g. `2 sqrt` on integers.
+    NotRepresentable,
+    /// The operands have dimensions the operation cannot combine, e.g. `1m 1s +`.
+    IncompatibleUnits,
+    /// The operation does not apply to the operands, e.g. adding vectors of
+    /// different lengths.
+    TypeMismatch,
+}
+
+impl fmt::Display for RpnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpnError::UnknownToken { token, offset } => {
+                write!(f, "Unknown operator: {} (at byte {})", token, offset)
+            }
+            RpnError::StackUnderflow { operator } => {
+                write!(f, "Stack underflow: {}", operator)
+            }
+            RpnError::EmptyStack => write!(f, "Stack underflow: no result"),
+            RpnError::LeftoverValues { count } => {
+                write!(f, "{} value(s) left on the stack", count)
+            }
+            RpnError::Arithmetic { operator, error } => write!(f, "{}: {}", operator, error),
+            RpnError::UndefinedVariable { name } => write!(f, "Undefined variable: {}", name),
+            RpnError::ConstantAssignment { name } => {
+                write!(f, "Cannot assign to constant: {}", name)
+            }
+            RpnError::UnterminatedDefinition { offset } => {
+                write!(f, "Definition without `;` (at byte {})", offset)
+            }
+            RpnError::InvalidWordName { name, offset } => {
+                write!(f, "Invalid word name: {} (at byte {})", name, offset)
+            }
+            RpnError::RecursionLimit { word, depth } => {
+                write!(f, "Recursion limit of {} reached in {}", depth, word)
+            }
+            RpnError::NestingLimit {
+                word,
+                depth,
+                offset,
+            } => {
+                write!(
+                    f,
+                    "Nesting limit of {} reached at {} (at byte {})",
+                    depth, word, offset
+                )
+            }
+            RpnError::StepLimit { steps } => write!(f, "Step limit of {} reached", steps),
+            RpnError::UnmatchedControl { word, offset } => {
+                write!(f, "Unmatched {} (at byte {})", word, offset)
+            }
+            RpnError::NotAnInteger { operator } => {
+                write!(f, "{} expects an integer", operator)
+            }
+            RpnError::NotCompilable { token, offset } => {
+                write!(f, "{} cannot be compiled (at byte {})", token, offset)
+            }
+            RpnError::BranchDepthMismatch { offset } => {
+                write!(
+                    f,
+                    "Branches leave different stack depths (at byte {})",
+                    offset
+                )
+            }
+            RpnError::Breakpoint { step } => {
+                write!(f, "Stopped at breakpoint before step {}", step)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl fmt::Display for InfixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InfixError::MismatchedParen { offset } => {
+                write!(f, "Mismatched parenthesis (at byte {})", offset)
+            }
+            InfixError::UnexpectedToken { token, offset } => {
+                write!(f, "Unexpected {} (at byte {})", token, offset)
+            }
+            InfixError::UnexpectedEnd => write!(f, "Unexpected end of expression"),
+        }
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionError::MissingHeader => write!(f, "Not a session file"),
+            SessionError::UnsupportedVersion { version } => write!(
+                f,
+                "Unsupported session version {} (expected at most {})",
+                version,
+                crate::session::VERSION
+            ),
+            SessionError::UnknownEntry { line, entry } => {
+                write!(f, "Unknown entry on line {}: {}", line, entry)
+            }
+            SessionError::InvalidEntry { line, error } => write!(f, "Line {}: {}", line, error),
+        }
+    }
+}
+
+impl fmt::Display for ArithError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArithError::Overflow => write!(f, "overflow"),
+            ArithError::DivisionByZero => write!(f, "division by zero"),
+            ArithError::NotRepresentable => write!(f, "result is not representable"),
+            ArithError::IncompatibleUnits => write!(f, "incompatible units"),
+            ArithError::TypeMismatch => write!(f, "type mismatch"),
+        }
+    }
+}
+
+impl Error for RpnError {}
+
+impl Error for Diagnostic {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl Error for InfixError {}
+
+impl Error for SessionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SessionError::InvalidEntry { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl Error for ArithError {}
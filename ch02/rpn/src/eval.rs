@@ -0,0 +1,1228 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::env::{is_identifier, Env};
+use crate::error::{ArithError, Diagnostic, RpnError};
+use crate::history::History;
+use crate::number::Number;
+use crate::ops::Operators;
+use crate::program::Program;
+use crate::stats::{self, STACK_WIDE_WORDS};
+use crate::token::{tokenize, Span, Token};
+use crate::trace::{Action, Trace, TraceStep, Tracer};
+
+pub type ParseFn<N> = Rc<dyn Fn(&str) -> Option<N>>;
+
+type RewriteFn = Rc<dyn Fn(&str) -> Option<String>>;
+
+/// A stack machine evaluating whitespace-separated RPN expressions.
+///
+/// The stack is kept between calls to [`Evaluator::run`], so values can be
+/// pushed by the caller before an expression is evaluated.
+pub struct Evaluator<N = f64> {
+    operators: Operators<N>,
+    parser: ParseFn<N>,
+    /// Rewrites literals before they are parsed, see [`Evaluator::rewrite_literals`].
+    rewrite: Option<RewriteFn>,
+    env: Env<N>,
+    words: HashMap<String, Rc<[String]>>,
+    max_depth: usize,
+    /// The number of blocks open while running.
+    nesting: usize,
+    /// The stack depth the innermost open `[` started at, which nothing in
+    /// it may pop below.
+    floor: usize,
+    max_steps: u64,
+    /// The steps taken by the current run.
+    steps: u64,
+    loop_indices: Vec<i64>,
+    tracer: Option<Tracer<N>>,
+    /// The innermost token that failed during the current run.
+    failed_at: Option<Span>,
+    history: History<N>,
+    stack: Vec<N>,
+}
+
+/// The stack, variables and words of an evaluator at one point, to go back to
+/// with [`Evaluator::restore`].
+pub(crate) struct Snapshot<N> {
+    stack: Vec<N>,
+    env: Env<N>,
+    words: HashMap<String, Rc<[String]>>,
+}
+
+/// How deeply user-defined words may call each other by default.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// How deeply `if`, `times`, `do` and `[` blocks may nest.
+pub const MAX_NESTING: usize = 256;
+
+/// How many tokens one run may execute by default, counting every loop
+/// iteration and word call.
+pub const DEFAULT_MAX_STEPS: u64 = 10_000_000;
+
+const STACK_WORDS: &[&str] = &["clear", "dup", "drop", "swap"];
+
+const CONTROL_WORDS: &[&str] = &[
+    ":", ";", "if", "else", "then", "times", "do", "loop", "[", "]",
+];
+
+impl<N: Number> Evaluator<N> {
+    /// Creates an evaluator with the builtin operators.
+    pub fn new() -> Self {
+        Self::with_operators(Operators::builtin())
+    }
+
+    /// Creates an evaluator without any operators.
+    pub fn empty() -> Self {
+        Self::with_operators(Operators::new())
+    }
+
+    pub fn with_operators(operators: Operators<N>) -> Self {
+        Self {
+            operators,
+            parser: Rc::new(N::parse),
+            rewrite: None,
+            env: Env::new(),
+            words: HashMap::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            nesting: 0,
+            floor: 0,
+            max_steps: DEFAULT_MAX_STEPS,
+            steps: 0,
+            loop_indices: Vec::new(),
+            tracer: None,
+            failed_at: None,
+            history: History::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Creates an evaluator with the same operators and literal parser, but
+    /// without the stack, variables, words and literal rewriting of this one.
+    /// It reads values as they are saved, in plain notation.
+    pub(crate) fn scratch(&self) -> Self {
+        let mut scratch = Self::with_operators(self.operators.clone());
+        scratch.parser = Rc::clone(&self.parser);
+        scratch
+    }
+
+    pub fn operators(&self) -> &Operators<N> {
+        &self.operators
+    }
+
+    /// Gives access to the operator table, e.g. to register custom operators.
+    pub fn operators_mut(&mut self) -> &mut Operators<N> {
+        &mut self.operators
+    }
+
+    /// Parses a number literal the way the evaluator does.
+    pub fn parse(&self, token: &str) -> Option<N> {
+        match &self.rewrite {
+            Some(rewrite) => (self.parser)(&rewrite(token)?),
+            None => (self.parser)(token),
+        }
+    }
+
+    /// Replaces how number literals are parsed, which is [`Number::parse`] by
+    /// default, e.g. to accept literals with units.
+    pub fn set_parser<F>(&mut self, parse: F)
+    where
+        F: Fn(&str) -> Option<N> + 'static,
+    {
+        self.parser = Rc::new(parse);
+    }
+
+    /// Reads literals through `rewrite` before parsing them: it turns each
+    /// into one the parser accepts, e.g. `6,1` into `6.1`, or returns `None`
+    /// to reject it.
+    pub fn rewrite_literals<F>(&mut self, rewrite: F)
+    where
+        F: Fn(&str) -> Option<String> + 'static,
+    {
+        self.rewrite = Some(Rc::new(rewrite));
+    }
+
+    pub fn env(&self) -> &Env<N> {
+        &self.env
+    }
+
+    /// Gives access to the variables, e.g. to bind the parameters of a formula.
+    pub fn env_mut(&mut self) -> &mut Env<N> {
+        &mut self.env
+    }
+
+    pub fn set_env(&mut self, env: Env<N>) {
+        self.env = env
+    }
+
+    pub fn stack(&self) -> &[N] {
+        &self.stack
+    }
+
+    pub fn push(&mut self, value: N) {
+        self.stack.push(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear()
+    }
+
+    /// Replaces the whole stack, e.g. to restore it after a failed line.
+    pub fn set_stack(&mut self, stack: Vec<N>) {
+        self.stack = stack
+    }
+
+    /// Records the stack, variables and words.
+    pub(crate) fn snapshot(&self) -> Snapshot<N> {
+        Snapshot {
+            stack: self.stack.clone(),
+            env: self.env.clone(),
+            words: self.words.clone(),
+        }
+    }
+
+    /// Puts back the stack, variables and words `snapshot` recorded.
+    pub(crate) fn restore(&mut self, snapshot: Snapshot<N>) {
+        self.stack = snapshot.stack;
+        self.env = snapshot.env;
+        self.words = snapshot.words;
+    }
+
+    /// Returns the snapshots [`Evaluator::undo`] and [`Evaluator::redo`] use.
+    /// Nothing is recorded unless the caller does, as the REPL does for every
+    /// line that changes the stack.
+    pub fn history(&self) -> &History<N> {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History<N> {
+        &mut self.history
+    }
+
+    /// Restores the stack before the last recorded change, returning `false`
+    /// if there is none.
+    pub fn undo(&mut self) -> bool {
+        match self.history.undo(&self.stack) {
+            Some(stack) => {
+                self.stack = stack;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Restores the stack the last [`Evaluator::undo`] replaced, returning
+    /// `false` if there is none.
+    pub fn redo(&mut self) -> bool {
+        match self.history.redo(&self.stack) {
+            Some(stack) => {
+                self.stack = stack;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs every token of `exp` against the current stack.
+    pub fn run(&mut self, exp: &str) -> Result<(), RpnError> {
+        self.run_spanned(exp).map_err(|d| d.error)
+    }
+
+    /// Like [`Evaluator::run`], but a failure also tells which token of `exp`
+    /// failed. Tokens of a user-defined word report the span of the call.
+    pub fn run_spanned(&mut self, exp: &str) -> Result<(), Diagnostic> {
+        self.failed_at = None;
+        self.steps = 0;
+        let tokens = tokenize(exp);
+        self.exec(&tokens, 0).map_err(|error| Diagnostic {
+            error,
+            span: self.failed_at.take(),
+        })
+    }
+
+    /// Runs a library of definitions, ignoring everything after `#` on each line.
+    pub fn load(&mut self, source: &str) -> Result<(), Diagnostic> {
+        // Comments are blanked out rather than removed so offsets stay valid.
+        let source: String = source
+            .lines()
+            .map(|line| match line.find('#') {
+                Some(i) => format!("{}{}\n", &line[..i], " ".repeat(line.len() - i)),
+                None => format!("{}\n", line),
+            })
+            .collect();
+        self.run_spanned(&source)
+    }
+
+    /// Defines the word `name` as `body`, like `: name body ;` would.
+    pub fn define(&mut self, name: &str, body: &str) -> Result<(), RpnError> {
+        self.run(&format!(": {} {} ;", name, body))
+    }
+
+    /// Returns the body of the user-defined word `name`.
+    pub fn word(&self, name: &str) -> Option<&[String]> {
+        self.words.get(name).map(|body| &body[..])
+    }
+
+    /// Returns the names of the user-defined words in alphabetical order.
+    pub fn words(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.words.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Sets how deeply user-defined words may call each other.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth
+    }
+
+    pub fn max_steps(&self) -> u64 {
+        self.max_steps
+    }
+
+    /// Sets how many tokens one run may execute before it fails.
+    pub fn set_max_steps(&mut self, max_steps: u64) {
+        self.max_steps = max_steps
+    }
+
+    fn exec(&mut self, tokens: &[Token], depth: usize) -> Result<(), RpnError> {
+        let mut i = 0;
+        while i < tokens.len() {
+            let start = i;
+            let result = self.begin_step(tokens, i, depth).and_then(|step| {
+                let result = self.exec_one(tokens, &mut i, depth);
+                if let Some(step) = step {
+                    self.end_step(step, &tokens[start..i]);
+                }
+                result
+            });
+            if result.is_err() && self.failed_at.is_none() {
+                self.failed_at = Some(tokens[start].span);
+            }
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Runs the token at `*i` and advances `*i` past everything it consumed.
+    fn exec_one(&mut self, tokens: &[Token], i: &mut usize, depth: usize) -> Result<(), RpnError> {
+        let token = &tokens[*i];
+        *i += 1;
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            return Err(RpnError::StepLimit {
+                steps: self.max_steps,
+            });
+        }
+        let next = tokens.get(*i).map(|next| next.text);
+        if token.text == ":" {
+            *i = self.compile_definition(tokens, *i, token.span.start)?;
+        } else if let Some(end) = self.exec_control(tokens, *i, depth)? {
+            *i = end;
+        } else if (next == Some("!") || next == Some("@")) && is_identifier(token.text) {
+            *i += 1;
+            self.access_variable(token.text, next.unwrap())?;
+        } else if next == Some("map") && token.text.len() > 1 && token.text.starts_with('\'') {
+            *i += 1;
+            self.map_word(token, depth)?;
+        } else {
+            self.exec_token(token, depth)?;
+        }
+        Ok(())
+    }
+
+    /// Evaluates `exp` recording every step, stopping before step `breakpoint + 1`
+    /// if a breakpoint is given.
+    pub fn trace(&mut self, exp: &str, breakpoint: Option<usize>) -> Trace<N> {
+        self.tracer = Some(Tracer {
+            steps: Vec::new(),
+            breakpoint,
+        });
+        let result = self.run(exp);
+        let steps = self.tracer.take().map(|t| t.steps).unwrap_or_default();
+        Trace { steps, result }
+    }
+
+    /// Records the stack before `tokens[i]` runs, returning the index of the new step.
+    fn begin_step(
+        &mut self,
+        tokens: &[Token],
+        i: usize,
+        depth: usize,
+    ) -> Result<Option<usize>, RpnError> {
+        let recorded = match &self.tracer {
+            Some(tracer) => tracer.steps.len(),
+            None => return Ok(None),
+        };
+        if let Some(Some(breakpoint)) = self.tracer.as_ref().map(|t| t.breakpoint) {
+            if recorded >= breakpoint {
+                return Err(RpnError::Breakpoint { step: recorded + 1 });
+            }
+        }
+        let action = self.classify(tokens, i);
+        let step = TraceStep {
+            token: tokens[i].text.to_string(),
+            span: tokens[i].span,
+            action,
+            depth,
+            before: self.stack.clone(),
+            after: Vec::new(),
+        };
+        let tracer = self.tracer.as_mut().unwrap();
+        tracer.steps.push(step);
+        Ok(Some(recorded))
+    }
+
+    /// Records the stack after the tokens of step `step` ran.
+    fn end_step(&mut self, step: usize, consumed: &[Token]) {
+        let after = self.stack.clone();
+        if let Some(tracer) = self.tracer.as_mut() {
+            let step = &mut tracer.steps[step];
+            step.after = after;
+            if let [_, access] = consumed {
+                if ["!", "@", "map"].contains(&access.text) {
+                    step.token = format!("{} {}", step.token, access.text);
+                }
+            }
+        }
+    }
+
+    fn classify(&self, tokens: &[Token], i: usize) -> Action {
+        let text = tokens[i].text;
+        let next = tokens.get(i + 1).map(|next| next.text);
+        if text == ":" {
+            Action::Define
+        } else if CONTROL_WORDS.contains(&text) {
+            Action::Control
+        } else if next == Some("!") && is_identifier(text) {
+            Action::Store
+        } else if next == Some("@") && is_identifier(text) {
+            Action::Load
+        } else if next == Some("map") && text.starts_with('\'') {
+            Action::Call
+        } else if self.parse(text).is_some() {
+            Action::Push
+        } else if self.words.contains_key(text) {
+            Action::Call
+        } else if STACK_WORDS.contains(&text) || STACK_WIDE_WORDS.contains(&text) {
+            Action::StackWord
+        } else if let Some(op) = self.operators.registered(text) {
+            Action::Apply { arity: op.arity() }
+        } else if self.env.get(text).is_some() || (text == "i" && !self.loop_indices.is_empty()) {
+            Action::Load
+        } else if let Some(op) = self.operators.resolve(text) {
+            Action::Apply { arity: op.arity() }
+        } else {
+            Action::Unknown
+        }
+    }
+
+    /// Runs the control structure opened by `tokens[start - 1]`, returning the
+    /// index after it, or `None` if that token is no control word.
+    fn exec_control(
+        &mut self,
+        tokens: &[Token],
+        start: usize,
+        depth: usize,
+    ) -> Result<Option<usize>, RpnError> {
+        let token = &tokens[start - 1];
+        match token.text {
+            "if" => {
+                let (else_at, then_at) = find_block_end(tokens, start)?;
+                let flag = self.pop("if")?;
+                let branch = match (flag.is_truthy(), else_at) {
+                    (true, Some(else_at)) => &tokens[start..else_at],
+                    (true, None) => &tokens[start..then_at],
+                    (false, Some(else_at)) => &tokens[else_at + 1..then_at],
+                    (false, None) => &[],
+                };
+                self.exec_block(token, branch, depth)?;
+                Ok(Some(then_at + 1))
+            }
+            "times" => {
+                let (_, end) = find_block_end(tokens, start)?;
+                let count = self.pop_integer("times")?;
+                for _ in 0..count {
+                    self.exec_block(token, &tokens[start..end], depth)?;
+                }
+                Ok(Some(end + 1))
+            }
+            "do" => {
+                let (_, end) = find_block_end(tokens, start)?;
+                let first = self.pop_integer("do")?;
+                let limit = self.pop_integer("do")?;
+                for index in first..limit {
+                    self.loop_indices.push(index);
+                    let result = self.exec_block(token, &tokens[start..end], depth);
+                    self.loop_indices.pop();
+                    result?;
+                }
+                Ok(Some(end + 1))
+            }
+            "[" => {
+                let (_, end) = find_block_end(tokens, start)?;
+                let base = self.stack.len();
+                let floor = std::mem::replace(&mut self.floor, base);
+                let result = self.exec_block(token, &tokens[start..end], depth);
+                self.floor = floor;
+                result?;
+                let items = self.stack.split_off(base);
+                let vector = N::from_vector(items).ok_or_else(|| RpnError::Arithmetic {
+                    operator: "[".to_string(),
+                    error: ArithError::NotRepresentable,
+                })?;
+                self.stack.push(vector);
+                Ok(Some(end + 1))
+            }
+            "else" | "then" | "loop" | "]" => Err(RpnError::UnmatchedControl {
+                word: token.text.to_string(),
+                offset: token.span.start,
+            }),
+            _ => Ok(None),
+        }
+    }
+
+    /// Runs the body of the block `opener` opens, failing when
+    /// [`MAX_NESTING`] blocks are open already.
+    fn exec_block(&mut self, opener: &Token, body: &[Token], depth: usize) -> Result<(), RpnError> {
+        if self.nesting >= MAX_NESTING {
+            return Err(RpnError::NestingLimit {
+                word: opener.text.to_string(),
+                depth: MAX_NESTING,
+                offset: opener.span.start,
+            });
+        }
+        self.nesting += 1;
+        let result = self.exec(body, depth);
+        self.nesting -= 1;
+        result
+    }
+
+    fn pop(&mut self, operator: &str) -> Result<N, RpnError> {
+        self.ensure(operator, 1)?;
+        Ok(self.stack.pop().unwrap())
+    }
+
+    /// Fails unless `operator` can take `needed` values, blaming the
+    /// innermost open `[` if they are there but some lie below its stack.
+    fn ensure(&self, operator: &str, needed: usize) -> Result<(), RpnError> {
+        if self.stack.len() >= self.floor + needed {
+            return Ok(());
+        }
+        let operator = if self.stack.len() >= needed {
+            "["
+        } else {
+            operator
+        };
+        Err(RpnError::StackUnderflow {
+            operator: operator.to_string(),
+        })
+    }
+
+    fn pop_integer(&mut self, operator: &str) -> Result<i64, RpnError> {
+        let x = self.pop(operator)?.to_f64();
+        if x.fract() == 0.0 && x.abs() < i64::MAX as f64 {
+            Ok(x as i64)
+        } else {
+            Err(RpnError::NotAnInteger {
+                operator: operator.to_string(),
+            })
+        }
+    }
+
+    fn exec_token(&mut self, token: &Token, depth: usize) -> Result<(), RpnError> {
+        if let Some(num) = self.parse(token.text) {
+            self.stack.push(num);
+        } else if let Some(body) = self.words.get(token.text).cloned() {
+            if depth >= self.max_depth {
+                return Err(RpnError::RecursionLimit {
+                    word: token.text.to_string(),
+                    depth,
+                });
+            }
+            // Errors inside the body are reported at the call site.
+            let body: Vec<Token> = body
+                .iter()
+                .map(|text| Token {
+                    text,
+                    span: token.span,
+                })
+                .collect();
+            self.exec(&body, depth + 1)?;
+        } else if self.stack_word(token.text)? || self.stack_wide_word(token.text)? {
+            // dup, swap and friends have already been applied
+        } else if let Some(op) = self.operators.registered(token.text) {
+            self.ensure(token.text, op.arity())?;
+            op.apply(token.text, &mut self.stack)?;
+        } else if let (Some(&index), "i") = (self.loop_indices.last(), token.text) {
+            self.stack.push(N::from_i64(index));
+        } else if let Some(value) = self.env.get(token.text) {
+            self.stack.push(value.clone());
+        } else if let Some(op) = self.operators.resolve(token.text) {
+            self.ensure(token.text, op.arity())?;
+            op.apply(token.text, &mut self.stack)?;
+        } else {
+            return Err(RpnError::UnknownToken {
+                token: token.text.to_string(),
+                offset: token.span.start,
+            });
+        }
+        Ok(())
+    }
+
+    /// Runs the word quoted by `quote` (`'name`) on every element of the
+    /// vector on top of the stack, collecting one result per element.
+    fn map_word(&mut self, quote: &Token, depth: usize) -> Result<(), RpnError> {
+        let word = Token {
+            text: &quote.text[1..],
+            span: quote.span,
+        };
+        let items = self.pop("map")?.to_vector().ok_or(RpnError::Arithmetic {
+            operator: "map".to_string(),
+            error: ArithError::TypeMismatch,
+        })?;
+        let mut results = Vec::with_capacity(items.len());
+        for item in items {
+            let base = self.stack.len();
+            self.stack.push(item);
+            self.exec_token(&word, depth)?;
+            if self.stack.len() <= base {
+                return Err(RpnError::StackUnderflow {
+                    operator: "map".to_string(),
+                });
+            }
+            if self.stack.len() > base + 1 {
+                return Err(RpnError::LeftoverValues {
+                    count: self.stack.len() - base - 1,
+                });
+            }
+            results.push(self.stack.pop().unwrap());
+        }
+        self.stack.push(N::from_vector(results).unwrap());
+        Ok(())
+    }
+
+    /// Reads `name body ;` starting at `tokens[start]` and returns the index after `;`.
+    fn compile_definition(
+        &mut self,
+        tokens: &[Token],
+        start: usize,
+        offset: usize,
+    ) -> Result<usize, RpnError> {
+        let name = match tokens.get(start) {
+            Some(name) => name,
+            None => return Err(RpnError::UnterminatedDefinition { offset }),
+        };
+        if CONTROL_WORDS.contains(&name.text) || self.parse(name.text).is_some() {
+            return Err(RpnError::InvalidWordName {
+                name: name.text.to_string(),
+                offset: name.span.start,
+            });
+        }
+        let mut body = Vec::new();
+        for (i, token) in tokens.iter().enumerate().skip(start + 1) {
+            match token.text {
+                ";" => {
+                    self.words.insert(name.text.to_string(), body.into());
+                    return Ok(i + 1);
+                }
+                ":" => break,
+                text => body.push(text.to_string()),
+            }
+        }
+        Err(RpnError::UnterminatedDefinition { offset })
+    }
+
+    /// Stores the top of the stack into `name` (`!`) or pushes its value (`@`).
+    fn access_variable(&mut self, name: &str, access: &str) -> Result<(), RpnError> {
+        if access == "@" {
+            let value = self
+                .env
+                .get(name)
+                .ok_or_else(|| RpnError::UndefinedVariable {
+                    name: name.to_string(),
+                })?;
+            self.stack.push(value.clone());
+            return Ok(());
+        }
+        if self.env.is_constant(name) {
+            return Err(RpnError::ConstantAssignment {
+                name: name.to_string(),
+            });
+        }
+        let value = self.pop("!")?;
+        self.env.set(name, value);
+        Ok(())
+    }
+
+    /// Applies the stack manipulation word `token`, returning `false` if it is not one.
+    fn stack_word(&mut self, token: &str) -> Result<bool, RpnError> {
+        let needed = match token {
+            "clear" => self.stack.len(),
+            "dup" | "drop" => 1,
+            "swap" => 2,
+            _ => return Ok(false),
+        };
+        self.ensure(token, needed)?;
+        let len = self.stack.len();
+        match token {
+            "clear" => self.stack.clear(),
+            "dup" => self.stack.push(self.stack[len - 1].clone()),
+            "drop" => {
+                self.stack.pop();
+            }
+            _ => self.stack.swap(len - 1, len - 2),
+        }
+        Ok(true)
+    }
+
+    /// Applies `roll`, `pick` or a word aggregating the whole stack, returning
+    /// `false` if `token` is none of them.
+    ///
+    /// `n roll` moves the value `n` levels below the top to the top and
+    /// `n pick` copies it, so `0 pick` is `dup` and `1 roll` is `swap`. The
+    /// aggregates replace every value with one result, or a vector on top
+    /// with the result for its elements.
+    fn stack_wide_word(&mut self, token: &str) -> Result<bool, RpnError> {
+        if !STACK_WIDE_WORDS.contains(&token) {
+            return Ok(false);
+        }
+        let underflow = || RpnError::StackUnderflow {
+            operator: token.to_string(),
+        };
+        if token == "roll" || token == "pick" {
+            let n = self.pop_integer(token)?;
+            if n < 0 {
+                return Err(underflow());
+            }
+            self.ensure(token, n as usize + 1)?;
+            let len = self.stack.len();
+            let at = len - 1 - n as usize;
+            let value = if token == "roll" {
+                self.stack.remove(at)
+            } else {
+                self.stack[at].clone()
+            };
+            self.stack.push(value);
+            return Ok(true);
+        }
+        let arithmetic = |error| RpnError::Arithmetic {
+            operator: token.to_string(),
+            error,
+        };
+        let vector = self.stack.last().and_then(N::to_vector);
+        let in_vector = vector.is_some();
+        let values = match vector {
+            Some(items) => {
+                self.pop(token)?;
+                items
+            }
+            None => {
+                self.ensure(token, self.stack.len())?;
+                std::mem::take(&mut self.stack)
+            }
+        };
+        if token == "sort" {
+            let sorted = stats::sort(&values).map_err(arithmetic)?;
+            if in_vector {
+                self.stack.push(N::from_vector(sorted).unwrap());
+            } else {
+                self.stack.extend(sorted);
+            }
+            return Ok(true);
+        }
+        let needed = match token {
+            "count" => 0,
+            "sum" | "prod" if in_vector => 0,
+            "stddev" => 2,
+            _ => 1,
+        };
+        if values.len() < needed {
+            return Err(underflow());
+        }
+        let result = match token {
+            "count" => Ok(N::from_i64(values.len() as i64)),
+            "sum" => stats::sum(&values),
+            "prod" => stats::prod(&values),
+            "mean" => stats::mean(&values),
+            "median" => stats::median(&values),
+            _ => stats::stddev(&values),
+        };
+        let result = result.map_err(arithmetic)?;
+        self.stack.push(result);
+        Ok(true)
+    }
+
+    /// Compiles `exp` into a [`Program`] using the current operators and words.
+    pub fn compile(&self, exp: &str) -> Result<Program<N>, RpnError> {
+        Program::compile(exp, self)
+    }
+
+    /// Runs `exp` and takes its result, which must be the only value left on the stack.
+    pub fn eval(&mut self, exp: &str) -> Result<N, RpnError> {
+        self.eval_spanned(exp).map_err(|d| d.error)
+    }
+
+    /// Like [`Evaluator::eval`], but a failure also tells which token of `exp` failed.
+    ///
+    /// Any failure clears the stack, so the next evaluation starts afresh.
+    pub fn eval_spanned(&mut self, exp: &str) -> Result<N, Diagnostic> {
+        if let Err(e) = self.run_spanned(exp) {
+            self.stack.clear();
+            return Err(e);
+        }
+        let ans = self.stack.pop().ok_or(RpnError::EmptyStack)?;
+        if self.stack.is_empty() {
+            Ok(ans)
+        } else {
+            let count = self.stack.len();
+            self.stack.clear();
+            Err(RpnError::LeftoverValues { count }.into())
+        }
+    }
+}
+
+impl<N: Number> Default for Evaluator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Finds the `else` (for `if` only) and the `then`, `loop` or `]` closing the block
+/// whose opening word is `tokens[start - 1]`.
+fn find_block_end(tokens: &[Token], start: usize) -> Result<(Option<usize>, usize), RpnError> {
+    let opener = &tokens[start - 1];
+    let mut open = vec![opener.text];
+    let mut else_at = None;
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        match token.text {
+            "if" | "times" | "do" | "[" => open.push(token.text),
+            "else" if open.len() == 1 && opener.text == "if" && else_at.is_none() => {
+                else_at = Some(i)
+            }
+            "then" | "loop" | "]" => {
+                let expected = match open.last() {
+                    Some(&"if") => "then",
+                    Some(&"[") => "]",
+                    _ => "loop",
+                };
+                if token.text != expected {
+                    break;
+                }
+                open.pop();
+                if open.is_empty() {
+                    return Ok((else_at, i));
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(RpnError::UnmatchedControl {
+        word: opener.text.to_string(),
+        offset: opener.span.start,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Evaluator, MAX_NESTING};
+    use crate::number::{Decimal, Number, Rational};
+    use crate::token::Span;
+    use crate::value::Value;
+    use crate::{rpn, ArithError, RpnError};
+
+    #[test]
+    fn eval_documented_example() {
+        let ans = rpn("6.1 5.2 4.3 * + 3.4 2.5 / 1.6 * -").unwrap();
+        assert_eq!("26.2840", format!("{:.4}", ans));
+    }
+
+    #[test]
+    fn unknown_token_reports_offset() {
+        assert_eq!(
+            rpn("1 2 ?"),
+            Err(RpnError::UnknownToken {
+                token: "?".to_string(),
+                offset: 4,
+            })
+        );
+    }
+
+    #[test]
+    fn diagnostics_point_at_the_failing_token() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator.define("half", "2 /").unwrap();
+        let span = |evaluator: &mut Evaluator, exp| {
+            evaluator.clear();
+            evaluator.run_spanned(exp).unwrap_err().span
+        };
+        assert_eq!(span(&mut evaluator, "1 2 foo +"), Some(Span::new(4, 7)));
+        assert_eq!(
+            span(&mut evaluator, "1 if 2 3 + + + then"),
+            Some(Span::new(11, 12))
+        );
+        assert_eq!(span(&mut evaluator, "  half"), Some(Span::new(2, 6)));
+        assert_eq!(span(&mut evaluator, "1 2 3 if"), Some(Span::new(6, 8)));
+        evaluator.clear();
+        let diagnostic = evaluator.eval_spanned("1 2").unwrap_err();
+        assert_eq!(diagnostic.span, None);
+        assert_eq!(diagnostic.render("1 2"), "1 value(s) left on the stack");
+        assert_eq!(
+            evaluator.eval_spanned("3 x @").unwrap_err().render("3 x @"),
+            "Undefined variable: x\n  3 x @\n    ^"
+        );
+    }
+
+    #[test]
+    fn underflow_reports_operator() {
+        assert_eq!(
+            rpn("1 +"),
+            Err(RpnError::StackUnderflow {
+                operator: "+".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn leftover_values_are_an_error() {
+        assert_eq!(rpn("1 2 3 +"), Err(RpnError::LeftoverValues { count: 1 }));
+        assert_eq!(rpn(""), Err(RpnError::EmptyStack));
+    }
+
+    #[test]
+    fn evaluator_is_reusable_after_a_failure() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        assert_eq!(
+            evaluator.eval("1 +"),
+            Err(RpnError::StackUnderflow {
+                operator: "+".to_string()
+            })
+        );
+        assert!(evaluator.stack().is_empty());
+        assert_eq!(evaluator.eval("2"), Ok(2.0));
+        assert!(evaluator.eval("1 2").is_err());
+        assert_eq!(evaluator.eval("3"), Ok(3.0));
+    }
+
+    #[test]
+    fn custom_operator_and_preloaded_stack() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator
+            .operators_mut()
+            .register_binary("hypot", |x: f64, y| Ok(x.hypot(y)));
+        evaluator.push(3.0);
+        assert_eq!(evaluator.eval("4 hypot"), Ok(5.0));
+        assert!(evaluator.stack().is_empty());
+    }
+
+    #[test]
+    fn stack_words() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator.run("1 2 swap dup").unwrap();
+        assert_eq!(evaluator.stack(), &[2.0, 1.0, 1.0]);
+        evaluator.run("drop -").unwrap();
+        assert_eq!(evaluator.stack(), &[1.0]);
+        evaluator.run("clear").unwrap();
+        assert!(evaluator.stack().is_empty());
+        assert_eq!(
+            evaluator.run("swap"),
+            Err(RpnError::StackUnderflow {
+                operator: "swap".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn variables() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        assert_eq!(evaluator.eval("3 x ! x x @ *"), Ok(9.0));
+        assert_eq!(evaluator.env().get("x"), Some(&3.0));
+        assert_eq!(evaluator.eval("2 pi *"), Ok(2.0 * std::f64::consts::PI));
+        assert_eq!(
+            evaluator.eval("1 e !"),
+            Err(RpnError::ConstantAssignment {
+                name: "e".to_string(),
+            })
+        );
+        evaluator.clear();
+        assert_eq!(
+            evaluator.eval("y @"),
+            Err(RpnError::UndefinedVariable {
+                name: "y".to_string(),
+            })
+        );
+        assert_eq!(
+            evaluator.eval("x !"),
+            Err(RpnError::StackUnderflow {
+                operator: "!".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn caller_binds_parameters() {
+        let mut evaluator = Evaluator::<Decimal>::new();
+        evaluator
+            .env_mut()
+            .set("principal", Decimal::parse("1000").unwrap());
+        evaluator
+            .env_mut()
+            .set("rate", Decimal::parse("0.035").unwrap());
+        assert_eq!(
+            evaluator.eval("principal rate *"),
+            Ok(Decimal::parse("35").unwrap())
+        );
+    }
+
+    #[test]
+    fn stack_wide_words() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        let column = "2 4 4 4 5 5 7 9";
+        let mut eval = |word: &str| evaluator.eval(&format!("{} {}", column, word));
+        assert_eq!(eval("sum"), Ok(40.0));
+        assert_eq!(eval("prod"), Ok(201_600.0));
+        assert_eq!(eval("mean"), Ok(5.0));
+        assert_eq!(eval("median"), Ok(4.5));
+        assert_eq!(eval("count"), Ok(8.0));
+        assert_eq!(eval("stddev").map(|x| (x * 1e6).round()), Ok(2_138_090.0));
+        assert_eq!(evaluator.eval("3 1 2 sort + -"), Ok(-4.0));
+        assert_eq!(evaluator.eval("count"), Ok(0.0));
+        for word in &["sum", "prod", "mean", "median"] {
+            assert_eq!(
+                evaluator.eval(word),
+                Err(RpnError::StackUnderflow {
+                    operator: word.to_string(),
+                })
+            );
+        }
+        assert_eq!(
+            evaluator.eval("1 stddev"),
+            Err(RpnError::StackUnderflow {
+                operator: "stddev".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn roll_and_pick() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator.run("1 2 3 2 roll").unwrap();
+        assert_eq!(evaluator.stack(), &[2.0, 3.0, 1.0]);
+        evaluator.run("1 pick").unwrap();
+        assert_eq!(evaluator.stack(), &[2.0, 3.0, 1.0, 3.0]);
+        evaluator.run("0 roll").unwrap();
+        assert_eq!(evaluator.stack(), &[2.0, 3.0, 1.0, 3.0]);
+        assert_eq!(
+            evaluator.run("4 pick"),
+            Err(RpnError::StackUnderflow {
+                operator: "pick".to_string(),
+            })
+        );
+        assert_eq!(
+            evaluator.run("1.5 roll"),
+            Err(RpnError::NotAnInteger {
+                operator: "roll".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn user_defined_words() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        assert_eq!(
+            evaluator.eval(": sq dup * ; : cube dup sq * ; 3 cube"),
+            Ok(27.0)
+        );
+        evaluator.define("hyp", "sq swap sq + sqrt").unwrap();
+        assert_eq!(evaluator.eval("3 4 hyp"), Ok(5.0));
+        assert_eq!(evaluator.words(), vec!["cube", "hyp", "sq"]);
+        assert_eq!(
+            evaluator.word("sq"),
+            Some(&["dup".to_string(), "*".to_string()][..])
+        );
+    }
+
+    #[test]
+    fn words_are_bound_late() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator.run(": area r sq pi * ; : sq dup * ;").unwrap();
+        evaluator.env_mut().set("r", 2.0);
+        assert_eq!(evaluator.eval("area"), Ok(4.0 * std::f64::consts::PI));
+        assert_eq!(
+            evaluator.eval(": f g ; 1 f"),
+            Err(RpnError::UnknownToken {
+                token: "g".to_string(),
+                offset: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_definitions() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        assert_eq!(
+            evaluator.run("1 : sq dup *"),
+            Err(RpnError::UnterminatedDefinition { offset: 2 })
+        );
+        assert_eq!(
+            evaluator.run(": 2 dup ;"),
+            Err(RpnError::InvalidWordName {
+                name: "2".to_string(),
+                offset: 2,
+            })
+        );
+        assert_eq!(evaluator.words(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn recursion_is_limited() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator.set_max_depth(10);
+        assert_eq!(
+            evaluator.run(": forever 1 + forever ; 0 forever"),
+            Err(RpnError::RecursionLimit {
+                word: "forever".to_string(),
+                depth: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        let deep = format!("{}1{}", "1 if ".repeat(10_000), " then".repeat(10_000));
+        assert_eq!(
+            evaluator.run(&deep),
+            Err(RpnError::NestingLimit {
+                word: "if".to_string(),
+                depth: MAX_NESTING,
+                offset: 5 * MAX_NESTING + 2,
+            })
+        );
+        let shallow = format!("{}1{}", "1 if ".repeat(100), " then".repeat(100));
+        assert_eq!(evaluator.eval(&shallow), Ok(1.0));
+        assert!(evaluator.run(": f 1 if 1 if f then then ; f").is_err());
+        let mut evaluator = Evaluator::with_operators(Value::operators());
+        let deep = format!("{}{}", "[ ".repeat(10_000), "] ".repeat(10_000));
+        assert!(matches!(
+            evaluator.run(&deep),
+            Err(RpnError::NestingLimit { .. })
+        ));
+        assert_eq!(
+            evaluator.eval("[ [ 1 ] ]").map(|v| v.to_string()),
+            Ok("[[1]]".to_string())
+        );
+    }
+
+    #[test]
+    fn steps_are_limited() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator.set_max_steps(1000);
+        assert_eq!(
+            evaluator.eval("0 1e15 times 1 + loop"),
+            Err(RpnError::StepLimit { steps: 1000 })
+        );
+        assert_eq!(evaluator.eval("0 300 times 1 + loop"), Ok(300.0));
+        let mut evaluator = Evaluator::<Rational>::new();
+        assert_eq!(
+            evaluator.eval("2 30 times dup * loop"),
+            Err(RpnError::Arithmetic {
+                operator: "*".to_string(),
+                error: ArithError::Overflow,
+            })
+        );
+    }
+
+    #[test]
+    fn load_ignores_comments() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        let library = "# geometry\n: sq dup * ; # square\n: circle sq pi * ;\n";
+        evaluator.load(library).unwrap();
+        assert_eq!(evaluator.eval("1 circle"), Ok(std::f64::consts::PI));
+    }
+
+    #[test]
+    fn conditionals() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator
+            .define("tariff", "dup 100 > if 100 - 0.2 * 10 + else 0.1 * then")
+            .unwrap();
+        assert_eq!(evaluator.eval("50 tariff"), Ok(5.0));
+        assert_eq!(evaluator.eval("150 tariff"), Ok(20.0));
+        assert_eq!(evaluator.eval("3 1 2 < if neg then"), Ok(-3.0));
+        assert_eq!(
+            evaluator.eval("1 1 if 0 if 2 else 3 then else 4 then +"),
+            Ok(4.0)
+        );
+    }
+
+    #[test]
+    fn loops() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        assert_eq!(evaluator.eval("1 10 times 2 * loop"), Ok(1024.0));
+        assert_eq!(evaluator.eval("0 5 1 do i + loop"), Ok(10.0));
+        assert_eq!(evaluator.eval("0 3 0 do 2 0 do 1 + loop loop"), Ok(6.0));
+        evaluator
+            .define("fact", "dup 1 > if dup 1 - fact * then")
+            .unwrap();
+        assert_eq!(evaluator.eval("5 fact"), Ok(120.0));
+    }
+
+    #[test]
+    fn unmatched_control_words() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        assert_eq!(
+            evaluator.run("1 if 2"),
+            Err(RpnError::UnmatchedControl {
+                word: "if".to_string(),
+                offset: 2,
+            })
+        );
+        assert_eq!(
+            evaluator.run("1 2 then"),
+            Err(RpnError::UnmatchedControl {
+                word: "then".to_string(),
+                offset: 4,
+            })
+        );
+        evaluator.clear();
+        assert_eq!(
+            evaluator.run("3 times 1 then"),
+            Err(RpnError::UnmatchedControl {
+                word: "times".to_string(),
+                offset: 2,
+            })
+        );
+        assert_eq!(
+            evaluator.run("1.5 times loop"),
+            Err(RpnError::NotAnInteger {
+                operator: "times".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn exact_backends() {
+        let exp = "6.1 5.2 4.3 * + 3.4 2.5 / 1.6 * -";
+        assert_eq!(
+            Evaluator::<Decimal>::new().eval(exp),
+            Ok(Decimal::parse("26.284").unwrap())
+        );
+        assert_eq!(
+            Evaluator::<Rational>::new().eval(exp),
+            Ok(Rational::parse("6571/250").unwrap())
+        );
+        assert_eq!(Evaluator::<i64>::new().eval("7 2 / 3 *"), Ok(9));
+        assert_eq!(
+            Evaluator::<i64>::new().eval("9223372036854775807 1 +"),
+            Err(RpnError::Arithmetic {
+                operator: "+".to_string(),
+                error: ArithError::Overflow,
+            })
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+
+/// How many values a history keeps by default, see [`History::set_capacity`].
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Snapshots of the stack for `undo` and `redo`.
+///
+/// The capacity bounds the values stored in all snapshots, each snapshot
+/// counting one more than its length so that empty stacks are not free. The
+/// oldest snapshots are dropped first when it is exceeded.
+#[derive(Debug, Clone)]
+pub struct History<N> {
+    undo: VecDeque<Vec<N>>,
+    redo: Vec<Vec<N>>,
+    capacity: usize,
+    stored: usize,
+}
+
+impl<N: Clone> History<N> {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            capacity,
+            stored: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Sets how many values may be stored, dropping old snapshots if needed.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.trim();
+    }
+
+    /// Records `stack` as the state before a change. Anything that could
+    /// be redone is forgotten.
+    pub fn record(&mut self, stack: &[N]) {
+        for snapshot in self.redo.drain(..) {
+            self.stored -= snapshot.len() + 1;
+        }
+        self.stored += stack.len() + 1;
+        self.undo.push_back(stack.to_vec());
+        self.trim();
+    }
+
+    /// Returns the stack before the last change, remembering `current` for
+    /// [`History::redo`].
+    pub fn undo(&mut self, current: &[N]) -> Option<Vec<N>> {
+        let previous = self.undo.pop_back()?;
+        self.stored = self.stored + current.len() - previous.len();
+        self.redo.push(current.to_vec());
+        self.trim();
+        Some(previous)
+    }
+
+    /// Returns the stack the last [`History::undo`] left, remembering
+    /// `current` for undoing it again.
+    pub fn redo(&mut self, current: &[N]) -> Option<Vec<N>> {
+        let next = self.redo.pop()?;
+        self.stored = self.stored + current.len() - next.len();
+        self.undo.push_back(current.to_vec());
+        self.trim();
+        Some(next)
+    }
+
+    /// Returns the snapshots `undo` goes back through, oldest first.
+    pub fn undo_stack(&self) -> impl Iterator<Item = &[N]> {
+        self.undo.iter().map(Vec::as_slice)
+    }
+
+    /// Returns the snapshots `redo` goes forward through, next first.
+    pub fn redo_stack(&self) -> impl Iterator<Item = &[N]> {
+        self.redo.iter().rev().map(Vec::as_slice)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Returns how many values the snapshots hold, counted as for the capacity.
+    pub fn stored(&self) -> usize {
+        self.stored
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.stored = 0;
+    }
+
+    fn trim(&mut self) {
+        while self.stored > self.capacity {
+            let dropped = if self.undo.is_empty() {
+                self.redo.remove(0)
+            } else {
+                self.undo.pop_front().unwrap()
+            };
+            self.stored -= dropped.len() + 1;
+        }
+    }
+}
+
+impl<N: Clone> Default for History<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+
+    #[test]
+    fn undo_and_redo() {
+        let mut history = History::new();
+        history.record(&[]);
+        history.record(&[1]);
+        let current = vec![1, 2];
+        let current = history.undo(&current).unwrap();
+        assert_eq!(current, vec![1]);
+        let current = history.undo(&current).unwrap();
+        assert_eq!(current, Vec::<i32>::new());
+        assert_eq!(history.undo(&current), None);
+        let current = history.redo(&current).unwrap();
+        assert_eq!(current, vec![1]);
+        assert_eq!(history.redo_stack().collect::<Vec<_>>(), vec![&[1, 2][..]]);
+        history.record(&current);
+        assert!(!history.can_redo());
+        assert_eq!(
+            history.undo_stack().collect::<Vec<_>>(),
+            vec![&[][..], &[1][..]]
+        );
+    }
+
+    #[test]
+    fn capacity_drops_the_oldest_snapshots() {
+        let mut history = History::with_capacity(6);
+        history.record(&[1]);
+        history.record(&[1, 2]);
+        assert_eq!(history.stored(), 5);
+        history.record(&[1, 2, 3]);
+        assert_eq!(history.stored(), 4);
+        assert_eq!(
+            history.undo_stack().collect::<Vec<_>>(),
+            vec![&[1, 2, 3][..]]
+        );
+        history.set_capacity(3);
+        assert!(!history.can_undo());
+        assert_eq!(history.stored(), 0);
+    }
+}
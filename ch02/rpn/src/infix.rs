@@ -0,0 +1,382 @@
+use crate::error::{InfixError, RpnError};
+use crate::number::Number;
+use crate::ops::Operators;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+/// Binding strength and associativity of the infix binary operators.
+pub const BINARY_OPERATORS: &[(&str, u8, Assoc)] = &[
+    ("<", 1, Assoc::Left),
+    ("<=", 1, Assoc::Left),
+    ("=", 1, Assoc::Left),
+    ("<>", 1, Assoc::Left),
+    (">", 1, Assoc::Left),
+    (">=", 1, Assoc::Left),
+    ("+", 2, Assoc::Left),
+    ("-", 2, Assoc::Left),
+    ("*", 3, Assoc::Left),
+    ("/", 3, Assoc::Left),
+    ("%", 3, Assoc::Left),
+    ("^", 5, Assoc::Right),
+];
+
+/// Binding strength of prefix minus, written `neg` in RPN.
+pub const NEG_PRECEDENCE: u8 = 4;
+
+const ATOM_PRECEDENCE: u8 = u8::MAX;
+
+/// Looks up the precedence and associativity of a binary operator.
+pub fn binary_operator(name: &str) -> Option<(u8, Assoc)> {
+    BINARY_OPERATORS
+        .iter()
+        .find(|&&(op, _, _)| op == name)
+        .map(|&(_, prec, assoc)| (prec, assoc))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Num(String),
+    Ident(String),
+    Op(String),
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn tokenize(exp: &str) -> Result<Vec<(usize, Tok)>, InfixError> {
+    let mut tokens = Vec::new();
+    let mut chars = exp.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut end = start + c.len_utf8();
+        let mut take_while = |accept: &dyn Fn(char, char) -> bool| {
+            let mut prev = c;
+            while let Some(&(i, next)) = chars.peek() {
+                if !accept(prev, next) {
+                    break;
+                }
+                chars.next();
+                prev = next;
+                end = i + next.len_utf8();
+            }
+        };
+        let tok = match c {
+            _ if c.is_whitespace() => continue,
+            '(' => Tok::LParen,
+            ')' => Tok::RParen,
+            ',' => Tok::Comma,
+            '0'..='9' | '.' => {
+                take_while(&|prev, c| {
+                    c.is_ascii_digit()
+                        || c == '.'
+                        || c == 'e'
+                        || c == 'E'
+                        || ((c == '+' || c == '-') && (prev == 'e' || prev == 'E'))
+                });
+                Tok::Num(exp[start..end].to_string())
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                take_while(&|_, c| c.is_alphanumeric() || c == '_');
+                Tok::Ident(exp[start..end].to_string())
+            }
+            '<' | '>' => {
+                if let Some(&(_, next)) = chars.peek() {
+                    if next == '=' || (c == '<' && next == '>') {
+                        chars.next();
+                        end += 1;
+                    }
+                }
+                Tok::Op(exp[start..end].to_string())
+            }
+            '+' | '-' | '*' | '/' | '%' | '^' | '=' => Tok::Op(c.to_string()),
+            _ => {
+                return Err(InfixError::UnexpectedToken {
+                    token: c.to_string(),
+                    offset: start,
+                })
+            }
+        };
+        tokens.push((start, tok));
+    }
+    Ok(tokens)
+}
+
+enum Pending {
+    Binary(String, u8),
+    Neg,
+    Paren { offset: usize },
+    Func(String),
+}
+
+/// Converts an infix expression such as `(1 + 2) * x` into RPN (`1 2 + x *`)
+/// with the shunting-yard algorithm.
+///
+/// Function calls `f(a, b)` become `a b f` and prefix minus becomes `neg`,
+/// except on a plain number, which becomes a negative literal.
+pub fn to_rpn(exp: &str) -> Result<String, InfixError> {
+    let tokens = tokenize(exp)?;
+    let mut output: Vec<String> = Vec::new();
+    let mut pending: Vec<Pending> = Vec::new();
+    let mut expect_operand = true;
+
+    let unexpected = |offset: usize, tok: &Tok| InfixError::UnexpectedToken {
+        token: match tok {
+            Tok::Num(s) | Tok::Ident(s) | Tok::Op(s) => s.clone(),
+            Tok::LParen => "(".to_string(),
+            Tok::RParen => ")".to_string(),
+            Tok::Comma => ",".to_string(),
+        },
+        offset,
+    };
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let (offset, tok) = &tokens[i];
+        let next = tokens.get(i + 1).map(|(_, tok)| tok);
+        i += 1;
+        match tok {
+            Tok::Num(num) if expect_operand => {
+                output.push(num.clone());
+                expect_operand = false;
+            }
+            Tok::Ident(name) if expect_operand => {
+                if next == Some(&Tok::LParen) {
+                    pending.push(Pending::Func(name.clone()));
+                } else {
+                    output.push(name.clone());
+                    expect_operand = false;
+                }
+            }
+            Tok::LParen if expect_operand => pending.push(Pending::Paren { offset: *offset }),
+            Tok::Comma | Tok::RParen if !expect_operand => {
+                loop {
+                    match pending.pop() {
+                        Some(Pending::Binary(op, _)) => output.push(op),
+                        Some(Pending::Neg) => output.push("neg".to_string()),
+                        Some(Pending::Paren { .. }) => break,
+                        Some(Pending::Func(_)) | None => {
+                            return Err(InfixError::MismatchedParen { offset: *offset })
+                        }
+                    }
+                }
+                if *tok == Tok::Comma {
+                    let in_call = matches!(pending.last(), Some(Pending::Func(_)));
+                    if !in_call {
+                        return Err(unexpected(*offset, tok));
+                    }
+                    pending.push(Pending::Paren { offset: *offset });
+                    expect_operand = true;
+                } else if let Some(Pending::Func(_)) = pending.last() {
+                    if let Some(Pending::Func(name)) = pending.pop() {
+                        output.push(name);
+                    }
+                }
+            }
+            Tok::Op(op) if expect_operand && op == "-" => {
+                let folds = match (next, tokens.get(i + 1).map(|(_, tok)| tok)) {
+                    (Some(Tok::Num(_)), Some(Tok::Op(after))) => after != "^",
+                    (Some(Tok::Num(_)), _) => true,
+                    _ => false,
+                };
+                if folds {
+                    if let Some(Tok::Num(num)) = next {
+                        output.push(format!("-{}", num));
+                        expect_operand = false;
+                        i += 1;
+                    }
+                } else {
+                    pending.push(Pending::Neg);
+                }
+            }
+            Tok::Op(op) if expect_operand && op == "+" => {}
+            Tok::Op(op) if !expect_operand => {
+                let (prec, assoc) = binary_operator(op).unwrap();
+                while let Some(top) = pending.last() {
+                    let top_prec = match top {
+                        Pending::Binary(_, p) => *p,
+                        Pending::Neg => NEG_PRECEDENCE,
+                        _ => break,
+                    };
+                    if top_prec > prec || (top_prec == prec && assoc == Assoc::Left) {
+                        match pending.pop() {
+                            Some(Pending::Binary(op, _)) => output.push(op),
+                            _ => output.push("neg".to_string()),
+                        }
+                    } else {
+                        break;
+                    }
+                }
+                pending.push(Pending::Binary(op.clone(), prec));
+                expect_operand = true;
+            }
+            _ => return Err(unexpected(*offset, tok)),
+        }
+    }
+
+    if expect_operand {
+        return Err(InfixError::UnexpectedEnd);
+    }
+    while let Some(top) = pending.pop() {
+        match top {
+            Pending::Binary(op, _) => output.push(op),
+            Pending::Neg => output.push("neg".to_string()),
+            Pending::Paren { offset } => return Err(InfixError::MismatchedParen { offset }),
+            Pending::Func(_) => return Err(InfixError::UnexpectedEnd),
+        }
+    }
+    Ok(output.join(" "))
+}
+
+/// Converts RPN into infix with as few parentheses as keep the same tree,
+/// using the arities of the builtin `f64` operators for function names.
+pub fn to_infix(exp: &str) -> Result<String, RpnError> {
+    to_infix_with(exp, &Operators::<f64>::builtin())
+}
+
+/// Like [`to_infix`], taking the arities of function names from `ops`.
+pub fn to_infix_with<N: Number>(exp: &str, ops: &Operators<N>) -> Result<String, RpnError> {
+    // Each entry is the rendered subexpression and the precedence of its outermost operator.
+    let mut stack: Vec<(String, u8)> = Vec::new();
+    for token in exp.split_whitespace() {
+        let underflow = || RpnError::StackUnderflow {
+            operator: token.to_string(),
+        };
+        if let Some((prec, assoc)) = binary_operator(token) {
+            let (right, right_prec) = stack.pop().ok_or_else(underflow)?;
+            let (left, left_prec) = stack.pop().ok_or_else(underflow)?;
+            let left = parenthesize(
+                left,
+                left_prec < prec || (left_prec == prec && assoc == Assoc::Right),
+            );
+            let right = parenthesize(
+                right,
+                right_prec < prec || (right_prec == prec && assoc == Assoc::Left),
+            );
+            stack.push((format!("{} {} {}", left, token, right), prec));
+        } else if token == "neg" {
+            let (operand, prec) = stack.pop().ok_or_else(underflow)?;
+            let operand = parenthesize(operand, prec <= NEG_PRECEDENCE);
+            stack.push((format!("-{}", operand), NEG_PRECEDENCE));
+        } else if let Some(op) = ops.get(token) {
+            let arity = op.arity();
+            if stack.len() < arity {
+                return Err(underflow());
+            }
+            let args: Vec<String> = stack
+                .split_off(stack.len() - arity)
+                .into_iter()
+                .map(|(arg, _)| arg)
+                .collect();
+            stack.push((format!("{}({})", token, args.join(", ")), ATOM_PRECEDENCE));
+        } else if token.starts_with('-') && token.len() > 1 {
+            stack.push((token.to_string(), NEG_PRECEDENCE));
+        } else {
+            stack.push((token.to_string(), ATOM_PRECEDENCE));
+        }
+    }
+    let (result, _) = stack.pop().ok_or(RpnError::EmptyStack)?;
+    if stack.is_empty() {
+        Ok(result)
+    } else {
+        Err(RpnError::LeftoverValues { count: stack.len() })
+    }
+}
+
+fn parenthesize(exp: String, needed: bool) -> String {
+    if needed {
+        format!("({})", exp)
+    } else {
+        exp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_infix, to_rpn};
+    use crate::error::InfixError;
+    use crate::rpn;
+
+    #[test]
+    fn documented_example() {
+        let infix = "(6.1 + 5.2 * 4.3) - 3.4 / 2.5 * 1.6";
+        let exp = to_rpn(infix).unwrap();
+        assert_eq!(exp, "6.1 5.2 4.3 * + 3.4 2.5 / 1.6 * -");
+        assert_eq!(format!("{:.4}", rpn(&exp).unwrap()), "26.2840");
+        assert_eq!(to_infix(&exp).unwrap(), "6.1 + 5.2 * 4.3 - 3.4 / 2.5 * 1.6");
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(to_rpn("1 - 2 - 3").unwrap(), "1 2 - 3 -");
+        assert_eq!(to_rpn("2 ^ 3 ^ 2").unwrap(), "2 3 2 ^ ^");
+        assert_eq!(to_rpn("-2 ^ 2").unwrap(), "2 2 ^ neg");
+        assert_eq!(to_rpn("2 * -3").unwrap(), "2 -3 *");
+        assert_eq!(to_rpn("-(x + 1)").unwrap(), "x 1 + neg");
+        assert_eq!(to_rpn("x + 1 > 2 * y").unwrap(), "x 1 + 2 y * >");
+        assert_eq!(
+            to_rpn("max(a, b + 1) * sqrt(2)").unwrap(),
+            "a b 1 + max 2 sqrt *"
+        );
+        assert_eq!(to_rpn("1.5e-3 * 2").unwrap(), "1.5e-3 2 *");
+        assert_eq!(to_rpn("1 <= 2 <> größe").unwrap(), "1 2 <= größe <>");
+    }
+
+    #[test]
+    fn round_trip_keeps_the_tree() {
+        for infix in &[
+            "1 - (2 - 3)",
+            "(1 - 2) - 3",
+            "(2 ^ 3) ^ 2",
+            "2 ^ 3 ^ 2",
+            "-(a * b) + -c",
+            "a / (b * c)",
+            "max(1, 2) ^ -x",
+            "2 ^ (-3)",
+        ] {
+            let exp = to_rpn(infix).unwrap();
+            let printed = to_infix(&exp).unwrap();
+            assert_eq!(to_rpn(&printed).unwrap(), exp, "{} -> {}", infix, printed);
+        }
+        assert_eq!(to_infix("1 2 3 - -").unwrap(), "1 - (2 - 3)");
+        assert_eq!(to_infix("1 2 - 3 -").unwrap(), "1 - 2 - 3");
+        assert_eq!(to_infix("2 3 ^ 2 ^").unwrap(), "(2 ^ 3) ^ 2");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            to_rpn("(1 + 2"),
+            Err(InfixError::MismatchedParen { offset: 0 })
+        );
+        assert_eq!(
+            to_rpn("1 + 2)"),
+            Err(InfixError::MismatchedParen { offset: 5 })
+        );
+        assert_eq!(to_rpn("1 +"), Err(InfixError::UnexpectedEnd));
+        assert_eq!(
+            to_rpn("1 2"),
+            Err(InfixError::UnexpectedToken {
+                token: "2".to_string(),
+                offset: 2,
+            })
+        );
+        assert_eq!(
+            to_rpn("1 × 2"),
+            Err(InfixError::UnexpectedToken {
+                token: "×".to_string(),
+                offset: 2,
+            })
+        );
+        assert_eq!(
+            to_rpn("1 $ 2"),
+            Err(InfixError::UnexpectedToken {
+                token: "$".to_string(),
+                offset: 2,
+            })
+        );
+        assert!(to_infix("1 +").is_err());
+    }
+}
@@ -0,0 +1,519 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::error::ArithError;
+use crate::number::{integer_pow, Number};
+use crate::ops::Operators;
+
+/// A closed interval of reals `[lo, hi]` holding a value known only within
+/// a tolerance, such as a measurement.
+///
+/// Literals are written `1.6±0.05` (or `1.6+-0.05`), and plain numbers are
+/// intervals as narrow as `f64` allows around the number. Every result is
+/// rounded outwards so that it encloses all values the operands could
+/// combine to. An interval is less than another only if all its values are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    lo: f64,
+    hi: f64,
+}
+
+impl Interval {
+    /// Creates `[lo, hi]`, returning `None` unless `lo <= hi`.
+    pub fn new(lo: f64, hi: f64) -> Option<Self> {
+        if lo <= hi {
+            Some(Self { lo, hi })
+        } else {
+            None
+        }
+    }
+
+    /// Fails unless the ends are numbers in order, which they are not when
+    /// the operation is undefined at an end, e.g. `inf inf -`.
+    fn checked(self) -> Result<Self, ArithError> {
+        Self::new(self.lo, self.hi).ok_or(ArithError::NotRepresentable)
+    }
+
+    /// Creates the interval holding just `x`.
+    pub fn point(x: f64) -> Self {
+        Self { lo: x, hi: x }
+    }
+
+    pub fn lo(&self) -> f64 {
+        self.lo
+    }
+
+    pub fn hi(&self) -> f64 {
+        self.hi
+    }
+
+    /// The middle of the interval.
+    pub fn mid(&self) -> f64 {
+        if self.lo == self.hi {
+            self.lo
+        } else {
+            self.lo / 2.0 + self.hi / 2.0
+        }
+    }
+
+    /// The distance from [`Interval::mid`] to the farther end, rounded up.
+    pub fn rad(&self) -> f64 {
+        let mid = self.mid();
+        let below = up(mid - self.lo, sub_error(mid, self.lo));
+        let above = up(self.hi - mid, sub_error(self.hi, mid));
+        below.max(above)
+    }
+
+    pub fn contains(&self, x: f64) -> bool {
+        self.lo <= x && x <= self.hi
+    }
+
+    /// Creates a table with the builtin operators, `lo`, `hi`, `mid` and `rad`
+    /// to take intervals apart, and `±` to build one from a middle and a
+    /// radius. `sqrt`, `ln` and `exp` map whole intervals; the other
+    /// functions only take exact numbers.
+    pub fn operators() -> Operators<Interval> {
+        let mut ops: Operators<Interval> = Operators::builtin();
+        ops.register_unary("lo", |x| Ok(Interval::point(x.lo)));
+        ops.register_unary("hi", |x| Ok(Interval::point(x.hi)));
+        ops.register_unary("mid", |x| Ok(Interval::point(x.mid())));
+        ops.register_unary("rad", |x| Ok(Interval::point(x.rad())));
+        ops.register_binary("±", |x, y| x.widen(y.hi));
+        ops.register_unary("sqrt", |x| x.increasing(f64::sqrt));
+        ops.register_unary("ln", |x| x.increasing(f64::ln));
+        ops.register_unary("exp", |x| x.increasing(f64::exp));
+        ops.register_binary("min", |x, y| {
+            Ok(Interval {
+                lo: x.lo.min(y.lo),
+                hi: x.hi.min(y.hi),
+            })
+        });
+        ops.register_binary("max", |x, y| {
+            Ok(Interval {
+                lo: x.lo.max(y.lo),
+                hi: x.hi.max(y.hi),
+            })
+        });
+        ops
+    }
+
+    /// Widens by `rad` on both sides.
+    fn widen(&self, rad: f64) -> Result<Self, ArithError> {
+        if rad.is_nan() || rad < 0.0 {
+            return Err(ArithError::NotRepresentable);
+        }
+        Ok(Self {
+            lo: down(self.lo - rad, sub_error(self.lo, rad)),
+            hi: up(self.hi + rad, add_error(self.hi, rad)),
+        })
+    }
+
+    /// Maps through an increasing function computed to within an ulp, such
+    /// as `sqrt`, failing where it is undefined.
+    fn increasing<F>(&self, fun: F) -> Result<Self, ArithError>
+    where
+        F: Fn(f64) -> f64,
+    {
+        let (lo, hi) = (fun(self.lo), fun(self.hi));
+        if lo.is_nan() || hi.is_nan() {
+            return Err(ArithError::NotRepresentable);
+        }
+        Ok(Self {
+            lo: lo.next_down(),
+            hi: hi.next_up(),
+        })
+    }
+
+    /// The smallest interval holding the result of `op` on all pairs of ends,
+    /// where `op` returns the rounded result and its rounding error. Fails if
+    /// any pair has no result, e.g. `inf 0 *`.
+    fn corners<F>(&self, rhs: &Self, op: F) -> Result<Self, ArithError>
+    where
+        F: Fn(f64, f64) -> (f64, f64),
+    {
+        let mut lo = f64::INFINITY;
+        let mut hi = f64::NEG_INFINITY;
+        for &x in &[self.lo, self.hi] {
+            for &y in &[rhs.lo, rhs.hi] {
+                let (result, error) = op(x, y);
+                if result.is_nan() {
+                    return Err(ArithError::NotRepresentable);
+                }
+                lo = lo.min(down(result, error));
+                hi = hi.max(up(result, error));
+            }
+        }
+        Ok(Self { lo, hi })
+    }
+}
+
+/// Rounds `x` down given the sign of the error of computing it: `x` is
+/// exact for a zero error, and an unknown (NaN) error is assumed inexact.
+fn down(x: f64, error: f64) -> f64 {
+    if error >= 0.0 {
+        x
+    } else {
+        x.next_down()
+    }
+}
+
+fn up(x: f64, error: f64) -> f64 {
+    if error <= 0.0 {
+        x
+    } else {
+        x.next_up()
+    }
+}
+
+/// How much `a + b` exceeds its rounded value (Knuth's TwoSum).
+fn add_error(a: f64, b: f64) -> f64 {
+    let sum = a + b;
+    if !sum.is_finite() {
+        return f64::NAN;
+    }
+    let b_part = sum - a;
+    (a - (sum - b_part)) + (b - b_part)
+}
+
+fn sub_error(a: f64, b: f64) -> f64 {
+    add_error(a, -b)
+}
+
+/// How much `a * b` exceeds its rounded value, found with a fused
+/// multiply-add.
+fn mul_error(a: f64, b: f64) -> f64 {
+    let product = a * b;
+    if !product.is_finite() {
+        return f64::NAN;
+    }
+    let error = a.mul_add(b, -product);
+    if error == 0.0 && product != 0.0 && product.abs() < f64::MIN_POSITIVE {
+        f64::NAN
+    } else {
+        error
+    }
+}
+
+/// The sign of how much `a / b` exceeds its rounded value.
+fn div_error(a: f64, b: f64) -> f64 {
+    let quotient = a / b;
+    if !quotient.is_finite() || quotient.abs() < f64::MIN_POSITIVE {
+        return f64::NAN;
+    }
+    let remainder = -quotient.mul_add(b, -a);
+    remainder * b.signum()
+}
+
+/// Orders intervals that do not overlap. Equal intervals are equal only when
+/// they hold a single number.
+impl PartialOrd for Interval {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.hi < other.lo {
+            Some(Ordering::Less)
+        } else if self.lo > other.hi {
+            Some(Ordering::Greater)
+        } else if self.lo == self.hi && self == other {
+            Some(Ordering::Equal)
+        } else {
+            None
+        }
+    }
+}
+
+/// Prints `mid±rad`, or just the number for a single one. With a precision,
+/// the radius is rounded up to it so the printed interval still encloses.
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.lo == self.hi {
+            return fmt::Display::fmt(&self.lo, f);
+        }
+        match f.precision() {
+            Some(p) => {
+                // The radius covers the interval around the rounded midpoint,
+                // so what is printed still encloses it.
+                let mid = format!("{:.*}", p, self.mid());
+                let shown: f64 = mid.parse().unwrap_or(self.mid());
+                let scale = 10f64.powi(p as i32);
+                let mut rad = ((shown - self.lo).max(self.hi - shown) * scale).ceil() / scale;
+                if shown - rad > self.lo || shown + rad < self.hi {
+                    rad = (rad * scale + 1.0) / scale;
+                }
+                write!(f, "{}±{:.*}", mid, p, rad)
+            }
+            None => write!(f, "{}±{}", self.mid(), self.rad()),
+        }
+    }
+}
+
+/// Parses a number into the narrowest interval holding the value it names,
+/// rejecting `nan`, which names none.
+fn parse_number(token: &str) -> Option<Interval> {
+    let x: f64 = token.parse().ok()?;
+    if x.is_nan() {
+        return None;
+    }
+    let digits = token.trim_start_matches(['+', '-']);
+    let exact = !digits.is_empty()
+        && digits.bytes().all(|b| b.is_ascii_digit())
+        && x.abs() <= 2f64.powi(53);
+    if exact || !x.is_finite() {
+        Some(Interval::point(x))
+    } else {
+        Some(Interval {
+            lo: x.next_down(),
+            hi: x.next_up(),
+        })
+    }
+}
+
+impl Number for Interval {
+    fn parse(token: &str) -> Option<Self> {
+        let split = token
+            .find('±')
+            .map(|i| (i, '±'.len_utf8()))
+            .or_else(|| token.find("+-").map(|i| (i, 2)));
+        match split {
+            Some((i, len)) => {
+                let mid = parse_number(&token[..i])?;
+                let rad = parse_number(&token[i + len..])?;
+                mid.widen(rad.hi).ok()
+            }
+            None => parse_number(token),
+        }
+    }
+
+    fn from_i64(n: i64) -> Self {
+        let x = n as f64;
+        match (x as i128).cmp(&(n as i128)) {
+            Ordering::Equal => Self::point(x),
+            Ordering::Less => Self {
+                lo: x,
+                hi: x.next_up(),
+            },
+            Ordering::Greater => Self {
+                lo: x.next_down(),
+                hi: x,
+            },
+        }
+    }
+
+    fn from_f64(x: f64) -> Option<Self> {
+        Some(Self::point(x))
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.mid()
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Self {
+            lo: down(self.lo + rhs.lo, add_error(self.lo, rhs.lo)),
+            hi: up(self.hi + rhs.hi, add_error(self.hi, rhs.hi)),
+        }
+        .checked()
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Self {
+            lo: down(self.lo - rhs.hi, sub_error(self.lo, rhs.hi)),
+            hi: up(self.hi - rhs.lo, sub_error(self.hi, rhs.lo)),
+        }
+        .checked()
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.corners(rhs, |x, y| (x * y, mul_error(x, y)))
+    }
+
+    /// Divides, failing if `rhs` contains zero.
+    fn div(&self, rhs: &Self) -> Result<Self, ArithError> {
+        if rhs.contains(0.0) {
+            return Err(ArithError::DivisionByZero);
+        }
+        self.corners(rhs, |x, y| (x / y, div_error(x, y)))
+    }
+
+    /// Only exact numbers have a remainder.
+    fn rem(&self, rhs: &Self) -> Result<Self, ArithError> {
+        if self.lo != self.hi || rhs.lo != rhs.hi {
+            return Err(ArithError::NotRepresentable);
+        }
+        if rhs.lo == 0.0 {
+            return Err(ArithError::DivisionByZero);
+        }
+        Ok(Self::point(self.lo % rhs.lo))
+    }
+
+    /// Raises to `rhs`, exactly when it is an integer. Even powers are taken
+    /// of the absolute value, so they never reach below zero.
+    fn pow(&self, rhs: &Self) -> Result<Self, ArithError> {
+        let even = rhs.lo == rhs.hi && rhs.lo % 2.0 == 0.0;
+        let base = if even { self.abs()? } else { *self };
+        integer_pow(&base, rhs, u32::MAX)
+    }
+
+    fn neg(&self) -> Result<Self, ArithError> {
+        Ok(Self {
+            lo: -self.hi,
+            hi: -self.lo,
+        })
+    }
+
+    fn abs(&self) -> Result<Self, ArithError> {
+        if self.lo >= 0.0 {
+            Ok(*self)
+        } else if self.hi <= 0.0 {
+            self.neg()
+        } else {
+            Ok(Self {
+                lo: 0.0,
+                hi: self.hi.max(-self.lo),
+            })
+        }
+    }
+
+    /// Maps an exact number through `fun`, which is assumed to be within an
+    /// ulp. Intervals fail, since `fun` may not be monotonic.
+    fn map_f64<F>(&self, fun: F) -> Result<Self, ArithError>
+    where
+        F: Fn(f64) -> f64,
+    {
+        if self.lo != self.hi {
+            return Err(ArithError::NotRepresentable);
+        }
+        let x = fun(self.lo);
+        if x.is_nan() {
+            return Err(ArithError::NotRepresentable);
+        }
+        Ok(Self {
+            lo: x.next_down(),
+            hi: x.next_up(),
+        })
+    }
+
+    /// Only an interval that cannot hold zero is true.
+    fn is_truthy(&self) -> bool {
+        !self.contains(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Interval;
+    use crate::error::{ArithError, RpnError};
+    use crate::eval::Evaluator;
+    use crate::number::Number;
+
+    fn eval(exp: &str) -> Result<Interval, RpnError> {
+        Evaluator::with_operators(Interval::operators()).eval(exp)
+    }
+
+    fn encloses(interval: Interval, lo: f64, hi: f64) -> bool {
+        interval.lo() <= lo && hi <= interval.hi()
+    }
+
+    #[test]
+    fn literals() {
+        let x = Interval::parse("1.6±0.05").unwrap();
+        assert!(encloses(x, 1.55, 1.65));
+        assert!(x.hi() - x.lo() < 0.1 + 1e-15);
+        assert_eq!(Interval::parse("1.6+-0.05"), Some(x));
+        assert_eq!(Interval::parse("3"), Some(Interval::point(3.0)));
+        let tenth = Interval::parse("0.1").unwrap();
+        assert!(tenth.lo() < 0.1 && 0.1 < tenth.hi());
+        assert_eq!(Interval::parse("1±-1"), None);
+        assert_eq!(Interval::parse("±1"), None);
+        assert_eq!(Interval::parse("x"), None);
+        assert_eq!(Interval::parse("nan"), None);
+        assert_eq!(Interval::parse("1±nan"), None);
+    }
+
+    #[test]
+    fn arithmetic_encloses() {
+        assert_eq!(eval("1 2 +"), Ok(Interval::point(3.0)));
+        assert_eq!(eval("3 4 *"), Ok(Interval::point(12.0)));
+        assert_eq!(eval("1 4 /"), Ok(Interval::point(0.25)));
+        let third = eval("1 3 /").unwrap();
+        assert!(third.lo() < third.hi());
+        assert_eq!(third.lo().next_up(), third.hi());
+        let x = eval("1±0.5 2±0.5 -").unwrap();
+        assert!(encloses(x, -2.0, 0.0));
+        let x = eval("-1±2 3±1 *").unwrap();
+        assert_eq!((x.lo(), x.hi()), (-12.0, 4.0));
+        let x = eval("1 2±1 /").unwrap();
+        assert!(encloses(x, 1.0 / 3.0, 1.0));
+        assert_eq!(
+            eval("1 0±1 /"),
+            Err(RpnError::Arithmetic {
+                operator: "/".to_string(),
+                error: ArithError::DivisionByZero,
+            })
+        );
+        let x = eval("-1±2 abs").unwrap();
+        assert_eq!((x.lo(), x.hi()), (0.0, 3.0));
+        let x = eval("-1±2 2 ^").unwrap();
+        assert_eq!((x.lo(), x.hi()), (0.0, 9.0));
+        let x = eval("-2±1 2 ^").unwrap();
+        assert_eq!((x.lo(), x.hi()), (1.0, 9.0));
+        assert!(encloses(eval("-1±2 3 ^").unwrap(), -27.0, 1.0));
+    }
+
+    #[test]
+    fn undefined_ends_fail() {
+        for (exp, operator) in &[("inf 0 *", "*"), ("inf inf -", "-"), ("-inf inf +", "+")] {
+            assert_eq!(
+                eval(exp),
+                Err(RpnError::Arithmetic {
+                    operator: operator.to_string(),
+                    error: ArithError::NotRepresentable,
+                }),
+                "{}",
+                exp
+            );
+        }
+        assert!(matches!(
+            eval("1 nan +"),
+            Err(RpnError::UnknownToken { .. })
+        ));
+        assert_eq!(eval("inf 1 +").map(|x| x.hi()), Ok(f64::INFINITY));
+    }
+
+    #[test]
+    fn tolerances_propagate() {
+        let x = eval("6.1±0.05 5.2±0.05 4.3±0.05 * + 3.4±0.05 2.5±0.05 / 1.6±0.05 * -").unwrap();
+        assert!(x.contains(26.284));
+        assert!(encloses(x, 25.62, 26.95));
+        assert!(x.lo() > 25.6 && x.hi() < 26.96);
+        assert_eq!(
+            format!("{:.4}", eval("1±0.00001").unwrap()),
+            "1.0000±0.0001"
+        );
+        let x = eval("0.045±0.06").unwrap();
+        let shown = format!("{:.1}", x);
+        assert_eq!(shown, "0.0±0.2");
+        let shown = Interval::parse(&shown).unwrap();
+        assert!(shown.lo() <= x.lo() && shown.hi() >= x.hi());
+        assert_eq!(eval("2±1 rad"), Ok(Interval::point(1.0)));
+        assert_eq!(eval("4±1 lo sqrt"), eval("3 sqrt"));
+        assert!(encloses(
+            eval("4±1 sqrt").unwrap(),
+            3f64.sqrt(),
+            5f64.sqrt()
+        ));
+        assert_eq!(
+            eval("0±1 sin"),
+            Err(RpnError::Arithmetic {
+                operator: "sin".to_string(),
+                error: ArithError::NotRepresentable,
+            })
+        );
+    }
+
+    #[test]
+    fn comparisons_need_the_whole_interval() {
+        assert_eq!(eval("1±1 3±0.5 <"), Ok(Interval::point(1.0)));
+        assert_eq!(eval("1±1 2±0.5 <"), Ok(Interval::point(0.0)));
+        assert_eq!(eval("1±1 2±0.5 >="), Ok(Interval::point(0.0)));
+        assert_eq!(eval("1±1 if 1 else 2 then"), Ok(Interval::point(2.0)));
+    }
+}
@@ -0,0 +1,44 @@
+pub mod env;
+pub mod error;
+pub mod eval;
+pub mod history;
+pub mod infix;
+pub mod interval;
+pub mod json;
+pub mod locale;
+pub mod number;
+pub mod ops;
+pub mod program;
+pub mod repl;
+pub mod serve;
+pub mod session;
+pub mod stats;
+pub mod symbolic;
+pub mod time;
+pub mod token;
+pub mod trace;
+pub mod units;
+pub mod value;
+pub mod word;
+
+pub use crate::env::Env;
+pub use crate::error::{ArithError, Diagnostic, InfixError, RpnError, SessionError};
+pub use crate::eval::Evaluator;
+pub use crate::history::History;
+pub use crate::interval::Interval;
+pub use crate::locale::NumberFormat;
+pub use crate::number::Number;
+pub use crate::ops::{Operator, Operators};
+pub use crate::program::Program;
+pub use crate::symbolic::Expr;
+pub use crate::time::Time;
+pub use crate::token::{Span, Token};
+pub use crate::trace::{Trace, TraceStep};
+pub use crate::units::{Quantity, Units};
+pub use crate::value::Value;
+pub use crate::word::{Word, WordSize};
+
+/// Evaluates `exp` with the default operators and returns the single value left on the stack.
+pub fn rpn(exp: &str) -> Result<f64, RpnError> {
+    Evaluator::new().eval(exp)
+}
@@ -0,0 +1,488 @@
+//! Number formats for literals and printed results: the decimal separator,
+//! thousands grouping, the notation and rounding to significant digits.
+//!
+//! A format is written as items separated by `:`, as in `comma:group:sig3`:
+//!
+//! - `point` or `comma` chooses the decimal separator, `.` by default.
+//! - `group` groups the digits before the separator in threes, with `,`
+//!   after a decimal point and `.` after a decimal comma; `group=C` uses `C`.
+//! - `fixed`, `sci` or `eng` chooses plain (`12345.6`), scientific
+//!   (`1.23456e4`) or engineering notation, whose exponents are multiples of
+//!   three (`12.3456e3`).
+//! - `sigN` rounds to `N` significant digits.
+//!
+//! Printed values are rewritten number by number, so `5.2 km` and
+//! `1.6±0.05` keep their shape. Literals, libraries included, are read in
+//! the format too: after `comma:group`, `6,1` is 6.1, `1.234` is 1234 and
+//! `1.5` is rejected as badly grouped. Dates and times are read and printed
+//! as they are, and saved sessions hold values in plain notation, so they
+//! load under any format.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notation {
+    Fixed,
+    Scientific,
+    Engineering,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub decimal: char,
+    pub grouping: Option<char>,
+    pub notation: Notation,
+    pub significant: Option<usize>,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self {
+            decimal: '.',
+            grouping: None,
+            notation: Notation::Fixed,
+            significant: None,
+        }
+    }
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, String> {
+        let mut format = NumberFormat::default();
+        let mut group = None;
+        for item in spec
+            .split(':')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            match item {
+                "point" => format.decimal = '.',
+                "comma" => format.decimal = ',',
+                "group" => group = Some(None),
+                "fixed" => format.notation = Notation::Fixed,
+                "sci" => format.notation = Notation::Scientific,
+                "eng" => format.notation = Notation::Engineering,
+                _ if item.starts_with("group=") => {
+                    let mut chars = item["group=".len()..].chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) if is_separator(c) => group = Some(Some(c)),
+                        _ => return Err(format!("invalid digit group separator: {}", item)),
+                    }
+                }
+                _ if item.starts_with("sig") => match item["sig".len()..].parse() {
+                    Ok(digits) if digits > 0 => format.significant = Some(digits),
+                    _ => return Err(format!("invalid number of significant digits: {}", item)),
+                },
+                _ => return Err(format!("unknown number format item: {}", item)),
+            }
+        }
+        format.grouping =
+            group.map(|c: Option<char>| c.unwrap_or(if format.decimal == ',' { '.' } else { ',' }));
+        if format.grouping == Some(format.decimal) {
+            return Err("digits cannot be grouped with the decimal separator".to_string());
+        }
+        Ok(format)
+    }
+}
+
+/// Whether `c` can group digits without being confused with a sign, a digit,
+/// an exponent or a token boundary.
+fn is_separator(c: char) -> bool {
+    !(c.is_alphanumeric() || c.is_whitespace() || c == '+' || c == '-')
+}
+
+impl NumberFormat {
+    pub fn is_default(&self) -> bool {
+        *self == NumberFormat::default()
+    }
+
+    /// Whether printed numbers are rounded or renotated rather than only
+    /// punctuated differently.
+    fn rewrites_values(&self) -> bool {
+        self.notation != Notation::Fixed || self.significant.is_some()
+    }
+
+    /// Prints `value` in this format. `precision` is the number of digits
+    /// after the point, of the mantissa in scientific and engineering
+    /// notation; significant digits take precedence over it.
+    pub fn display<T: fmt::Display>(
+        &self,
+        value: &T,
+        precision: Option<usize>,
+        alternate: bool,
+    ) -> String {
+        if self.is_default() {
+            return plain(value, precision, alternate);
+        }
+        if !self.rewrites_values() {
+            let text = plain(value, precision, alternate);
+            return rewrite_numbers(&text, |number| self.punctuate(number));
+        }
+        let text = plain(value, None, alternate);
+        rewrite_numbers(&text, |number| {
+            let x = match number.find('/') {
+                Some(i) => read_f64(&number[..i]) / read_f64(&number[i + 1..]),
+                None => read_f64(number),
+            };
+            self.punctuate(&self.notate(x, precision))
+        })
+    }
+
+    /// Writes the non-negative `x` in the notation of this format, with a
+    /// decimal point.
+    fn notate(&self, x: f64, precision: Option<usize>) -> String {
+        match self.notation {
+            Notation::Fixed => {
+                let (digits, exp) = decimal_digits(x, self.significant);
+                fixed(&digits, exp)
+            }
+            Notation::Scientific => {
+                let count = self.significant.or_else(|| precision.map(|p| p + 1));
+                let (digits, exp) = decimal_digits(x, count);
+                format!("{}e{}", fixed(&digits, 0), exp)
+            }
+            Notation::Engineering => {
+                let (mut digits, mut exp) = decimal_digits(x, self.significant);
+                if let (None, Some(p)) = (self.significant, precision) {
+                    // Rounding up to a power of ten can move the point, as
+                    // 999.96 becomes 1.000e3, so round again for the new one.
+                    for _ in 0..2 {
+                        let count = exp.rem_euclid(3) as usize + 1 + p;
+                        let (rounded, rounded_exp) = decimal_digits(x, Some(count));
+                        digits = rounded;
+                        exp = rounded_exp;
+                    }
+                }
+                let shift = exp.rem_euclid(3);
+                format!("{}e{}", fixed(&digits, shift), exp - shift)
+            }
+        }
+    }
+
+    /// Replaces the decimal point of `number` and groups its integer digits,
+    /// in both parts of a fraction.
+    fn punctuate(&self, number: &str) -> String {
+        if let Some(i) = number.find('/') {
+            let (numerator, denominator) = (&number[..i], &number[i + 1..]);
+            return format!(
+                "{}/{}",
+                self.punctuate(numerator),
+                self.punctuate(denominator)
+            );
+        }
+        let (mantissa, exponent) = match number.find(['e', 'E']) {
+            Some(i) => number.split_at(i),
+            None => (number, ""),
+        };
+        let (integer, fraction) = match mantissa.find('.') {
+            Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+            None => (mantissa, ""),
+        };
+        let mut out = String::with_capacity(number.len() + integer.len() / 3);
+        for (i, c) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                if let Some(separator) = self.grouping {
+                    out.push(separator);
+                }
+            }
+            out.push(c);
+        }
+        if mantissa.len() > integer.len() {
+            out.push(self.decimal);
+            out.push_str(fraction);
+        }
+        out.push_str(exponent);
+        out
+    }
+
+    /// Rewrites the numbers in a literal written in this format with a
+    /// decimal point and no grouping, as `1.234,5` to `1234.5` after a
+    /// decimal comma. Returns `None` if a number is malformed, such as
+    /// `1,2,3`, `12.34,5` or `1.5` there.
+    pub fn read(&self, token: &str) -> Option<String> {
+        let mut out = String::with_capacity(token.len());
+        let mut copied = 0;
+        let mut chars = token.char_indices().peekable();
+        let mut previous = None;
+        while let Some((start, c)) = chars.next() {
+            let in_word = previous.is_some_and(|p| self.continues_word(p));
+            previous = Some(c);
+            if !c.is_ascii_digit() || in_word {
+                continue;
+            }
+            if is_date_or_time(&token.as_bytes()[start..]) {
+                while let Some(&(_, c)) = chars.peek() {
+                    if !(c.is_ascii_digit() || "-:T.".contains(c)) {
+                        break;
+                    }
+                    previous = Some(c);
+                    chars.next();
+                }
+                continue;
+            }
+            let mut end = start + 1;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_ascii_digit() || self.continues_number(c)) {
+                    break;
+                }
+                if c.is_ascii_digit() {
+                    end = i + 1;
+                }
+                previous = Some(c);
+                chars.next();
+            }
+            out.push_str(&token[copied..start]);
+            out.push_str(&self.canonical(&token[start..end])?);
+            copied = end;
+        }
+        out.push_str(&token[copied..]);
+        Some(out)
+    }
+
+    fn continues_number(&self, c: char) -> bool {
+        c == '.' || c == ',' || c == self.decimal || Some(c) == self.grouping
+    }
+
+    fn continues_word(&self, c: char) -> bool {
+        c.is_alphanumeric() || c == '_' || self.continues_number(c)
+    }
+
+    /// Reads one number of digits and separators, such as `1.234,5`.
+    fn canonical(&self, number: &str) -> Option<String> {
+        let mut parts = number.split(self.decimal);
+        let integer = parts.next()?;
+        let fraction = parts.next();
+        if parts.next().is_some() || !fraction.is_none_or(all_digits) {
+            return None;
+        }
+        let mut out = match self.grouping {
+            Some(separator) if integer.contains(separator) => {
+                let groups: Vec<&str> = integer.split(separator).collect();
+                let (first, rest) = groups.split_first()?;
+                let grouped = (1..=3).contains(&first.len())
+                    && all_digits(first)
+                    && rest
+                        .iter()
+                        .all(|group| group.len() == 3 && all_digits(group));
+                if !grouped {
+                    return None;
+                }
+                groups.concat()
+            }
+            _ if all_digits(integer) => integer.to_string(),
+            _ => return None,
+        };
+        if let Some(fraction) = fraction {
+            out.push('.');
+            out.push_str(fraction);
+        }
+        Some(out)
+    }
+}
+
+fn read_f64(number: &str) -> f64 {
+    number.parse().unwrap_or(f64::NAN)
+}
+
+fn all_digits(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn plain<T: fmt::Display>(value: &T, precision: Option<usize>, alternate: bool) -> String {
+    match (precision, alternate) {
+        (Some(precision), false) => format!("{:.*}", precision, value),
+        (Some(precision), true) => format!("{:#.*}", precision, value),
+        (None, false) => value.to_string(),
+        (None, true) => format!("{:#}", value),
+    }
+}
+
+/// Returns the decimal digits of the non-negative `x` and the exponent of
+/// the first one, rounded to `count` digits or as few as tell `x` apart.
+fn decimal_digits(x: f64, count: Option<usize>) -> (String, i32) {
+    let scientific = match count {
+        Some(count) => format!("{:.*e}", count.saturating_sub(1), x),
+        None => format!("{:e}", x),
+    };
+    let (mantissa, exp) = scientific.split_at(scientific.find('e').unwrap_or(0));
+    (mantissa.replace('.', ""), exp[1..].parse().unwrap_or(0))
+}
+
+/// Writes `digits` with the point after the digit of exponent `exp`, as
+/// `fixed("125", 1)` is `12.5`.
+fn fixed(digits: &str, exp: i32) -> String {
+    if exp < 0 {
+        return format!("0.{}{}", "0".repeat((-exp - 1) as usize), digits);
+    }
+    let integer = exp as usize + 1;
+    if digits.len() <= integer {
+        format!("{}{}", digits, "0".repeat(integer - digits.len()))
+    } else {
+        format!("{}.{}", &digits[..integer], &digits[integer..])
+    }
+}
+
+/// Replaces each unsigned decimal number in `text`, such as `12.5`, `3e-7`
+/// or the fraction `2/3`, with `number` of it. Digits inside words, such as `0x1f`, `x2`
+/// or the exponent of `m^2`, are left alone; an imaginary unit may follow.
+fn rewrite_numbers<F: FnMut(&str) -> String>(text: &str, mut number: F) -> String {
+    let bytes = text.as_bytes();
+    let in_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'.' || b == b'^';
+    let digits_from = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        i
+    };
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() || (i > 0 && in_word(bytes[i - 1])) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        i = digits_from(i);
+        if is_date_or_time(&bytes[start..]) {
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || b"-:T.".contains(&bytes[i])) {
+                i += 1;
+            }
+            continue;
+        }
+        if bytes.get(i) == Some(&b'.') && bytes.get(i + 1).is_some_and(u8::is_ascii_digit) {
+            i = digits_from(i + 1);
+        }
+        if let Some(b'e') | Some(b'E') = bytes.get(i) {
+            let sign = matches!(bytes.get(i + 1), Some(b'+') | Some(b'-')) as usize;
+            if bytes.get(i + 1 + sign).is_some_and(u8::is_ascii_digit) {
+                i = digits_from(i + 1 + sign);
+            }
+        }
+        if bytes.get(i) == Some(&b'/') && bytes.get(i + 1).is_some_and(u8::is_ascii_digit) {
+            i = digits_from(i + 1);
+        }
+        let imaginary =
+            bytes.get(i) == Some(&b'i') && !bytes.get(i + 1).is_some_and(|&b| in_word(b));
+        if bytes.get(i).is_some_and(|&b| in_word(b)) && !imaginary {
+            continue;
+        }
+        out.push_str(&text[copied..start]);
+        out.push_str(&number(&text[start..i]));
+        copied = i;
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+/// Whether `bytes` start with a date such as `2024-02-28` or a time such as
+/// `14:30`, which are no numbers to rewrite.
+fn is_date_or_time(bytes: &[u8]) -> bool {
+    let shape = |pattern: &[u8]| {
+        bytes.len() >= pattern.len()
+            && pattern.iter().zip(bytes).all(|(&p, &b)| {
+                if p == b'0' {
+                    b.is_ascii_digit()
+                } else {
+                    p == b
+                }
+            })
+    };
+    shape(b"0000-00-00") || shape(b"00:00")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Notation, NumberFormat};
+
+    fn format(spec: &str) -> NumberFormat {
+        spec.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!(format(""), NumberFormat::default());
+        let de = format("comma:group:sig3");
+        assert_eq!(de.decimal, ',');
+        assert_eq!(de.grouping, Some('.'));
+        assert_eq!(de.significant, Some(3));
+        assert_eq!(format("group").grouping, Some(','));
+        assert_eq!(format("group='").grouping, Some('\''));
+        assert_eq!(format("eng").notation, Notation::Engineering);
+        for spec in &[
+            "comma:group=,",
+            "group=x",
+            "group= ",
+            "sig0",
+            "sigx",
+            "roman",
+        ] {
+            assert!(spec.parse::<NumberFormat>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn punctuates_printed_values() {
+        let de = format("comma:group");
+        assert_eq!(de.display(&1234567.891, Some(2), false), "1.234.567,89");
+        assert_eq!(de.display(&-0.5, None, false), "-0,5");
+        assert_eq!(de.display(&"26.2840±0.3331", None, false), "26,2840±0,3331");
+        assert_eq!(de.display(&"9.81 m/s^2", None, false), "9,81 m/s^2");
+        assert_eq!(de.display(&"0x1f2345", None, false), "0x1f2345");
+        assert_eq!(de.display(&"1.5+2000.25i", None, false), "1,5+2.000,25i");
+        assert_eq!(de.display(&"x2 * 1000", None, false), "x2 * 1.000");
+        assert_eq!(de.display(&"1000/3", None, false), "1.000/3");
+        assert_eq!(
+            de.display(&"2024-02-29T14:30:07.5", None, false),
+            "2024-02-29T14:30:07.5"
+        );
+        assert_eq!(format("sci").display(&"P1DT2H", None, false), "P1DT2H");
+        assert_eq!(de.display(&f64::INFINITY, None, false), "inf");
+    }
+
+    #[test]
+    fn rounds_and_renotates() {
+        let sig = format("sig3");
+        assert_eq!(sig.display(&12345.678, Some(4), false), "12300");
+        assert_eq!(sig.display(&0.0012345, None, false), "0.00123");
+        assert_eq!(sig.display(&2.0, None, false), "2.00");
+        assert_eq!(sig.display(&-9.999, None, false), "-10.0");
+        assert_eq!(sig.display(&"-2/3", None, false), "-0.667");
+        let sci = format("sci:comma");
+        assert_eq!(sci.display(&12345.678, Some(2), false), "1,23e4");
+        assert_eq!(sci.display(&0.00025, None, false), "2,5e-4");
+        assert_eq!(sci.display(&0.0, Some(1), false), "0,0e0");
+        let eng = format("eng");
+        assert_eq!(eng.display(&12345.678, None, false), "12.345678e3");
+        assert_eq!(eng.display(&0.00025, None, false), "250e-6");
+        assert_eq!(eng.display(&999.96, Some(1), false), "1.0e3");
+        assert_eq!(
+            format("eng:sig2").display(&"4700 ohm", None, false),
+            "4.7e3 ohm"
+        );
+    }
+
+    #[test]
+    fn reads_literals() {
+        let de = format("comma:group");
+        assert_eq!(de.read("6,1").as_deref(), Some("6.1"));
+        assert_eq!(de.read("1.234.567,5").as_deref(), Some("1234567.5"));
+        assert_eq!(de.read("1,6±0,05").as_deref(), Some("1.6±0.05"));
+        assert_eq!(de.read("6,1km").as_deref(), Some("6.1km"));
+        assert_eq!(de.read("-2,5e3").as_deref(), Some("-2.5e3"));
+        assert_eq!(de.read("1.234").as_deref(), Some("1234"));
+        for token in &["1,2,3", "12.34,5", "1.5", "1.2345"] {
+            assert_eq!(de.read(token), None, "{}", token);
+        }
+        for token in &["dup", "0x1,5", "2024-02-29T14:30:07.5", "12:30"] {
+            assert_eq!(de.read(token).as_deref(), Some(*token));
+        }
+        let en = format("group");
+        assert_eq!(en.read("1,234.5").as_deref(), Some("1234.5"));
+        assert_eq!(en.read("1.5").as_deref(), Some("1.5"));
+        assert_eq!(en.read("1,2"), None);
+    }
+}
@@ -1,35 +1,458 @@
+use std::fs;
+use std::io::{self, BufRead, IsTerminal};
+use std::process;
+
+use rpn::interval::Interval;
+use rpn::locale::NumberFormat;
+use rpn::number::{Decimal, Rational};
+use rpn::symbolic::Expr;
+use rpn::time::Time;
+use rpn::units::Units;
+use rpn::value::Value;
+use rpn::word::{Word, WordSize};
+use rpn::{
+    infix, repl, serve, session, token, trace, Diagnostic, Evaluator, InfixError, Number, RpnError,
+};
+
+const EXIT_RUNTIME_ERROR: i32 = 1;
+const EXIT_PARSE_ERROR: i32 = 2;
+const EXIT_USAGE: i32 = 64;
+const EXIT_NO_INPUT: i32 = 66;
+
+const USAGE: &str = "\
+Usage: rpn [OPTIONS] [EXPRESSION]...
+
+Evaluates each EXPRESSION and prints its result. Without expressions or files,
+lines are read from stdin, or an interactive session starts on a terminal.
+
+Options:
+  -f, --file FILE        evaluate each line of FILE (`-` for stdin)
+  -i, --interactive      start an interactive session
+  -l, --load FILE        load word definitions from FILE first
+  -n, --number TYPE      f64 (default), i64, rational, decimal, units
+                         value (complex numbers and vectors), interval
+                         (tolerances such as 1.6±0.05), symbolic (expression
+                         trees of names, with d/dx to differentiate), time
+                         (dates such as 2024-02-28 and durations such as 1d
+                         or P1M) or word
+  -w, --word BITS        use BITS-bit words (8, 16, 32 or 64) that wrap around,
+                         with `0x`, `0o` and `0b` literals and bitwise words
+  -u, --unsigned         make words unsigned
+      --base N           print words in base N (2 to 36)
+  -p, --precision N      print N digits after the point
+      --format SPEC      read and print numbers in SPEC, items such as comma,
+                         group, sci, eng or sig3 joined by `:` (see the
+                         `locale` module); defaults to $RPN_FORMAT
+      --rpn-output       print symbolic results in RPN instead of infix
+  -t, --trace            print a table of the steps of each evaluation
+  -b, --break N          stop tracing after step N
+      --history N        keep up to N values for undo in interactive sessions
+  -s, --session FILE     resume the session saved in FILE, if any, and save the
+                         interactive session back to it on exit
+      --serve            answer JSON requests read from stdin, one per line
+                         (see the `serve` module for the protocol)
+      --to-rpn INFIX     print INFIX converted to RPN
+      --to-infix RPN     print RPN converted to infix
+  -h, --help             print this help
+
+Exit status: 0 on success, 1 if an expression fails while it runs,
+2 if an expression is malformed, 64 on bad usage, 66 if a file cannot be read.
+
+Example: rpn \"6.1 5.2 4.3 * + 3.4 2.5 / 1.6 * -\"";
+
+#[derive(Default)]
+struct Options {
+    expressions: Vec<String>,
+    files: Vec<String>,
+    libraries: Vec<String>,
+    interactive: bool,
+    number: Option<String>,
+    precision: Option<usize>,
+    rpn_output: bool,
+    serve: bool,
+    trace: bool,
+    breakpoint: Option<usize>,
+    history: Option<usize>,
+    format: Option<NumberFormat>,
+    session: Option<String>,
+    word: Option<u32>,
+    unsigned: bool,
+    base: Option<u32>,
+    to_rpn: Option<String>,
+    to_infix: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "-f" | "--file" => options.files.push(value()?),
+            "-i" | "--interactive" => options.interactive = true,
+            "-l" | "--load" => options.libraries.push(value()?),
+            "-n" | "--number" => options.number = Some(value()?),
+            "-p" | "--precision" => {
+                let value = value()?;
+                let precision = value
+                    .parse()
+                    .map_err(|_| format!("invalid precision: {}", value))?;
+                options.precision = Some(precision);
+            }
+            "--rpn-output" => options.rpn_output = true,
+            "--serve" => options.serve = true,
+            "-t" | "--trace" => options.trace = true,
+            "-b" | "--break" => {
+                let value = value()?;
+                let step = value
+                    .parse()
+                    .map_err(|_| format!("invalid breakpoint: {}", value))?;
+                options.trace = true;
+                options.breakpoint = Some(step);
+            }
+            "--history" => {
+                let value = value()?;
+                let capacity = value
+                    .parse()
+                    .map_err(|_| format!("invalid history size: {}", value))?;
+                options.history = Some(capacity);
+            }
+            "-s" | "--session" => options.session = Some(value()?),
+            "--format" => options.format = Some(value()?.parse()?),
+            "-w" | "--word" => {
+                let value = value()?;
+                let bits = value
+                    .parse()
+                    .ok()
+                    .filter(|&bits| WordSize::new(bits, true).is_some())
+                    .ok_or_else(|| format!("invalid word size: {}", value))?;
+                options.word = Some(bits);
+            }
+            "-u" | "--unsigned" => options.unsigned = true,
+            "--base" => {
+                let value = value()?;
+                let base = value
+                    .parse()
+                    .ok()
+                    .filter(|base| (2..=36).contains(base))
+                    .ok_or_else(|| format!("invalid base: {}", value))?;
+                options.base = Some(base);
+            }
+            "--to-rpn" => options.to_rpn = Some(value()?),
+            "--to-infix" => options.to_infix = Some(value()?),
+            "--" => options.expressions.extend(args.by_ref().cloned()),
+            // Negative numbers such as `-3 abs` are expressions, not options.
+            _ if arg.starts_with('-')
+                && arg.len() > 1
+                && !arg[1..].starts_with(|c: char| c.is_ascii_digit() || c == '.') =>
+            {
+                return Err(format!("unknown option: {}", arg))
+            }
+            _ => options.expressions.push(arg.clone()),
+        }
+    }
+    Ok(options)
+}
+
 fn main() {
-    let exp = "6.1 5.2 4.3 * + 3.4 2.5 / 1.6 * -";
-    let ans = rpn(exp);
-    debug_assert_eq!("26.2840", format!("{:.4}", ans));
-    println!("{} = {:.4}", exp, ans);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut options = parse_args(&args).unwrap_or_else(|e| {
+        eprintln!("rpn: {}\n\n{}", e, USAGE);
+        process::exit(EXIT_USAGE);
+    });
+    if options.format.is_none() {
+        if let Ok(spec) = std::env::var("RPN_FORMAT") {
+            let format = spec.parse().unwrap_or_else(|e| {
+                eprintln!("rpn: RPN_FORMAT: {}", e);
+                process::exit(EXIT_USAGE);
+            });
+            options.format = Some(format);
+        }
+    }
+
+    if let Some(exp) = &options.to_rpn {
+        convert(infix::to_rpn(exp).map_err(|e| render_infix_error(&e, exp)));
+    }
+    if let Some(exp) = &options.to_infix {
+        convert(infix::to_infix(exp).map_err(|e| e.to_string()));
+    }
+
+    let word = options.word.is_some() || options.unsigned || options.base.is_some();
+    let default_number = if word { "word" } else { "f64" };
+    let code = match options.number.as_deref().unwrap_or(default_number) {
+        "f64" | "float" => start(Evaluator::<f64>::new, &options, Some(4)),
+        "i64" | "int" => start(Evaluator::<i64>::new, &options, None),
+        "rational" => start(Evaluator::<Rational>::new, &options, None),
+        "decimal" => start(Evaluator::<Decimal>::new, &options, None),
+        "units" => start(|| Units::builtin().evaluator(), &options, Some(4)),
+        "value" => start(
+            || Evaluator::with_operators(Value::operators()),
+            &options,
+            Some(4),
+        ),
+        "interval" => start(
+            || Evaluator::with_operators(Interval::operators()),
+            &options,
+            Some(4),
+        ),
+        "symbolic" => start(
+            || Evaluator::with_operators(Expr::operators()),
+            &options,
+            None,
+        ),
+        "time" => start(
+            || Evaluator::with_operators(Time::operators()),
+            &options,
+            None,
+        ),
+        "word" => {
+            let size = WordSize::new(options.word.unwrap_or(64), !options.unsigned).unwrap();
+            let base = options.base.unwrap_or(10);
+            start(move || Word::evaluator(size, base), &options, None)
+        }
+        number => {
+            eprintln!(
+                "rpn: unknown number type: {} (expected f64, i64, rational, decimal, units, value, interval, symbolic, time or word)",
+                number
+            );
+            EXIT_USAGE
+        }
+    };
+    process::exit(code);
+}
+
+/// Renders a conversion error with the offending part of `exp` marked.
+fn render_infix_error(e: &InfixError, exp: &str) -> String {
+    match e.span() {
+        Some(span) => format!("{}\n{}", e, token::render_caret(exp, span)),
+        None => e.to_string(),
+    }
+}
+
+fn convert(converted: Result<String, String>) -> ! {
+    match converted {
+        Ok(exp) => {
+            println!("{}", exp);
+            process::exit(0)
+        }
+        Err(e) => {
+            eprintln!("rpn: {}", e);
+            process::exit(EXIT_PARSE_ERROR)
+        }
+    }
 }
 
-fn rpn(exp: &str) -> f64 {
-    let mut stack = Vec::new();
-    for token in exp.split_whitespace() {
-        if let Ok(num) = token.parse::<f64>() {
-            stack.push(num)
+/// How results are printed.
+struct Printing {
+    precision: Option<usize>,
+    format: NumberFormat,
+    /// Print with the alternate flag, which symbolic results use for RPN.
+    alternate: bool,
+    trace: bool,
+    breakpoint: Option<usize>,
+}
+
+/// Runs the session described by `options` on evaluators made by
+/// `new_evaluator` and returns the exit status.
+fn start<N: Number, F: Fn() -> Evaluator<N>>(
+    new_evaluator: F,
+    options: &Options,
+    default_precision: Option<usize>,
+) -> i32 {
+    let printing = Printing {
+        precision: options.precision.or(default_precision),
+        format: options.format.unwrap_or_default(),
+        alternate: options.rpn_output,
+        trace: options.trace,
+        breakpoint: options.breakpoint,
+    };
+    let format = printing.format;
+    let new_evaluator = || {
+        let mut evaluator = new_evaluator();
+        if !format.is_default() {
+            evaluator.rewrite_literals(move |token| format.read(token));
+        }
+        evaluator
+    };
+    let mut evaluator = new_evaluator();
+    let mut libraries = Vec::new();
+    for path in &options.libraries {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("rpn: {}: {}", path, e);
+                return EXIT_NO_INPUT;
+            }
+        };
+        if let Err(e) = evaluator.load(&source) {
+            eprintln!("rpn: {}: {}", path, e.render(&source));
+            return exit_code(&e.error);
+        }
+        libraries.push(source);
+    }
+    if options.serve {
+        let new_session = || {
+            let mut evaluator = new_evaluator();
+            for source in &libraries {
+                // Every library has loaded into the first evaluator already.
+                let _ = evaluator.load(source);
+            }
+            evaluator
+        };
+        if let Err(e) = serve::run(new_session, io::stdin().lock(), io::stdout().lock()) {
+            eprintln!("rpn: {}", e);
+            return EXIT_NO_INPUT;
+        }
+        return 0;
+    }
+    if let Some(path) = &options.session {
+        match fs::read_to_string(path) {
+            Ok(source) => {
+                if let Err(e) = session::restore(&mut evaluator, &source) {
+                    eprintln!("rpn: {}: {}", path, e);
+                    return EXIT_PARSE_ERROR;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                eprintln!("rpn: {}: {}", path, e);
+                return EXIT_NO_INPUT;
+            }
+        }
+    }
+
+    let mut status = 0;
+    for exp in &options.expressions {
+        if let Err(e) = eval_line(&mut evaluator, exp, &printing) {
+            eprintln!("rpn: {}", e.render(exp));
+            status = first_failure(status, exit_code(&e.error));
+        }
+    }
+    for path in &options.files {
+        let code = if path == "-" {
+            eval_lines(&mut evaluator, "<stdin>", io::stdin().lock(), &printing)
         } else {
-            match token {
-                "+" => apply2(&mut stack, |x, y| x + y),
-                "-" => apply2(&mut stack, |x, y| x - y),
-                "*" => apply2(&mut stack, |x, y| x * y),
-                "/" => apply2(&mut stack, |x, y| x / y),
-                _ => panic!("Unknown operator: {}", token),
+            match fs::File::open(path) {
+                Ok(file) => eval_lines(&mut evaluator, path, io::BufReader::new(file), &printing),
+                Err(e) => {
+                    eprintln!("rpn: {}: {}", path, e);
+                    EXIT_NO_INPUT
+                }
             }
+        };
+        status = first_failure(status, code);
+    }
+
+    let nothing_else = options.expressions.is_empty() && options.files.is_empty();
+    if options.interactive || (nothing_else && io::stdin().is_terminal()) {
+        if let Some(capacity) = options.history {
+            evaluator.history_mut().set_capacity(capacity);
+        }
+        let stdin = io::stdin();
+        let output = io::stdout();
+        if let Err(e) = repl::run_with_format(&mut evaluator, &format, stdin.lock(), output) {
+            eprintln!("rpn: {}", e);
+            return EXIT_NO_INPUT;
         }
+        if let Some(path) = &options.session {
+            if let Err(e) = fs::write(path, session::save(&evaluator)) {
+                eprintln!("rpn: {}: {}", path, e);
+                return EXIT_NO_INPUT;
+            }
+        }
+    } else if nothing_else {
+        status = eval_lines(&mut evaluator, "<stdin>", io::stdin().lock(), &printing);
+    }
+    status
+}
+
+/// Evaluates every non-blank line, reporting failures with their line number.
+/// Returns the exit status of the first failure, or 0.
+fn eval_lines<N: Number, R: BufRead>(
+    evaluator: &mut Evaluator<N>,
+    name: &str,
+    input: R,
+    printing: &Printing,
+) -> i32 {
+    let mut status = 0;
+    for (number, line) in input.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("rpn: {}: {}", name, e);
+                return first_failure(status, EXIT_NO_INPUT);
+            }
+        };
+        let line = line.split('#').next().unwrap_or("");
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Err(e) = eval_line(evaluator, line, printing) {
+            eprintln!("rpn: {}:{}: {}", name, number + 1, e.render(line));
+            status = first_failure(status, exit_code(&e.error));
+        }
+    }
+    status
+}
+
+/// Runs one expression on an empty stack and prints its result. Lines that
+/// only define words or store variables leave nothing and print nothing.
+fn eval_line<N: Number>(
+    evaluator: &mut Evaluator<N>,
+    exp: &str,
+    printing: &Printing,
+) -> Result<(), Diagnostic> {
+    evaluator.clear();
+    let run = if printing.trace {
+        let trace = evaluator.trace(exp, printing.breakpoint);
+        print!("{}", trace::render_table(&trace.steps));
+        if let Err(RpnError::Breakpoint { step }) = trace.result {
+            println!(
+                "stopped before step {}: {}",
+                step,
+                repl::format_stack(evaluator.stack())
+            );
+            evaluator.clear();
+            return Ok(());
+        }
+        let span = trace.steps.last().map(|step| step.span);
+        trace.result.map_err(|error| Diagnostic { error, span })
+    } else {
+        evaluator.run_spanned(exp)
+    };
+    let result = run.and_then(|()| match evaluator.stack().len() {
+        0 => Ok(None),
+        1 => Ok(evaluator.stack().last().cloned()),
+        n => Err(RpnError::LeftoverValues { count: n - 1 }.into()),
+    });
+    evaluator.clear();
+    if let Some(ans) = result? {
+        let (precision, alternate) = (printing.precision, printing.alternate);
+        println!("{}", printing.format.display(&ans, precision, alternate));
     }
-    stack.pop().expect("Stack underflow")
+    Ok(())
 }
 
-fn apply2<F>(stack: &mut Vec<f64>, fun: F)
-    where F: Fn(f64, f64) -> f64
-{
-    if let (Some(y), Some(x)) = (stack.pop(), stack.pop()) {
-        let z = fun(x, y);
-        stack.push(z);
+fn exit_code(e: &RpnError) -> i32 {
+    if e.is_parse_error() {
+        EXIT_PARSE_ERROR
     } else {
-        panic!("Stack underflow")
+        EXIT_RUNTIME_ERROR
     }
-}
\ No newline at end of file
+}
+
+fn first_failure(status: i32, code: i32) -> i32 {
+    if status == 0 {
+        code
+    } else {
+        status
+    }
+}
@@ -0,0 +1,221 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+use super::{checked_mul, exponent_cap, integer_pow, parse_decimal, BigInt, Number, MAX_EXPONENT};
+use crate::error::ArithError;
+
+/// Digits kept after the point when a division does not terminate.
+pub const DIV_SCALE: u32 = 32;
+
+/// An arbitrary-precision decimal, `mantissa / 10^scale`.
+///
+/// Addition, subtraction and multiplication are exact. Division is exact when
+/// the quotient terminates and is otherwise truncated to [`DIV_SCALE`] digits.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Decimal {
+    mantissa: BigInt,
+    scale: u32,
+}
+
+impl Decimal {
+    pub fn new(mantissa: BigInt, scale: u32) -> Self {
+        let mut d = Self { mantissa, scale };
+        let ten = BigInt::from(10);
+        while d.scale > 0 {
+            let (q, r) = d.mantissa.div_rem(&ten).unwrap();
+            if !r.is_zero() {
+                break;
+            }
+            d.mantissa = q;
+            d.scale -= 1;
+        }
+        d
+    }
+
+    fn rescaled(&self, scale: u32) -> Result<BigInt, ArithError> {
+        match scale - self.scale {
+            shift if shift > MAX_EXPONENT => Err(ArithError::Overflow),
+            shift => checked_mul(&self.mantissa, &BigInt::pow10(shift)),
+        }
+    }
+
+    /// Rounds half away from zero to `digits` places after the point.
+    pub fn round(&self, digits: u32) -> Self {
+        if self.scale <= digits {
+            return self.clone();
+        }
+        let (q, r) = self
+            .mantissa
+            .div_rem(&BigInt::pow10(self.scale - digits))
+            .unwrap();
+        let twice = &r.abs() * &BigInt::from(2);
+        let q = if twice >= BigInt::pow10(self.scale - digits) {
+            let unit = BigInt::from(if self.mantissa.is_negative() { -1 } else { 1 });
+            &q + &unit
+        } else {
+            q
+        };
+        Self::new(q, digits)
+    }
+
+    // The digits without sign, with exactly `places` digits after the point.
+    fn write_digits(&self, places: u32) -> String {
+        let digits = self.mantissa.abs().to_string();
+        let scale = self.scale as usize;
+        let padded = format!("{:0>width$}", digits, width = scale + 1);
+        let (int_part, frac_part) = padded.split_at(padded.len() - scale);
+        let mut s = String::from(int_part);
+        if places > 0 {
+            s.push('.');
+            s.push_str(frac_part);
+            s.push_str(&"0".repeat(places as usize - scale));
+        }
+        s
+    }
+}
+
+impl Number for Decimal {
+    fn parse(token: &str) -> Option<Self> {
+        let (mantissa, exp) = parse_decimal(token)?;
+        if exp < 0 {
+            Some(Self::new(mantissa, u32::try_from(-exp).ok()?))
+        } else {
+            let scale = BigInt::pow10(u32::try_from(exp).ok()?);
+            Some(Self::new(&mantissa * &scale, 0))
+        }
+    }
+
+    fn from_i64(n: i64) -> Self {
+        Self::new(BigInt::from(n), 0)
+    }
+
+    fn from_f64(x: f64) -> Option<Self> {
+        if !x.is_finite() {
+            return None;
+        }
+        Self::parse(&x.to_string())
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, ArithError> {
+        let scale = self.scale.max(rhs.scale);
+        Ok(Self::new(
+            &self.rescaled(scale)? + &rhs.rescaled(scale)?,
+            scale,
+        ))
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, ArithError> {
+        let scale = self.scale.max(rhs.scale);
+        Ok(Self::new(
+            &self.rescaled(scale)? - &rhs.rescaled(scale)?,
+            scale,
+        ))
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, ArithError> {
+        let scale = self.scale + rhs.scale;
+        if scale > MAX_EXPONENT {
+            return Err(ArithError::Overflow);
+        }
+        Ok(Self::new(
+            checked_mul(&self.mantissa, &rhs.mantissa)?,
+            scale,
+        ))
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, ArithError> {
+        let scale = DIV_SCALE.max(self.scale).max(rhs.scale);
+        let num = checked_mul(
+            &self.mantissa,
+            &BigInt::pow10(scale + rhs.scale - self.scale),
+        )?;
+        let (q, _) = num
+            .div_rem(&rhs.mantissa)
+            .ok_or(ArithError::DivisionByZero)?;
+        Ok(Self::new(q, scale))
+    }
+
+    fn rem(&self, rhs: &Self) -> Result<Self, ArithError> {
+        let scale = self.scale.max(rhs.scale);
+        let (_, r) = self
+            .rescaled(scale)?
+            .div_rem(&rhs.rescaled(scale)?)
+            .ok_or(ArithError::DivisionByZero)?;
+        Ok(Self::new(r, scale))
+    }
+
+    /// Overflows when the result would have far more than [`MAX_EXPONENT`] digits.
+    fn pow(&self, rhs: &Self) -> Result<Self, ArithError> {
+        integer_pow(self, rhs, exponent_cap(self.mantissa.digits()))
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let scale = self.scale.max(other.scale);
+        Some(self.rescaled(scale).ok()?.cmp(&other.rescaled(scale).ok()?))
+    }
+}
+
+/// Honours the precision flag (`{:.4}`) by rounding half away from zero.
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (value, places) = match f.precision() {
+            Some(places) => (self.round(places as u32), places as u32),
+            None => (self.clone(), self.scale),
+        };
+        let non_negative = !value.mantissa.is_negative();
+        f.pad_integral(non_negative, "", &value.write_digits(places))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decimal;
+    use crate::error::ArithError;
+    use crate::number::Number;
+
+    fn d(s: &str) -> Decimal {
+        Decimal::parse(s).unwrap()
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(d("6.10").to_string(), "6.1");
+        assert_eq!(d("-0.05").to_string(), "-0.05");
+        assert_eq!(d("1.5e3").to_string(), "1500");
+        assert_eq!(format!("{:.4}", d("26.284")), "26.2840");
+        assert_eq!(format!("{:.1}", d("-0.25")), "-0.3");
+        assert_eq!(format!("{:.0}", d("0.4")), "0");
+    }
+
+    #[test]
+    fn exact_arithmetic() {
+        assert_eq!(d("0.1").add(&d("0.2")), Ok(d("0.3")));
+        assert_eq!(d("5.2").mul(&d("4.3")), Ok(d("22.36")));
+        assert_eq!(d("3.4").div(&d("2.5")), Ok(d("1.36")));
+        assert_eq!(d("7.5").rem(&d("2")), Ok(d("1.5")));
+        assert_eq!(d("1").div(&d("0")), Err(ArithError::DivisionByZero));
+        assert_eq!(
+            d("2").div(&d("3")).unwrap().to_string(),
+            "0.66666666666666666666666666666666"
+        );
+        assert!(d("-1.5") < d("-1.25"));
+    }
+
+    #[test]
+    fn exponents_are_bounded() {
+        assert_eq!(Decimal::parse("1e4000000000"), None);
+        assert_eq!(Decimal::parse("1e-400000000"), None);
+        assert_eq!(d("1e-10000").to_string().len(), 10002);
+        let tiny = d("1e-6000");
+        assert_eq!(tiny.mul(&tiny), Err(ArithError::Overflow));
+        assert_eq!(d("2").pow(&d("1000000000")), Err(ArithError::Overflow));
+        assert_eq!(d("10").pow(&d("3")), Ok(d("1000")));
+    }
+}
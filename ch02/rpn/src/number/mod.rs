@@ -0,0 +1,315 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::error::ArithError;
+
+mod bigint;
+mod decimal;
+mod rational;
+
+pub use self::bigint::BigInt;
+pub use self::decimal::Decimal;
+pub use self::rational::Rational;
+
+/// A value type the evaluator can compute with.
+///
+/// Arithmetic is fallible so that exact types can report overflow or division
+/// by zero instead of producing a wrong result. The transcendental functions
+/// go through `f64` and fail if the result cannot be represented.
+pub trait Number: Clone + PartialEq + PartialOrd + fmt::Debug + fmt::Display + 'static {
+    /// Parses a literal, returning `None` if `token` is not a number of this type.
+    fn parse(token: &str) -> Option<Self>;
+
+    fn from_i64(n: i64) -> Self;
+
+    /// Converts a float, returning `None` if it cannot be represented.
+    fn from_f64(x: f64) -> Option<Self>;
+
+    fn to_f64(&self) -> f64;
+
+    fn add(&self, rhs: &Self) -> Result<Self, ArithError>;
+    fn sub(&self, rhs: &Self) -> Result<Self, ArithError>;
+    fn mul(&self, rhs: &Self) -> Result<Self, ArithError>;
+    fn div(&self, rhs: &Self) -> Result<Self, ArithError>;
+    fn rem(&self, rhs: &Self) -> Result<Self, ArithError>;
+
+    fn neg(&self) -> Result<Self, ArithError> {
+        Self::from_i64(0).sub(self)
+    }
+
+    fn abs(&self) -> Result<Self, ArithError> {
+        if *self < Self::from_i64(0) {
+            self.neg()
+        } else {
+            Ok(self.clone())
+        }
+    }
+
+    /// Raises to `rhs`, exactly when `rhs` is an integer.
+    fn pow(&self, rhs: &Self) -> Result<Self, ArithError> {
+        integer_pow(self, rhs, u32::MAX)
+    }
+
+    /// Applies `fun` to the value converted to `f64`.
+    fn map_f64<F>(&self, fun: F) -> Result<Self, ArithError>
+    where
+        F: Fn(f64) -> f64,
+    {
+        Self::from_f64(fun(self.to_f64())).ok_or(ArithError::NotRepresentable)
+    }
+
+    /// The value comparisons push: 1 for true and 0 for false.
+    fn from_bool(b: bool) -> Self {
+        Self::from_i64(b as i64)
+    }
+
+    /// Tells whether `if` takes its first branch: anything but zero is true.
+    fn is_truthy(&self) -> bool {
+        *self != Self::from_i64(0)
+    }
+
+    /// Builds the value `[ ... ]` pushes, or `None` if the type has no vectors.
+    fn from_vector(_items: Vec<Self>) -> Option<Self> {
+        None
+    }
+
+    /// Returns the elements if the value is a vector.
+    fn to_vector(&self) -> Option<Vec<Self>> {
+        None
+    }
+
+    /// Writes the value as RPN that pushes it back, as sessions are saved.
+    fn to_source(&self) -> String {
+        self.to_string()
+    }
+}
+
+fn integer_exponent<N: Number>(n: &N) -> Option<i64> {
+    let x = n.to_f64();
+    if x.fract() == 0.0 && x.abs() <= u32::MAX as f64 && N::from_i64(x as i64) == *n {
+        Some(x as i64)
+    } else {
+        None
+    }
+}
+
+/// The largest power of ten, and the largest integer exponent, that the exact
+/// number types accept; beyond it they fail instead of exhausting memory.
+pub(crate) const MAX_EXPONENT: u32 = 10_000;
+
+/// The most digits the factors of an exact product may have together; a
+/// larger product overflows, however it is reached.
+pub(crate) const MAX_DIGITS: usize = 2 * MAX_EXPONENT as usize;
+
+/// Multiplies `a` by `b`, or overflows if the product could have more than
+/// [`MAX_DIGITS`] digits.
+pub(crate) fn checked_mul(a: &BigInt, b: &BigInt) -> Result<BigInt, ArithError> {
+    if a.digits() + b.digits() > MAX_DIGITS {
+        return Err(ArithError::Overflow);
+    }
+    Ok(a * b)
+}
+
+/// The largest integer exponent for a base of `digits` digits whose power
+/// stays within about twice [`MAX_EXPONENT`] digits.
+pub(crate) fn exponent_cap(digits: usize) -> u32 {
+    let digits = u32::try_from(digits.saturating_sub(1)).unwrap_or(u32::MAX);
+    MAX_EXPONENT / digits.max(1)
+}
+
+/// Raises `x` to `rhs` by repeated squaring when `rhs` is an integer of at
+/// most `max_exponent` in magnitude, and through `f64` when it is not one.
+pub(crate) fn integer_pow<N: Number>(x: &N, rhs: &N, max_exponent: u32) -> Result<N, ArithError> {
+    match integer_exponent(rhs) {
+        Some(exp) if exp.unsigned_abs() > u64::from(max_exponent) => Err(ArithError::Overflow),
+        Some(exp) => {
+            let mut result = N::from_i64(1);
+            let mut base = x.clone();
+            let mut exp_left = exp.unsigned_abs();
+            while exp_left > 0 {
+                if exp_left & 1 == 1 {
+                    result = result.mul(&base)?;
+                }
+                exp_left >>= 1;
+                if exp_left > 0 {
+                    base = base.mul(&base)?;
+                }
+            }
+            if exp < 0 {
+                N::from_i64(1).div(&result)
+            } else {
+                Ok(result)
+            }
+        }
+        None => x.map_f64(|x| x.powf(rhs.to_f64())),
+    }
+}
+
+/// Splits a decimal literal such as `-6.1` or `2.5e-3` into an integer
+/// mantissa and a power of ten of at most [`MAX_EXPONENT`] in magnitude.
+pub(crate) fn parse_decimal(token: &str) -> Option<(BigInt, i64)> {
+    let (number, exp) = match token.find(['e', 'E']) {
+        Some(i) => (&token[..i], token[i + 1..].parse::<i64>().ok()?),
+        None => (token, 0),
+    };
+    let (int_part, frac_part) = match number.find('.') {
+        Some(i) => (&number[..i], &number[i + 1..]),
+        None => (number, ""),
+    };
+    let digits = int_part.trim_start_matches(['+', '-']);
+    if digits.is_empty() && frac_part.is_empty() {
+        return None;
+    }
+    if !frac_part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut mantissa = String::from(int_part);
+    if digits.is_empty() {
+        mantissa.push('0');
+    }
+    mantissa.push_str(frac_part);
+    let mantissa = mantissa.parse().ok()?;
+    let exp = exp.checked_sub(frac_part.len() as i64)?;
+    if exp.unsigned_abs() > u64::from(MAX_EXPONENT) {
+        return None;
+    }
+    Some((mantissa, exp))
+}
+
+impl Number for f64 {
+    fn parse(token: &str) -> Option<Self> {
+        token.parse().ok()
+    }
+
+    fn from_i64(n: i64) -> Self {
+        n as f64
+    }
+
+    fn from_f64(x: f64) -> Option<Self> {
+        Some(x)
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(self + rhs)
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(self - rhs)
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(self * rhs)
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(self / rhs)
+    }
+
+    fn rem(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(self % rhs)
+    }
+
+    fn neg(&self) -> Result<Self, ArithError> {
+        Ok(-self)
+    }
+
+    fn abs(&self) -> Result<Self, ArithError> {
+        Ok(f64::abs(*self))
+    }
+
+    fn pow(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(self.powf(*rhs))
+    }
+}
+
+impl Number for i64 {
+    fn parse(token: &str) -> Option<Self> {
+        token.parse().ok()
+    }
+
+    fn from_i64(n: i64) -> Self {
+        n
+    }
+
+    fn from_f64(x: f64) -> Option<Self> {
+        if x.fract() == 0.0 && x >= i64::MIN as f64 && x < i64::MAX as f64 {
+            Some(x as i64)
+        } else {
+            None
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.checked_add(*rhs).ok_or(ArithError::Overflow)
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.checked_sub(*rhs).ok_or(ArithError::Overflow)
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.checked_mul(*rhs).ok_or(ArithError::Overflow)
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, ArithError> {
+        if *rhs == 0 {
+            return Err(ArithError::DivisionByZero);
+        }
+        self.checked_div(*rhs).ok_or(ArithError::Overflow)
+    }
+
+    fn rem(&self, rhs: &Self) -> Result<Self, ArithError> {
+        if *rhs == 0 {
+            return Err(ArithError::DivisionByZero);
+        }
+        self.checked_rem(*rhs).ok_or(ArithError::Overflow)
+    }
+
+    fn neg(&self) -> Result<Self, ArithError> {
+        self.checked_neg().ok_or(ArithError::Overflow)
+    }
+
+    fn pow(&self, rhs: &Self) -> Result<Self, ArithError> {
+        let exp = u32::try_from(*rhs).map_err(|_| ArithError::NotRepresentable)?;
+        self.checked_pow(exp).ok_or(ArithError::Overflow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_decimal, Number};
+    use crate::error::ArithError;
+
+    #[test]
+    fn decimal_literals() {
+        let parse = |s| parse_decimal(s).map(|(m, e)| (m.to_string(), e));
+        assert_eq!(parse("6.1"), Some(("61".to_string(), -1)));
+        assert_eq!(parse("-.5"), Some(("-5".to_string(), -1)));
+        assert_eq!(parse("2.5e-3"), Some(("25".to_string(), -4)));
+        assert_eq!(parse("12"), Some(("12".to_string(), 0)));
+        assert_eq!(parse("."), None);
+        assert_eq!(parse("1.2.3"), None);
+        assert_eq!(parse("x"), None);
+        assert_eq!(parse("1e10000").map(|(_, e)| e), Some(10000));
+        assert_eq!(parse("1e10001"), None);
+        assert_eq!(parse("0.1e-10000"), None);
+    }
+
+    #[test]
+    fn i64_is_checked() {
+        assert_eq!(i64::MAX.add(&1), Err(ArithError::Overflow));
+        assert_eq!(7i64.div(&0), Err(ArithError::DivisionByZero));
+        assert_eq!(Number::pow(&2i64, &62), Ok(1 << 62));
+        assert_eq!(Number::pow(&2i64, &63), Err(ArithError::Overflow));
+        assert_eq!(16i64.map_f64(f64::sqrt), Ok(4));
+        assert_eq!(2i64.map_f64(f64::sqrt), Err(ArithError::NotRepresentable));
+    }
+}
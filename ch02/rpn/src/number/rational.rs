@@ -0,0 +1,184 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+use super::{checked_mul, exponent_cap, integer_pow, parse_decimal, BigInt, Number, MAX_EXPONENT};
+use crate::error::ArithError;
+
+/// An exact fraction, always kept in lowest terms with a positive denominator.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rational {
+    num: BigInt,
+    den: BigInt,
+}
+
+impl Rational {
+    /// Creates `num / den`, returning `None` if `den` is zero.
+    pub fn new(num: BigInt, den: BigInt) -> Option<Self> {
+        if den.is_zero() {
+            return None;
+        }
+        let gcd = num.gcd(&den);
+        let (mut num, _) = num.div_rem(&gcd).unwrap();
+        let (mut den, _) = den.div_rem(&gcd).unwrap();
+        if den.is_negative() {
+            num = -&num;
+            den = -&den;
+        }
+        Some(Self { num, den })
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.num
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.den
+    }
+
+    fn from_decimal(mantissa: BigInt, exp: i64) -> Option<Self> {
+        let exp_abs = u32::try_from(exp.unsigned_abs()).ok()?;
+        if exp_abs > MAX_EXPONENT {
+            return None;
+        }
+        let scale = BigInt::pow10(exp_abs);
+        if exp < 0 {
+            Self::new(mantissa, scale)
+        } else {
+            Self::new(&mantissa * &scale, BigInt::one())
+        }
+    }
+}
+
+impl Number for Rational {
+    /// Accepts decimal literals (`6.1`, `1e-3`) and fractions (`3/4`).
+    fn parse(token: &str) -> Option<Self> {
+        if let Some((num, den)) = token.split_once('/') {
+            return Self::new(num.parse().ok()?, den.parse().ok()?);
+        }
+        let (mantissa, exp) = parse_decimal(token)?;
+        Self::from_decimal(mantissa, exp)
+    }
+
+    fn from_i64(n: i64) -> Self {
+        Self {
+            num: BigInt::from(n),
+            den: BigInt::one(),
+        }
+    }
+
+    fn from_f64(x: f64) -> Option<Self> {
+        if !x.is_finite() {
+            return None;
+        }
+        Self::parse(&x.to_string())
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.num.to_f64() / self.den.to_f64()
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(Self::new(
+            &checked_mul(&self.num, &rhs.den)? + &checked_mul(&rhs.num, &self.den)?,
+            checked_mul(&self.den, &rhs.den)?,
+        )
+        .unwrap())
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(Self::new(
+            &checked_mul(&self.num, &rhs.den)? - &checked_mul(&rhs.num, &self.den)?,
+            checked_mul(&self.den, &rhs.den)?,
+        )
+        .unwrap())
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(Self::new(
+            checked_mul(&self.num, &rhs.num)?,
+            checked_mul(&self.den, &rhs.den)?,
+        )
+        .unwrap())
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Self::new(
+            checked_mul(&self.num, &rhs.den)?,
+            checked_mul(&self.den, &rhs.num)?,
+        )
+        .ok_or(ArithError::DivisionByZero)
+    }
+
+    /// The remainder of the division truncated towards zero.
+    fn rem(&self, rhs: &Self) -> Result<Self, ArithError> {
+        let (quot, _) = checked_mul(&self.num, &rhs.den)?
+            .div_rem(&checked_mul(&self.den, &rhs.num)?)
+            .ok_or(ArithError::DivisionByZero)?;
+        self.sub(&Self::from_decimal(quot, 0).unwrap().mul(rhs)?)
+    }
+
+    /// Overflows when the result would have far more than [`MAX_EXPONENT`] digits.
+    fn pow(&self, rhs: &Self) -> Result<Self, ArithError> {
+        let digits = self.num.digits().max(self.den.digits());
+        integer_pow(self, rhs, exponent_cap(digits))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some((&self.num * &other.den).cmp(&(&other.num * &self.den)))
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.den == BigInt::one() {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rational;
+    use crate::error::ArithError;
+    use crate::number::Number;
+
+    fn q(s: &str) -> Rational {
+        Rational::parse(s).unwrap()
+    }
+
+    #[test]
+    fn literals_are_reduced() {
+        assert_eq!(q("6/-4").to_string(), "-3/2");
+        assert_eq!(q("2.50").to_string(), "5/2");
+        assert_eq!(q("1e3").to_string(), "1000");
+        assert_eq!(Rational::parse("1/0"), None);
+    }
+
+    #[test]
+    fn exact_arithmetic() {
+        assert_eq!(q("1/3").add(&q("1/6")), Ok(q("1/2")));
+        assert_eq!(q("0.1").add(&q("0.2")), Ok(q("0.3")));
+        assert_eq!(q("7/2").rem(&q("1")), Ok(q("1/2")));
+        assert_eq!(q("2/3").pow(&q("-2")), Ok(q("9/4")));
+        assert_eq!(q("1").div(&q("0")), Err(ArithError::DivisionByZero));
+        assert!(q("1/3") < q("0.34"));
+    }
+
+    #[test]
+    fn exponents_are_bounded() {
+        assert_eq!(Rational::parse("1e4000000000"), None);
+        assert_eq!(Rational::parse("1e-20000"), None);
+        assert_eq!(q("2").pow(&q("1000000000")), Err(ArithError::Overflow));
+        assert_eq!(
+            q("2").pow(&q("-10000")).map(|r| r.denom().digits()),
+            Ok(3011)
+        );
+        let big = q("2").pow(&q("5000")).unwrap();
+        assert_eq!(big.pow(&q("5000")), Err(ArithError::Overflow));
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::error::{ArithError, RpnError};
+use crate::number::Number;
+
+pub type UnaryFn<N> = Rc<dyn Fn(N) -> Result<N, ArithError>>;
+pub type BinaryFn<N> = Rc<dyn Fn(N, N) -> Result<N, ArithError>>;
+pub type NaryFn<N> = Rc<dyn Fn(&[N]) -> Result<N, ArithError>>;
+pub type ResolveFn<N> = Rc<dyn Fn(&str) -> Option<Operator<N>>>;
+
+/// An operator taking its operands from the top of the stack and pushing one result.
+pub enum Operator<N> {
+    Unary(UnaryFn<N>),
+    Binary(BinaryFn<N>),
+    /// Takes a fixed number of operands, passed bottom first.
+    Nary(usize, NaryFn<N>),
+}
+
+impl<N> Clone for Operator<N> {
+    fn clone(&self) -> Self {
+        match self {
+            Operator::Unary(fun) => Operator::Unary(Rc::clone(fun)),
+            Operator::Binary(fun) => Operator::Binary(Rc::clone(fun)),
+            Operator::Nary(n, fun) => Operator::Nary(*n, Rc::clone(fun)),
+        }
+    }
+}
+
+impl<N: Number> Operator<N> {
+    pub fn arity(&self) -> usize {
+        match self {
+            Operator::Unary(_) => 1,
+            Operator::Binary(_) => 2,
+            Operator::Nary(n, _) => *n,
+        }
+    }
+
+    /// Applies the operator to `stack`, naming it `name` in errors.
+    pub fn apply(&self, name: &str, stack: &mut Vec<N>) -> Result<(), RpnError> {
+        match self {
+            Operator::Unary(fun) => apply1(stack, name, |x| fun(x)),
+            Operator::Binary(fun) => apply2(stack, name, |x, y| fun(x, y)),
+            Operator::Nary(n, fun) => apply_n(stack, name, *n, |args| fun(args)),
+        }
+    }
+}
+
+/// The table of operators an evaluator looks tokens up in.
+pub struct Operators<N> {
+    table: HashMap<String, Operator<N>>,
+    fallback: Option<ResolveFn<N>>,
+}
+
+impl<N> Clone for Operators<N> {
+    fn clone(&self) -> Self {
+        Self {
+            table: self.table.clone(),
+            fallback: self.fallback.clone(),
+        }
+    }
+}
+
+impl<N> Default for Operators<N> {
+    fn default() -> Self {
+        Self {
+            table: HashMap::new(),
+            fallback: None,
+        }
+    }
+}
+
+impl<N: Number> Operators<N> {
+    /// Creates an empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a table with the arithmetic and math operators.
+    pub fn builtin() -> Self {
+        let mut ops = Self::new();
+        ops.register_binary("+", |x, y| x.add(&y));
+        ops.register_binary("-", |x, y| x.sub(&y));
+        ops.register_binary("*", |x, y| x.mul(&y));
+        ops.register_binary("/", |x, y| x.div(&y));
+        ops.register_binary("%", |x, y| x.rem(&y));
+        ops.register_binary("^", |x, y| x.pow(&y));
+        ops.register_binary("pow", |x, y| x.pow(&y));
+        ops.register_binary("min", |x, y| Ok(if y < x { y } else { x }));
+        ops.register_binary("max", |x, y| Ok(if y > x { y } else { x }));
+        ops.register_binary("<", |x, y| Ok(N::from_bool(x < y)));
+        ops.register_binary("<=", |x, y| Ok(N::from_bool(x <= y)));
+        ops.register_binary("=", |x, y| Ok(N::from_bool(x == y)));
+        ops.register_binary("<>", |x, y| Ok(N::from_bool(x != y)));
+        ops.register_binary(">", |x, y| Ok(N::from_bool(x > y)));
+        ops.register_binary(">=", |x, y| Ok(N::from_bool(x >= y)));
+        ops.register_unary("neg", |x| x.neg());
+        ops.register_unary("abs", |x| x.abs());
+        ops.register_unary("sqrt", |x| x.map_f64(f64::sqrt));
+        ops.register_unary("ln", |x| x.map_f64(f64::ln));
+        ops.register_unary("exp", |x| x.map_f64(f64::exp));
+        ops.register_unary("sin", |x| x.map_f64(f64::sin));
+        ops.register_unary("cos", |x| x.map_f64(f64::cos));
+        ops.register_unary("tan", |x| x.map_f64(f64::tan));
+        ops
+    }
+
+    /// Registers (or replaces) `name` with an already built operator.
+    pub fn register(&mut self, name: &str, operator: Operator<N>) {
+        self.table.insert(name.to_string(), operator);
+    }
+
+    pub fn register_unary<F>(&mut self, name: &str, fun: F)
+    where
+        F: Fn(N) -> Result<N, ArithError> + 'static,
+    {
+        self.register(name, Operator::Unary(Rc::new(fun)));
+    }
+
+    pub fn register_binary<F>(&mut self, name: &str, fun: F)
+    where
+        F: Fn(N, N) -> Result<N, ArithError> + 'static,
+    {
+        self.register(name, Operator::Binary(Rc::new(fun)));
+    }
+
+    pub fn register_nary<F>(&mut self, name: &str, arity: usize, fun: F)
+    where
+        F: Fn(&[N]) -> Result<N, ArithError> + 'static,
+    {
+        self.register(name, Operator::Nary(arity, Rc::new(fun)));
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Operator<N>> {
+        self.table.remove(name)
+    }
+
+    /// Sets how names that are not registered are looked up, e.g. to build
+    /// a whole family of operators such as `km->m` on demand. The evaluator
+    /// asks it last, after variables, so it never shadows a bound name.
+    pub fn set_fallback<F>(&mut self, resolve: F)
+    where
+        F: Fn(&str) -> Option<Operator<N>> + 'static,
+    {
+        self.fallback = Some(Rc::new(resolve));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Operator<N>> {
+        self.registered(name).or_else(|| self.resolve(name))
+    }
+
+    /// Looks `name` up among the registered operators only.
+    pub fn registered(&self, name: &str) -> Option<Operator<N>> {
+        self.table.get(name).cloned()
+    }
+
+    /// Asks the fallback for `name`, ignoring the registered operators.
+    pub fn resolve(&self, name: &str) -> Option<Operator<N>> {
+        self.fallback.as_ref().and_then(|resolve| resolve(name))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Returns the registered names in alphabetical order, without the ones the
+    /// fallback resolves.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.table.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+fn underflow(operator: &str) -> RpnError {
+    RpnError::StackUnderflow {
+        operator: operator.to_string(),
+    }
+}
+
+fn arithmetic(operator: &str) -> impl Fn(ArithError) -> RpnError + '_ {
+    move |error| RpnError::Arithmetic {
+        operator: operator.to_string(),
+        error,
+    }
+}
+
+pub fn apply1<N, F>(stack: &mut Vec<N>, operator: &str, fun: F) -> Result<(), RpnError>
+where
+    F: Fn(N) -> Result<N, ArithError>,
+{
+    let x = stack.pop().ok_or_else(|| underflow(operator))?;
+    stack.push(fun(x).map_err(arithmetic(operator))?);
+    Ok(())
+}
+
+pub fn apply2<N, F>(stack: &mut Vec<N>, operator: &str, fun: F) -> Result<(), RpnError>
+where
+    F: Fn(N, N) -> Result<N, ArithError>,
+{
+    if stack.len() < 2 {
+        return Err(underflow(operator));
+    }
+    let y = stack.pop().unwrap();
+    let x = stack.pop().unwrap();
+    stack.push(fun(x, y).map_err(arithmetic(operator))?);
+    Ok(())
+}
+
+pub fn apply_n<N, F>(stack: &mut Vec<N>, operator: &str, n: usize, fun: F) -> Result<(), RpnError>
+where
+    F: Fn(&[N]) -> Result<N, ArithError>,
+{
+    if stack.len() < n {
+        return Err(underflow(operator));
+    }
+    let args = stack.split_off(stack.len() - n);
+    stack.push(fun(&args).map_err(arithmetic(operator))?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Operators;
+    use crate::error::ArithError;
+    use crate::number::Number;
+    use crate::RpnError;
+
+    fn run<N: Number>(ops: &Operators<N>, name: &str, stack: &[N]) -> Result<Vec<N>, RpnError> {
+        let mut stack = stack.to_vec();
+        ops.get(name).unwrap().apply(name, &mut stack)?;
+        Ok(stack)
+    }
+
+    #[test]
+    fn builtin_operators() {
+        let ops = Operators::builtin();
+        assert_eq!(run(&ops, "%", &[7.0, 4.0]), Ok(vec![3.0]));
+        assert_eq!(run(&ops, "^", &[2.0, 10.0]), Ok(vec![1024.0]));
+        assert_eq!(run(&ops, "pow", &[9.0, 0.5]), Ok(vec![3.0]));
+        assert_eq!(run(&ops, "neg", &[1.0, 2.0]), Ok(vec![1.0, -2.0]));
+        assert_eq!(run(&ops, "max", &[1.0, 2.0]), Ok(vec![2.0]));
+        assert_eq!(run(&ops, "sqrt", &[16.0]), Ok(vec![4.0]));
+        assert_eq!(run(&ops, "exp", &[0.0]), Ok(vec![1.0]));
+        assert_eq!(run(&ops, "<", &[1.0, 2.0]), Ok(vec![1.0]));
+        assert_eq!(run(&ops, "=", &[1.0, 2.0]), Ok(vec![0.0]));
+        assert_eq!(run(&ops, ">=", &[2.0, 2.0]), Ok(vec![1.0]));
+    }
+
+    #[test]
+    fn nary_operator_takes_operands_bottom_first() {
+        let mut ops = Operators::new();
+        ops.register_nary("fma", 3, |args: &[f64]| Ok(args[0] * args[1] + args[2]));
+        assert_eq!(run(&ops, "fma", &[9.0, 2.0, 3.0, 4.0]), Ok(vec![9.0, 10.0]));
+        assert_eq!(
+            run(&ops, "fma", &[2.0, 3.0]),
+            Err(RpnError::StackUnderflow {
+                operator: "fma".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn arithmetic_errors_name_the_operator() {
+        let ops = Operators::<i64>::builtin();
+        assert_eq!(
+            run(&ops, "/", &[1, 0]),
+            Err(RpnError::Arithmetic {
+                operator: "/".to_string(),
+                error: ArithError::DivisionByZero,
+            })
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use crate::env::{is_identifier, Env};
+use crate::error::RpnError;
+use crate::eval::{Evaluator, MAX_NESTING};
+use crate::number::Number;
+use crate::ops::Operator;
+use crate::stats::STACK_WIDE_WORDS;
+use crate::token::{tokenize, Span, Token};
+
+enum Op<N> {
+    Push(N),
+    Load(usize),
+    Apply(usize),
+    Dup,
+    Drop,
+    Swap,
+    JumpUnless(usize),
+    Jump(usize),
+}
+
+/// An RPN expression compiled for repeated evaluation.
+///
+/// Tokens are resolved once when compiling and the stack depth is checked for
+/// every path through the program, so running it only fails on arithmetic
+/// errors or unbound variables. User-defined words are expanded inline and
+/// `if ... else ... then` becomes jumps. Loops, `!`, definitions, vectors and
+/// words working on the whole stack are not supported.
+pub struct Program<N> {
+    code: Vec<Op<N>>,
+    operators: Vec<(String, Operator<N>)>,
+    variables: Vec<String>,
+    max_stack: usize,
+}
+
+impl<N: Number> Program<N> {
+    /// Compiles `exp` against the operators and words of `evaluator`.
+    pub fn compile(exp: &str, evaluator: &Evaluator<N>) -> Result<Self, RpnError> {
+        let tokens = tokenize(exp);
+        let mut compiler = Compiler {
+            evaluator,
+            program: Program {
+                code: Vec::new(),
+                operators: Vec::new(),
+                variables: Vec::new(),
+                max_stack: 0,
+            },
+            depth: 0,
+            nesting: 0,
+        };
+        let mut pos = 0;
+        if let Some((offset, word)) = compiler.block(&tokens, &mut pos, 0)? {
+            return Err(RpnError::UnmatchedControl {
+                word: word.to_string(),
+                offset,
+            });
+        }
+        match compiler.depth {
+            0 => Err(RpnError::EmptyStack),
+            1 => Ok(compiler.program),
+            depth => Err(RpnError::LeftoverValues { count: depth - 1 }),
+        }
+    }
+
+    /// Returns the names of the variables the program reads.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Returns the number of instructions.
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// Runs the program. Every variable it reads must be bound in `env`.
+    pub fn run(&self, env: &Env<N>) -> Result<N, RpnError> {
+        let mut variables = Vec::with_capacity(self.variables.len());
+        for name in &self.variables {
+            let value = env
+                .get(name)
+                .ok_or_else(|| RpnError::UndefinedVariable { name: name.clone() })?;
+            variables.push(value);
+        }
+        let mut stack: Vec<N> = Vec::with_capacity(self.max_stack);
+        let mut pc = 0;
+        while pc < self.code.len() {
+            match &self.code[pc] {
+                Op::Push(value) => stack.push(value.clone()),
+                Op::Load(slot) => stack.push(variables[*slot].clone()),
+                Op::Apply(index) => {
+                    let (name, op) = &self.operators[*index];
+                    op.apply(name, &mut stack)?;
+                }
+                Op::Dup => stack.push(stack[stack.len() - 1].clone()),
+                Op::Drop => {
+                    stack.pop();
+                }
+                Op::Swap => {
+                    let len = stack.len();
+                    stack.swap(len - 1, len - 2);
+                }
+                Op::JumpUnless(target) => {
+                    if !stack.pop().unwrap().is_truthy() {
+                        pc = *target;
+                        continue;
+                    }
+                }
+                Op::Jump(target) => {
+                    pc = *target;
+                    continue;
+                }
+            }
+            pc += 1;
+        }
+        Ok(stack.pop().unwrap())
+    }
+}
+
+struct Compiler<'a, N> {
+    evaluator: &'a Evaluator<N>,
+    program: Program<N>,
+    depth: usize,
+    /// The number of `if`s open, limited like the evaluator's blocks.
+    nesting: usize,
+}
+
+impl<'a, N: Number> Compiler<'a, N> {
+    /// Compiles tokens until the end or an `else`/`then`, which is returned.
+    fn block(
+        &mut self,
+        tokens: &[Token<'a>],
+        pos: &mut usize,
+        word_depth: usize,
+    ) -> Result<Option<(usize, &'a str)>, RpnError> {
+        while *pos < tokens.len() {
+            let Token { text, span } = tokens[*pos];
+            let offset = span.start;
+            *pos += 1;
+            let next = tokens.get(*pos).map(|next| next.text);
+            match text {
+                "else" | "then" => return Ok(Some((offset, text))),
+                "if" => self.branch(tokens, pos, offset, word_depth)?,
+                ":" | ";" | "times" | "do" | "loop" | "clear" | "i" | "[" | "]" => {
+                    return Err(not_compilable(text, offset))
+                }
+                _ if STACK_WIDE_WORDS.contains(&text) => return Err(not_compilable(text, offset)),
+                _ if next == Some("map") && text.starts_with('\'') => {
+                    return Err(not_compilable("map", offset))
+                }
+                _ if next == Some("!") && is_identifier(text) => {
+                    return Err(not_compilable("!", offset))
+                }
+                _ if next == Some("@") && is_identifier(text) => {
+                    *pos += 1;
+                    self.load(text);
+                }
+                _ => self.token(text, span, word_depth)?,
+            }
+        }
+        Ok(None)
+    }
+
+    fn branch(
+        &mut self,
+        tokens: &[Token<'a>],
+        pos: &mut usize,
+        offset: usize,
+        word_depth: usize,
+    ) -> Result<(), RpnError> {
+        if self.nesting >= MAX_NESTING {
+            return Err(RpnError::NestingLimit {
+                word: "if".to_string(),
+                depth: MAX_NESTING,
+                offset,
+            });
+        }
+        self.nesting += 1;
+        let result = self.branch_body(tokens, pos, offset, word_depth);
+        self.nesting -= 1;
+        result
+    }
+
+    fn branch_body(
+        &mut self,
+        tokens: &[Token<'a>],
+        pos: &mut usize,
+        offset: usize,
+        word_depth: usize,
+    ) -> Result<(), RpnError> {
+        self.consume("if", 1, 0)?;
+        let start = self.depth;
+        let jump_unless = self.emit(Op::JumpUnless(0));
+        let (else_depth, end) = match self.block(tokens, pos, word_depth)? {
+            Some((_, "else")) => {
+                let jump = self.emit(Op::Jump(0));
+                self.patch(jump_unless);
+                let true_depth = std::mem::replace(&mut self.depth, start);
+                let end = self.block(tokens, pos, word_depth)?;
+                self.patch(jump);
+                (true_depth, end)
+            }
+            end => {
+                self.patch(jump_unless);
+                (start, end)
+            }
+        };
+        match end {
+            Some((_, "then")) if else_depth == self.depth => Ok(()),
+            Some((_, "then")) => Err(RpnError::BranchDepthMismatch { offset }),
+            _ => Err(RpnError::UnmatchedControl {
+                word: "if".to_string(),
+                offset,
+            }),
+        }
+    }
+
+    fn token(&mut self, text: &'a str, span: Span, word_depth: usize) -> Result<(), RpnError> {
+        let evaluator = self.evaluator;
+        let offset = span.start;
+        if let Some(num) = evaluator.parse(text) {
+            self.consume(text, 0, 1)?;
+            self.emit(Op::Push(num));
+        } else if let Some(body) = evaluator.word(text) {
+            if word_depth >= evaluator.max_depth() {
+                return Err(RpnError::RecursionLimit {
+                    word: text.to_string(),
+                    depth: word_depth,
+                });
+            }
+            let body: Vec<Token> = body.iter().map(|text| Token { text, span }).collect();
+            let mut pos = 0;
+            if let Some((offset, word)) = self.block(&body, &mut pos, word_depth + 1)? {
+                return Err(RpnError::UnmatchedControl {
+                    word: word.to_string(),
+                    offset,
+                });
+            }
+        } else if text == "dup" {
+            self.consume(text, 1, 2)?;
+            self.emit(Op::Dup);
+        } else if text == "drop" {
+            self.consume(text, 1, 0)?;
+            self.emit(Op::Drop);
+        } else if text == "swap" {
+            self.consume(text, 2, 2)?;
+            self.emit(Op::Swap);
+        } else if let Some(op) = evaluator.operators().get(text) {
+            self.consume(text, op.arity(), 1)?;
+            let index = match self
+                .program
+                .operators
+                .iter()
+                .position(|(name, _)| name == text)
+            {
+                Some(index) => index,
+                None => {
+                    self.program.operators.push((text.to_string(), op));
+                    self.program.operators.len() - 1
+                }
+            };
+            self.emit(Op::Apply(index));
+        } else if is_identifier(text) {
+            self.load(text);
+        } else {
+            return Err(RpnError::UnknownToken {
+                token: text.to_string(),
+                offset,
+            });
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, name: &str) {
+        let variables = &mut self.program.variables;
+        let slot = match variables.iter().position(|v| v == name) {
+            Some(slot) => slot,
+            None => {
+                variables.push(name.to_string());
+                variables.len() - 1
+            }
+        };
+        self.depth += 1;
+        self.program.max_stack = self.program.max_stack.max(self.depth);
+        self.emit(Op::Load(slot));
+    }
+
+    /// Checks that `operator` finds `pops` values and records the depth after it pushes `pushes`.
+    fn consume(&mut self, operator: &str, pops: usize, pushes: usize) -> Result<(), RpnError> {
+        if self.depth < pops {
+            return Err(RpnError::StackUnderflow {
+                operator: operator.to_string(),
+            });
+        }
+        self.depth = self.depth - pops + pushes;
+        self.program.max_stack = self.program.max_stack.max(self.depth);
+        Ok(())
+    }
+
+    fn emit(&mut self, op: Op<N>) -> usize {
+        self.program.code.push(op);
+        self.program.code.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.program.code.len();
+        match &mut self.program.code[at] {
+            Op::JumpUnless(t) | Op::Jump(t) => *t = target,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+}
+
+fn not_compilable(token: &str, offset: usize) -> RpnError {
+    RpnError::NotCompilable {
+        token: token.to_string(),
+        offset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Program;
+    use crate::eval::MAX_NESTING;
+    use crate::{Env, Evaluator, RpnError};
+
+    #[test]
+    fn matches_the_string_evaluator() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator.define("sq", "dup *").unwrap();
+        let exp = "x sq 3 x * + 1 - x 0 > if sqrt else neg then";
+        let program = evaluator.compile(exp).unwrap();
+        assert_eq!(program.variables(), &["x".to_string()]);
+        for &x in &[-2.0, 0.5, 4.0] {
+            let mut env = Env::new();
+            env.set("x", x);
+            evaluator.set_env(env.clone());
+            assert_eq!(program.run(&env), evaluator.eval(exp));
+        }
+    }
+
+    #[test]
+    fn depth_is_checked_at_compile_time() {
+        let evaluator: Evaluator = Evaluator::new();
+        let compile = |exp| Program::compile(exp, &evaluator).map(|_| ());
+        assert_eq!(
+            compile("1 2 + +"),
+            Err(RpnError::StackUnderflow {
+                operator: "+".to_string(),
+            })
+        );
+        assert_eq!(compile("1 2"), Err(RpnError::LeftoverValues { count: 1 }));
+        assert_eq!(compile(""), Err(RpnError::EmptyStack));
+        assert_eq!(
+            compile("1 if 2 3 else 4 then"),
+            Err(RpnError::BranchDepthMismatch { offset: 2 })
+        );
+        assert_eq!(
+            compile("0 if 2 then"),
+            Err(RpnError::BranchDepthMismatch { offset: 2 })
+        );
+        assert_eq!(compile("2 1 if dup * then"), Ok(()));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let evaluator: Evaluator = Evaluator::new();
+        let nested = |n| format!("1 {}{}", "0 if ".repeat(n), "then ".repeat(n));
+        assert_eq!(
+            evaluator.compile(&nested(10_000)).map(|_| ()),
+            Err(RpnError::NestingLimit {
+                word: "if".to_string(),
+                depth: MAX_NESTING,
+                offset: 5 * MAX_NESTING + 4,
+            })
+        );
+        assert!(evaluator.compile(&nested(100)).is_ok());
+    }
+
+    #[test]
+    fn unsupported_words() {
+        let evaluator: Evaluator = Evaluator::new();
+        assert_eq!(
+            evaluator.compile("1 x !").map(|_| ()),
+            Err(RpnError::NotCompilable {
+                token: "!".to_string(),
+                offset: 2,
+            })
+        );
+        assert_eq!(
+            evaluator.compile("3 times 1 loop").map(|_| ()),
+            Err(RpnError::NotCompilable {
+                token: "times".to_string(),
+                offset: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn unbound_variables_fail_at_run_time() {
+        let evaluator: Evaluator = Evaluator::new();
+        let program = evaluator.compile("rate 2 *").unwrap();
+        assert_eq!(
+            program.run(&Env::new()),
+            Err(RpnError::UndefinedVariable {
+                name: "rate".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use crate::eval::Evaluator;
+use crate::locale::NumberFormat;
+use crate::number::Number;
+use crate::session;
+
+/// Reads lines from `input` and runs them against the persistent stack of `evaluator`.
+///
+/// The stack is printed after each line. A line that fails leaves the stack as it
+/// was before the line and the session goes on; the failing token is marked
+/// with `^`. `.s` shows the stack one level per line, `words` lists the
+/// user-defined words, `undo` and `redo` step through the stacks before and
+/// after each line, `save FILE` and `load FILE` write and restore the
+/// session, and `quit` ends the session.
+pub fn run<N, R, W>(evaluator: &mut Evaluator<N>, input: R, output: W) -> io::Result<()>
+where
+    N: Number,
+    R: BufRead,
+    W: Write,
+{
+    run_with_format(evaluator, &NumberFormat::default(), input, output)
+}
+
+/// Like [`run`], but prints the values in `format`.
+pub fn run_with_format<N, R, W>(
+    evaluator: &mut Evaluator<N>,
+    format: &NumberFormat,
+    input: R,
+    mut output: W,
+) -> io::Result<()>
+where
+    N: Number,
+    R: BufRead,
+    W: Write,
+{
+    write!(output, "> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        match line.trim() {
+            "quit" | "exit" => return Ok(()),
+            ".s" => show_stack(evaluator.stack(), format, &mut output)?,
+            "words" => writeln!(output, "{}", evaluator.words().join(" "))?,
+            "undo" | "redo" => {
+                let done = if line.trim() == "undo" {
+                    evaluator.undo()
+                } else {
+                    evaluator.redo()
+                };
+                if !done {
+                    writeln!(output, "error: nothing to {}", line.trim())?;
+                }
+                writeln!(output, "{}", format_stack_with(evaluator.stack(), format))?;
+            }
+            line if line.starts_with("save ") => {
+                let path = line["save ".len()..].trim();
+                match fs::write(path, session::save(evaluator)) {
+                    Ok(()) => writeln!(output, "saved {}", path)?,
+                    Err(e) => writeln!(output, "error: {}: {}", path, e)?,
+                }
+            }
+            line if line.starts_with("load ") => {
+                let path = line["load ".len()..].trim();
+                let saved = evaluator.stack().to_vec();
+                match fs::read_to_string(path) {
+                    Ok(source) => match session::restore(evaluator, &source) {
+                        Ok(()) if evaluator.stack() != &saved[..] => {
+                            evaluator.history_mut().record(&saved)
+                        }
+                        Ok(()) => {}
+                        Err(e) => writeln!(output, "error: {}: {}", path, e)?,
+                    },
+                    Err(e) => writeln!(output, "error: {}: {}", path, e)?,
+                }
+                writeln!(output, "{}", format_stack_with(evaluator.stack(), format))?;
+            }
+            line => {
+                let saved = evaluator.stack().to_vec();
+                match evaluator.run_spanned(line) {
+                    Ok(()) if evaluator.stack() != &saved[..] => {
+                        evaluator.history_mut().record(&saved)
+                    }
+                    Ok(()) => {}
+                    Err(e) => {
+                        evaluator.set_stack(saved);
+                        writeln!(output, "error: {}", e.render(line))?;
+                    }
+                }
+                writeln!(output, "{}", format_stack_with(evaluator.stack(), format))?;
+            }
+        }
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+    writeln!(output)
+}
+
+/// Formats the stack Forth-style: the depth followed by the values, bottom first.
+pub fn format_stack<N: Number>(stack: &[N]) -> String {
+    format_stack_with(stack, &NumberFormat::default())
+}
+
+/// Like [`format_stack`], but prints the values in `format`.
+pub fn format_stack_with<N: Number>(stack: &[N], format: &NumberFormat) -> String {
+    let mut s = format!("<{}>", stack.len());
+    for value in stack {
+        s.push(' ');
+        s.push_str(&format.display(value, None, false));
+    }
+    s
+}
+
+fn show_stack<N: Number, W: Write>(
+    stack: &[N],
+    format: &NumberFormat,
+    output: &mut W,
+) -> io::Result<()> {
+    if stack.is_empty() {
+        return writeln!(output, "(empty)");
+    }
+    for (i, value) in stack.iter().rev().enumerate() {
+        writeln!(output, "{}: {}", i + 1, format.display(value, None, false))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, run_with_format};
+    use crate::locale::NumberFormat;
+    use crate::Evaluator;
+
+    fn session(input: &str) -> (Evaluator, String) {
+        let mut evaluator: Evaluator = Evaluator::new();
+        let mut output = Vec::new();
+        run(&mut evaluator, input.as_bytes(), &mut output).unwrap();
+        (evaluator, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn stack_persists_between_lines() {
+        let (evaluator, output) = session("3 4\n+\ndup *\n");
+        assert_eq!(evaluator.stack(), &[49.0]);
+        assert!(output.contains("<2> 3 4\n"));
+        assert!(output.contains("<1> 7\n"));
+        assert!(output.contains("<1> 49\n"));
+    }
+
+    #[test]
+    fn failed_line_restores_stack() {
+        let (evaluator, output) = session("1 2\n3 + + + +\n.s\n");
+        assert_eq!(evaluator.stack(), &[1.0, 2.0]);
+        assert!(output.contains("error: Stack underflow: +\n  3 + + + +\n        ^\n<2> 1 2\n"));
+        assert!(output.contains("1: 2\n2: 1\n"));
+    }
+
+    #[test]
+    fn definitions_persist_between_lines() {
+        let (evaluator, output) = session(": sq dup * ;\n3 sq\nwords\n");
+        assert_eq!(evaluator.stack(), &[9.0]);
+        assert!(output.contains("> sq\n"));
+    }
+
+    #[test]
+    fn undo_and_redo_lines() {
+        let (evaluator, output) = session("1 2\n+\n3 +\nundo\nundo\nredo\nundo\nundo\nundo\n");
+        assert_eq!(evaluator.stack(), &[] as &[f64]);
+        assert!(output.contains("> <1> 3\n> <2> 1 2\n> <1> 3\n> <2> 1 2\n> <0>\n"));
+        assert!(output.contains("error: nothing to undo\n<0>\n"));
+        assert_eq!(evaluator.history().redo_stack().count(), 3);
+    }
+
+    #[test]
+    fn save_and_load_sessions() {
+        let path = std::env::temp_dir().join(format!("rpn-repl-{}.session", std::process::id()));
+        let path = path.to_str().unwrap();
+        let (_, output) = session(&format!("1 2\n: sq dup * ;\nsave {}\n", path));
+        assert!(output.contains(&format!("> saved {}\n", path)));
+        let (evaluator, output) = session(&format!("9\nload {}\nsq\nundo\nundo\n", path));
+        std::fs::remove_file(path).unwrap();
+        assert!(output.contains("> <2> 1 2\n> <2> 1 4\n> <2> 1 2\n> <1> 9\n"));
+        assert_eq!(evaluator.stack(), &[9.0]);
+        let (_, output) = session("load /nonexistent/rpn.session\n");
+        assert!(output.contains("error: /nonexistent/rpn.session: "));
+    }
+
+    #[test]
+    fn values_in_a_number_format() {
+        let format: NumberFormat = "comma:group".parse().unwrap();
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator.rewrite_literals(move |token| format.read(token));
+        let mut output = Vec::new();
+        let input = "1.000,5 2\n*\n.s\n";
+        run_with_format(&mut evaluator, &format, input.as_bytes(), &mut output).unwrap();
+        assert_eq!(evaluator.stack(), &[2001.0]);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("> <2> 1.000,5 2\n> <1> 2.001\n> 1: 2.001\n"));
+    }
+
+    #[test]
+    fn quit_ends_session() {
+        let (evaluator, _) = session("1\nquit\n2\n");
+        assert_eq!(evaluator.stack(), &[1.0]);
+    }
+}
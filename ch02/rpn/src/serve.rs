@@ -0,0 +1,356 @@
+//! A JSON-lines protocol for driving the evaluator from another process.
+//!
+//! Each line of input is a request object and gets exactly one response
+//! line, in order:
+//!
+//! ```text
+//! {"id":1,"session":"a","vars":{"x":2},"expr":"x 3 +"}
+//! {"id":1,"ok":true,"result":5,"stack":[5]}
+//! {"id":2,"session":"a","expr":"+"}
+//! {"id":2,"ok":false,"error":{"kind":"stack_underflow","message":"Stack underflow: +","span":[0,1]},"stack":[5]}
+//! ```
+//!
+//! `id` is echoed back as given. `expr` is run after the variables in `vars`
+//! are set, each from a number or a literal in a string. Requests naming a
+//! `session` (a string or a number) share its stack, variables and words;
+//! the others start from a fresh evaluator. `"close":true` forgets the
+//! session after the request. A request that fails, whether on a variable
+//! or in `expr`, leaves the stack, the variables and the words as they were.
+//! Each request may run at most [`MAX_STEPS`] steps, and its blocks nest at
+//! most [`MAX_NESTING`](crate::eval::MAX_NESTING) deep, so no request can hang or crash the server.
+//!
+//! Values are JSON numbers when a number holds them exactly, and otherwise
+//! strings as the evaluator prints them, such as `"2/3"` or `"5 m"`. Errors
+//! carry a stable `kind` (see [`RpnError::kind`]), a message and the byte
+//! span of the failing token in `expr` if there is one. Lines that are not
+//! valid requests get an error of kind `invalid_request`.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::env::is_identifier;
+use crate::error::{Diagnostic, RpnError};
+use crate::eval::Evaluator;
+use crate::json::Json;
+use crate::number::Number;
+
+/// How many steps one request may run.
+pub const MAX_STEPS: u64 = 1_000_000;
+
+/// Answers the requests read from `input` on `output`, creating evaluators
+/// with `new_evaluator`.
+pub fn run<N, F, R, W>(new_evaluator: F, input: R, mut output: W) -> io::Result<()>
+where
+    N: Number,
+    F: Fn() -> Evaluator<N>,
+    R: BufRead,
+    W: Write,
+{
+    let mut sessions: HashMap<String, Evaluator<N>> = HashMap::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match Json::parse(&line) {
+            Ok(request) => respond(&request, &mut sessions, &new_evaluator),
+            Err(e) => failure(Json::Null, invalid_request(&e.to_string()), None),
+        };
+        writeln!(output, "{}", response)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+fn respond<N, F>(
+    request: &Json,
+    sessions: &mut HashMap<String, Evaluator<N>>,
+    new_evaluator: &F,
+) -> Json
+where
+    N: Number,
+    F: Fn() -> Evaluator<N>,
+{
+    let id = request.get("id").cloned().unwrap_or(Json::Null);
+    if !matches!(request, Json::Object(_)) {
+        return failure(id, invalid_request("a request must be an object"), None);
+    }
+    let expr = match request.get("expr") {
+        Some(Json::String(expr)) => expr.as_str(),
+        None => "",
+        Some(_) => return failure(id, invalid_request("`expr` must be a string"), None),
+    };
+    let session = match request.get("session") {
+        Some(Json::String(name)) => Some(name.clone()),
+        Some(Json::Number(n)) => Some(Json::Number(*n).to_string()),
+        None | Some(Json::Null) => None,
+        Some(_) => {
+            return failure(
+                id,
+                invalid_request("`session` must be a string or a number"),
+                None,
+            )
+        }
+    };
+    let close = match request.get("close") {
+        Some(close) => match close.as_bool() {
+            Some(close) => close,
+            None => return failure(id, invalid_request("`close` must be a boolean"), None),
+        },
+        None => false,
+    };
+
+    let mut fresh;
+    let evaluator = match &session {
+        Some(name) => sessions.entry(name.clone()).or_insert_with(new_evaluator),
+        None => {
+            fresh = new_evaluator();
+            &mut fresh
+        }
+    };
+    evaluator.set_max_steps(MAX_STEPS);
+    let saved = evaluator.snapshot();
+    let response = match request.get("vars") {
+        Some(vars) => set_vars(evaluator, vars).and_then(|()| run_expr(evaluator, expr)),
+        None => run_expr(evaluator, expr),
+    };
+    if response.is_err() {
+        evaluator.restore(saved);
+    }
+    let stack = stack(evaluator.stack());
+    if close {
+        if let Some(name) = &session {
+            sessions.remove(name);
+        }
+    }
+    match response {
+        Ok(()) => {
+            let result = match &stack {
+                Json::Array(values) => values.last().cloned().unwrap_or(Json::Null),
+                _ => Json::Null,
+            };
+            Json::Object(vec![
+                ("id".to_string(), id),
+                ("ok".to_string(), Json::Bool(true)),
+                ("result".to_string(), result),
+                ("stack".to_string(), stack),
+            ])
+        }
+        Err(error) => failure(id, error, Some(stack)),
+    }
+}
+
+fn set_vars<N: Number>(evaluator: &mut Evaluator<N>, vars: &Json) -> Result<(), Json> {
+    let vars = match vars {
+        Json::Object(vars) => vars,
+        _ => return Err(invalid_request("`vars` must be an object")),
+    };
+    let mut values = Vec::with_capacity(vars.len());
+    for (name, value) in vars {
+        if !is_identifier(name) {
+            return Err(invalid_request(&format!("invalid variable name: {}", name)));
+        }
+        if evaluator.env().is_constant(name) {
+            let error = RpnError::ConstantAssignment {
+                name: name.to_string(),
+            };
+            return Err(error_object(&error.into()));
+        }
+        let value = match value {
+            Json::Number(x) => N::from_f64(*x),
+            Json::String(literal) => evaluator.parse(literal),
+            _ => None,
+        }
+        .ok_or_else(|| invalid_request(&format!("invalid value for {}", name)))?;
+        values.push((name, value));
+    }
+    for (name, value) in values {
+        evaluator.env_mut().set(name, value);
+    }
+    Ok(())
+}
+
+fn run_expr<N: Number>(evaluator: &mut Evaluator<N>, expr: &str) -> Result<(), Json> {
+    evaluator.run_spanned(expr).map_err(|e| error_object(&e))
+}
+
+/// Writes a value as a number if that is exact, and as text otherwise.
+fn value<N: Number>(x: &N) -> Json {
+    let f = x.to_f64();
+    if f.is_finite() && N::from_f64(f).as_ref() == Some(x) {
+        Json::Number(f)
+    } else {
+        Json::String(x.to_string())
+    }
+}
+
+fn stack<N: Number>(stack: &[N]) -> Json {
+    Json::Array(stack.iter().map(value).collect())
+}
+
+fn error_object(e: &Diagnostic) -> Json {
+    let mut members = vec![
+        ("kind".to_string(), Json::from(e.error.kind())),
+        ("message".to_string(), Json::String(e.error.to_string())),
+    ];
+    if let Some(span) = e.span {
+        let span = vec![
+            Json::Number(span.start as f64),
+            Json::Number(span.end as f64),
+        ];
+        members.push(("span".to_string(), Json::Array(span)));
+    }
+    Json::Object(members)
+}
+
+fn invalid_request(message: &str) -> Json {
+    Json::Object(vec![
+        ("kind".to_string(), Json::from("invalid_request")),
+        ("message".to_string(), Json::from(message)),
+    ])
+}
+
+fn failure(id: Json, error: Json, stack: Option<Json>) -> Json {
+    let mut members = vec![
+        ("id".to_string(), id),
+        ("ok".to_string(), Json::Bool(false)),
+        ("error".to_string(), error),
+    ];
+    if let Some(stack) = stack {
+        members.push(("stack".to_string(), stack));
+    }
+    Json::Object(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use crate::number::Rational;
+    use crate::{Evaluator, Units};
+
+    fn serve(input: &str) -> Vec<String> {
+        let mut output = Vec::new();
+        run(Evaluator::<f64>::new, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn answers_each_request() {
+        let responses = serve(concat!(
+            "{\"id\":1,\"expr\":\"3 4 +\"}\n",
+            "\n",
+            "{\"id\":\"b\",\"expr\":\"x 2 *\",\"vars\":{\"x\":1.5}}\n",
+            "{\"expr\":\"1 0 /\"}\n",
+        ));
+        assert_eq!(
+            responses,
+            vec![
+                r#"{"id":1,"ok":true,"result":7,"stack":[7]}"#,
+                r#"{"id":"b","ok":true,"result":3,"stack":[3]}"#,
+                r#"{"id":null,"ok":true,"result":"inf","stack":["inf"]}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn sessions_keep_their_state() {
+        let responses = serve(concat!(
+            "{\"id\":1,\"session\":\"a\",\"expr\":\": sq dup * ; 3\"}\n",
+            "{\"id\":2,\"session\":7,\"expr\":\"1\"}\n",
+            "{\"id\":3,\"session\":\"a\",\"expr\":\"sq + +\"}\n",
+            "{\"id\":4,\"session\":\"a\",\"expr\":\"sq\",\"close\":true}\n",
+            "{\"id\":5,\"session\":\"a\",\"expr\":\"sq\"}\n",
+            "{\"id\":6,\"expr\":\"sq\"}\n",
+        ));
+        assert_eq!(responses[0], r#"{"id":1,"ok":true,"result":3,"stack":[3]}"#);
+        assert_eq!(responses[1], r#"{"id":2,"ok":true,"result":1,"stack":[1]}"#);
+        assert_eq!(
+            responses[2],
+            r#"{"id":3,"ok":false,"error":{"kind":"stack_underflow","message":"Stack underflow: +","span":[3,4]},"stack":[3]}"#
+        );
+        assert_eq!(responses[3], r#"{"id":4,"ok":true,"result":9,"stack":[9]}"#);
+        for response in &responses[4..] {
+            assert!(response.contains(
+                r#""kind":"unknown_token","message":"Unknown operator: sq (at byte 0)","span":[0,2]"#
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let responses = serve(concat!(
+            "not json\n",
+            "[1]\n",
+            "{\"id\":1,\"expr\":3}\n",
+            "{\"id\":2,\"expr\":\"pi\",\"vars\":{\"pi\":3}}\n",
+            "{\"id\":3,\"expr\":\"x\",\"vars\":{\"x\":true}}\n",
+        ));
+        assert_eq!(
+            responses[0],
+            r#"{"id":null,"ok":false,"error":{"kind":"invalid_request","message":"expected a value (at byte 0)"}}"#
+        );
+        assert!(responses[1].contains("a request must be an object"));
+        assert!(responses[2].contains("`expr` must be a string"));
+        assert!(responses[3].contains(r#""kind":"constant_assignment""#));
+        assert!(responses[4].contains("invalid value for x"));
+    }
+
+    #[test]
+    fn failed_requests_change_nothing() {
+        let responses = serve(concat!(
+            "{\"id\":1,\"session\":\"a\",\"expr\":\"1\"}\n",
+            "{\"id\":2,\"session\":\"a\",\"vars\":{\"y\":1,\"pi\":2}}\n",
+            "{\"id\":3,\"session\":\"a\",\"vars\":{\"y\":1},\"expr\":\"2 z ! +\"}\n",
+            "{\"id\":4,\"session\":\"a\",\"expr\":\"y\"}\n",
+            "{\"id\":5,\"session\":\"a\",\"expr\":\"z\"}\n",
+        ));
+        assert!(responses[1].contains(r#""kind":"constant_assignment""#));
+        assert!(responses[2].contains(r#""kind":"stack_underflow""#));
+        assert!(responses[2].ends_with(r#""stack":[1]}"#));
+        assert!(responses[3].contains("Unknown operator: y"));
+        assert!(responses[4].contains("Unknown operator: z"));
+    }
+
+    #[test]
+    fn runaway_requests_fail_alone() {
+        let deep = format!("{}1{}", "1 if ".repeat(10_000), " then".repeat(10_000));
+        let responses = serve(&format!(
+            "{}\n{{\"session\":\"a\",\"expr\":\"{}\"}}\n{}\n{}\n",
+            "{\"session\":\"a\",\"expr\":\"1\"}",
+            deep,
+            "{\"session\":\"a\",\"expr\":\": f 2 ; 1 1e15 times 1 + loop\"}",
+            "{\"session\":\"a\",\"expr\":\"f\"}",
+        ));
+        assert!(responses[1].contains(r#""kind":"nesting_limit""#));
+        assert!(responses[1].ends_with(r#""stack":[1]}"#));
+        assert!(responses[2].contains(r#""kind":"step_limit""#));
+        assert!(responses[2].ends_with(r#""stack":[1]}"#));
+        assert!(responses[3].contains("Unknown operator: f"));
+    }
+
+    #[test]
+    fn inexact_values_are_strings() {
+        let mut output = Vec::new();
+        let input = "{\"expr\":\"2 3 / 4\",\"vars\":{\"y\":\"1/2\"}}\n";
+        run(Evaluator::<Rational>::new, input.as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"id\":null,\"ok\":true,\"result\":4,\"stack\":[\"2/3\",4]}\n"
+        );
+        let mut output = Vec::new();
+        let input = "{\"expr\":\"5km\"}\n";
+        run(
+            || Units::builtin().evaluator(),
+            input.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("\"stack\":[\"5 km\"]"));
+    }
+}
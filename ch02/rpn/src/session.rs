@@ -0,0 +1,273 @@
+//! Saving and restoring the state of an evaluator as text.
+//!
+//! A session file starts with `rpn session VERSION` and then holds one entry
+//! per line, each written in RPN so that the file can be read and edited by
+//! hand:
+//!
+//! ```text
+//! rpn session 1
+//! stack 1 2 [3 4]
+//! const g 9.81
+//! var x 5
+//! : sq dup * ;
+//! ```
+//!
+//! Blank lines and everything after `#` are ignored. Files of any version up
+//! to [`VERSION`] can be restored.
+
+use crate::env::{is_identifier, Env};
+use crate::error::{RpnError, SessionError};
+use crate::eval::Evaluator;
+use crate::number::Number;
+
+/// The version [`save`] writes.
+pub const VERSION: u32 = 1;
+
+const HEADER: &str = "rpn session";
+
+/// Writes the stack, the variables, the constants other than the ones every
+/// evaluator starts with, and the user-defined words of `evaluator`.
+pub fn save<N: Number>(evaluator: &Evaluator<N>) -> String {
+    let mut out = format!("{} {}\n", HEADER, VERSION);
+    if !evaluator.stack().is_empty() {
+        out.push_str("stack");
+        for value in evaluator.stack() {
+            out.push(' ');
+            out.push_str(&value.to_source());
+        }
+        out.push('\n');
+    }
+    let defaults = Env::<N>::new();
+    let env = evaluator.env();
+    for (name, value) in env.iter() {
+        if !env.is_constant(name) {
+            out.push_str(&format!("var {} {}\n", name, value.to_source()));
+        } else if !defaults.is_constant(name) || defaults.get(name) != Some(value) {
+            out.push_str(&format!("const {} {}\n", name, value.to_source()));
+        }
+    }
+    for name in evaluator.words() {
+        let body = evaluator.word(name).unwrap_or_default();
+        match body.len() {
+            0 => out.push_str(&format!(": {} ;\n", name)),
+            _ => out.push_str(&format!(": {} {} ;\n", name, body.join(" "))),
+        }
+    }
+    out
+}
+
+/// Restores a session written by [`save`] into `evaluator`. The stack is
+/// replaced, while variables and words are added to the ones it has.
+///
+/// Nothing changes if the file is malformed.
+pub fn restore<N: Number>(evaluator: &mut Evaluator<N>, source: &str) -> Result<(), SessionError> {
+    let mut lines = source
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty());
+    let version = lines
+        .next()
+        .and_then(|(_, line)| line.strip_prefix(HEADER))
+        .and_then(|version| version.trim().parse().ok())
+        .ok_or(SessionError::MissingHeader)?;
+    if version == 0 || version > VERSION {
+        return Err(SessionError::UnsupportedVersion { version });
+    }
+
+    let mut stack = Vec::new();
+    let mut entries = Vec::new();
+    let mut definitions = Vec::new();
+    for (number, line) in lines {
+        let invalid = |error| SessionError::InvalidEntry {
+            line: number + 1,
+            error,
+        };
+        let (kind, rest) = line.split_at(line.find(' ').unwrap_or(line.len()));
+        match kind {
+            "stack" => stack = values(evaluator, rest).map_err(invalid)?,
+            "var" | "const" => {
+                let rest = rest.trim_start();
+                let (name, value) = rest.split_at(rest.find(' ').unwrap_or(rest.len()));
+                if !is_identifier(name) {
+                    return Err(invalid(RpnError::UnknownToken {
+                        token: name.to_string(),
+                        offset: line.len() - rest.len(),
+                    }));
+                }
+                let constant = kind == "const";
+                if !constant && evaluator.env().is_constant(name) {
+                    return Err(invalid(RpnError::ConstantAssignment {
+                        name: name.to_string(),
+                    }));
+                }
+                let value = match values(evaluator, value).map_err(invalid)?.as_slice() {
+                    [value] => value.clone(),
+                    [] => return Err(invalid(RpnError::EmptyStack)),
+                    values => {
+                        return Err(invalid(RpnError::LeftoverValues {
+                            count: values.len() - 1,
+                        }))
+                    }
+                };
+                entries.push((name.to_string(), value, constant));
+            }
+            ":" => {
+                Evaluator::<N>::empty().run(line).map_err(invalid)?;
+                definitions.push((number, line));
+            }
+            _ => {
+                return Err(SessionError::UnknownEntry {
+                    line: number + 1,
+                    entry: kind.to_string(),
+                })
+            }
+        }
+    }
+
+    for (number, line) in definitions {
+        evaluator
+            .run(line)
+            .map_err(|error| SessionError::InvalidEntry {
+                line: number + 1,
+                error,
+            })?;
+    }
+    for (name, value, constant) in entries {
+        if constant {
+            evaluator.env_mut().define_constant(&name, value);
+        } else {
+            evaluator.env_mut().set(&name, value);
+        }
+    }
+    evaluator.set_stack(stack);
+    Ok(())
+}
+
+/// Runs `source` in a scratch copy of `evaluator` and returns what it leaves,
+/// so that nothing `source` does reaches `evaluator` itself.
+fn values<N: Number>(evaluator: &Evaluator<N>, source: &str) -> Result<Vec<N>, RpnError> {
+    let mut scratch = evaluator.scratch();
+    scratch.run(source)?;
+    Ok(scratch.stack().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{restore, save};
+    use crate::error::{RpnError, SessionError};
+    use crate::units::Units;
+    use crate::value::Value;
+    use crate::Evaluator;
+
+    #[test]
+    fn round_trip() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator
+            .run("1 2.5 -0.125 1 0 / 7 x ! : sq dup * ; : nop ;")
+            .unwrap();
+        evaluator.env_mut().define_constant("g", 9.81);
+        let saved = save(&evaluator);
+        assert_eq!(
+            saved,
+            "rpn session 1\nstack 1 2.5 -0.125 inf\nconst g 9.81\nvar x 7\n: nop ;\n: sq dup * ;\n"
+        );
+        let mut restored: Evaluator = Evaluator::new();
+        restored.push(42.0);
+        restore(&mut restored, &saved).unwrap();
+        assert_eq!(restored.stack(), evaluator.stack());
+        assert_eq!(restored.env(), evaluator.env());
+        assert_eq!(restored.words(), vec!["nop", "sq"]);
+        restored.clear();
+        assert_eq!(restored.eval("3 sq"), Ok(9.0));
+    }
+
+    #[test]
+    fn values_that_are_not_plain_literals() {
+        let mut evaluator = Evaluator::with_operators(Value::operators());
+        evaluator.run("[1 2] 3+4i [5 [6]] v !").unwrap();
+        let saved = save(&evaluator);
+        assert_eq!(saved, "rpn session 1\nstack [1 2] 3+4i\nvar v [5 [6]]\n");
+        let mut restored = Evaluator::with_operators(Value::operators());
+        restore(&mut restored, &saved).unwrap();
+        assert_eq!(restored.stack(), evaluator.stack());
+        assert_eq!(restored.env(), evaluator.env());
+
+        let mut evaluator = Units::builtin().evaluator();
+        evaluator.run("9.8m/s^2 2kg * 3km").unwrap();
+        let saved = save(&evaluator);
+        assert_eq!(saved, "rpn session 1\nstack 19.6kg*m/s^2 3km\n");
+        let mut restored = Units::builtin().evaluator();
+        restore(&mut restored, &saved).unwrap();
+        assert_eq!(restored.stack(), evaluator.stack());
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_ignored() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        restore(
+            &mut evaluator,
+            "# saved by hand\n\nrpn session 1\nstack 1 2 # two values\n",
+        )
+        .unwrap();
+        assert_eq!(evaluator.stack(), &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn malformed_files_change_nothing() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator.push(1.0);
+        let mut restore = |source| {
+            let result = restore(&mut evaluator, source);
+            assert_eq!(evaluator.stack(), &[1.0]);
+            assert_eq!(evaluator.words(), Vec::<&str>::new());
+            assert_eq!(evaluator.env().get("x"), None);
+            result
+        };
+        assert_eq!(restore("stack 2\n"), Err(SessionError::MissingHeader));
+        assert_eq!(
+            restore("rpn session 2\n"),
+            Err(SessionError::UnsupportedVersion { version: 2 })
+        );
+        assert_eq!(
+            restore("rpn session 1\n: sq dup * ;\nstack 2\nvar x 1 2\n"),
+            Err(SessionError::InvalidEntry {
+                line: 4,
+                error: RpnError::LeftoverValues { count: 1 },
+            })
+        );
+        assert_eq!(
+            restore("rpn session 1\nvar x 1\n: 2 dup ;\n"),
+            Err(SessionError::InvalidEntry {
+                line: 3,
+                error: RpnError::InvalidWordName {
+                    name: "2".to_string(),
+                    offset: 2,
+                },
+            })
+        );
+        assert_eq!(
+            restore("rpn session 1\nvar pi 3\n"),
+            Err(SessionError::InvalidEntry {
+                line: 2,
+                error: RpnError::ConstantAssignment {
+                    name: "pi".to_string(),
+                },
+            })
+        );
+        assert_eq!(
+            restore("rpn session 1\nstack 1 x !\nvar z 1 2\n"),
+            Err(SessionError::InvalidEntry {
+                line: 3,
+                error: RpnError::LeftoverValues { count: 1 },
+            })
+        );
+        assert_eq!(
+            restore("rpn session 1\nstack 1\nhistory 2\n"),
+            Err(SessionError::UnknownEntry {
+                line: 3,
+                entry: "history".to_string(),
+            })
+        );
+    }
+}
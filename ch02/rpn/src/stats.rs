@@ -0,0 +1,95 @@
+use std::cmp::Ordering;
+
+use crate::error::ArithError;
+use crate::number::Number;
+
+/// The words that take every value on the stack, or the elements of a vector
+/// on top of it, plus `roll` and `pick` which reach below the top.
+pub(crate) const STACK_WIDE_WORDS: &[&str] = &[
+    "sum", "prod", "mean", "median", "stddev", "count", "sort", "roll", "pick",
+];
+
+pub fn sum<N: Number>(values: &[N]) -> Result<N, ArithError> {
+    values.iter().try_fold(N::from_i64(0), |sum, x| sum.add(x))
+}
+
+pub fn prod<N: Number>(values: &[N]) -> Result<N, ArithError> {
+    values
+        .iter()
+        .try_fold(N::from_i64(1), |prod, x| prod.mul(x))
+}
+
+pub fn mean<N: Number>(values: &[N]) -> Result<N, ArithError> {
+    sum(values)?.div(&N::from_i64(values.len() as i64))
+}
+
+/// The middle value, or the mean of the two middle values of an even count.
+pub fn median<N: Number>(values: &[N]) -> Result<N, ArithError> {
+    let sorted = sort(values)?;
+    let mid = sorted.len() / 2;
+    if sorted.len() % 2 == 1 {
+        Ok(sorted[mid].clone())
+    } else {
+        mean(&sorted[mid - 1..=mid])
+    }
+}
+
+/// The sample standard deviation, which needs at least two values.
+pub fn stddev<N: Number>(values: &[N]) -> Result<N, ArithError> {
+    let mean = mean(values)?;
+    let mut squares = N::from_i64(0);
+    for x in values {
+        let d = x.sub(&mean)?;
+        squares = squares.add(&d.mul(&d)?)?;
+    }
+    squares
+        .div(&N::from_i64(values.len() as i64 - 1))?
+        .map_f64(f64::sqrt)
+}
+
+/// Sorts ascending, failing on values that cannot be ordered such as NaN.
+pub fn sort<N: Number>(values: &[N]) -> Result<Vec<N>, ArithError> {
+    let mut sorted = values.to_vec();
+    let mut unordered = false;
+    sorted.sort_by(|x, y| {
+        x.partial_cmp(y).unwrap_or_else(|| {
+            unordered = true;
+            Ordering::Equal
+        })
+    });
+    if unordered {
+        Err(ArithError::TypeMismatch)
+    } else {
+        Ok(sorted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mean, median, prod, sort, stddev, sum};
+    use crate::error::ArithError;
+    use crate::number::{Number, Rational};
+
+    #[test]
+    fn aggregates() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(sum(&values), Ok(40.0));
+        assert_eq!(prod(&[2.0, 3.0, 4.0]), Ok(24.0));
+        assert_eq!(mean(&values), Ok(5.0));
+        assert_eq!(median(&values), Ok(4.5));
+        assert_eq!(median(&[3.0, 1.0, 2.0]), Ok(2.0));
+        assert_eq!(stddev(&[1.0, 3.0]), Ok(2f64.sqrt()));
+        assert_eq!(sort(&[3.0, 1.0, 2.0]), Ok(vec![1.0, 2.0, 3.0]));
+        assert_eq!(sort(&[1.0, f64::NAN]), Err(ArithError::TypeMismatch));
+    }
+
+    #[test]
+    fn exact_types_stay_exact() {
+        let values: Vec<Rational> = ["1", "2", "2"]
+            .iter()
+            .map(|s| Rational::parse(s).unwrap())
+            .collect();
+        assert_eq!(mean(&values).unwrap().to_string(), "5/3");
+        assert_eq!(median(&values[..2]).unwrap().to_string(), "3/2");
+    }
+}
@@ -0,0 +1,563 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::env::is_identifier;
+use crate::error::ArithError;
+use crate::infix;
+use crate::number::Number;
+use crate::ops::{Operator, Operators};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Abs,
+    Sqrt,
+    Ln,
+    Exp,
+    Sin,
+    Cos,
+    Tan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+}
+
+const UNARY_OPS: &[UnaryOp] = &[
+    UnaryOp::Neg,
+    UnaryOp::Abs,
+    UnaryOp::Sqrt,
+    UnaryOp::Ln,
+    UnaryOp::Exp,
+    UnaryOp::Sin,
+    UnaryOp::Cos,
+    UnaryOp::Tan,
+];
+
+impl UnaryOp {
+    /// The RPN word for the operation.
+    pub fn name(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "neg",
+            UnaryOp::Abs => "abs",
+            UnaryOp::Sqrt => "sqrt",
+            UnaryOp::Ln => "ln",
+            UnaryOp::Exp => "exp",
+            UnaryOp::Sin => "sin",
+            UnaryOp::Cos => "cos",
+            UnaryOp::Tan => "tan",
+        }
+    }
+
+    fn apply(self, x: f64) -> f64 {
+        match self {
+            UnaryOp::Neg => -x,
+            UnaryOp::Abs => x.abs(),
+            UnaryOp::Sqrt => x.sqrt(),
+            UnaryOp::Ln => x.ln(),
+            UnaryOp::Exp => x.exp(),
+            UnaryOp::Sin => x.sin(),
+            UnaryOp::Cos => x.cos(),
+            UnaryOp::Tan => x.tan(),
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn name(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Pow => "^",
+        }
+    }
+
+    fn apply(self, x: f64, y: f64) -> f64 {
+        match self {
+            BinaryOp::Add => x + y,
+            BinaryOp::Sub => x - y,
+            BinaryOp::Mul => x * y,
+            BinaryOp::Div => x / y,
+            BinaryOp::Rem => x % y,
+            BinaryOp::Pow => x.powf(y),
+        }
+    }
+}
+
+/// An expression tree for symbolic mode, built by running RPN on symbols.
+///
+/// Names that are neither words, operators nor variables push themselves as
+/// symbols, except `i` which stays the loop index, so `x 2 * 3 +` builds
+/// `2 * x + 3`.
+/// Every node is simplified as it is built: constants are folded and
+/// identities such as `x + 0`, `x * 1` and `x ^ 1` removed. `d/dx`
+/// differentiates with respect to `x`, and likewise for any other name.
+///
+/// Results print in infix, or in RPN with the `#` flag (`{:#}`). Only
+/// constants are ordered, so comparing symbolic expressions is false.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Num(f64),
+    Sym(Rc<str>),
+    Unary(UnaryOp, Rc<Node>),
+    Binary(BinaryOp, Rc<Node>, Rc<Node>),
+}
+
+/// How deep symbolic operations may nest an expression tree; deeper trees
+/// fail with [`ArithError::Overflow`], as walking them would exhaust the stack.
+pub const MAX_DEPTH: usize = 256;
+
+/// An operand of an [`Expr`], knowing the depth of its tree so that the
+/// depth of a new node is found without walking it.
+#[derive(Debug, Clone)]
+pub struct Node {
+    expr: Expr,
+    depth: usize,
+}
+
+impl Node {
+    fn new(expr: Expr) -> Rc<Node> {
+        let depth = expr.depth();
+        Rc::new(Node { expr, depth })
+    }
+}
+
+impl Deref for Node {
+    type Target = Expr;
+
+    fn deref(&self) -> &Expr {
+        &self.expr
+    }
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.expr == other.expr
+    }
+}
+
+impl Expr {
+    pub fn symbol(name: &str) -> Self {
+        Expr::Sym(name.into())
+    }
+
+    /// Applies `op`, simplifying the result.
+    pub fn unary(op: UnaryOp, x: Expr) -> Self {
+        match (op, &x) {
+            (_, Expr::Num(x)) => Expr::Num(op.apply(*x)),
+            (UnaryOp::Neg, Expr::Unary(UnaryOp::Neg, x)) => x.expr.clone(),
+            (UnaryOp::Abs, Expr::Unary(UnaryOp::Abs, _)) => x,
+            (UnaryOp::Ln, Expr::Unary(UnaryOp::Exp, x)) => x.expr.clone(),
+            _ => Expr::Unary(op, Node::new(x)),
+        }
+    }
+
+    /// Applies `op`, simplifying the result.
+    pub fn binary(op: BinaryOp, x: Expr, y: Expr) -> Self {
+        use self::BinaryOp::*;
+        match (op, x.constant(), y.constant()) {
+            (_, Some(a), Some(b)) => Expr::Num(op.apply(a, b)),
+            (Add, Some(0.0), _) => y,
+            (Add | Sub, _, Some(0.0)) => x,
+            (Sub, Some(0.0), _) => Expr::unary(UnaryOp::Neg, y),
+            (Sub, _, _) if x == y => Expr::Num(0.0),
+            (Mul, Some(0.0), _) | (Mul, _, Some(0.0)) => Expr::Num(0.0),
+            (Mul, Some(1.0), _) => y,
+            (Mul | Div | Pow, _, Some(1.0)) => x,
+            (Mul, Some(-1.0), _) => Expr::unary(UnaryOp::Neg, y),
+            // Constants go first, and are merged with a constant factor.
+            (Mul, None, Some(_)) => Expr::binary(Mul, y, x),
+            (Mul, Some(a), None) => match &y {
+                Expr::Binary(Mul, b, z) if b.constant().is_some() => {
+                    Expr::binary(Mul, Expr::Num(a * b.constant().unwrap()), z.expr.clone())
+                }
+                _ => Expr::Binary(Mul, Node::new(x), Node::new(y)),
+            },
+            (Div, Some(0.0), _) => Expr::Num(0.0),
+            (Div, _, _) if x == y => Expr::Num(1.0),
+            (Pow, _, Some(0.0)) | (Pow, Some(1.0), _) => Expr::Num(1.0),
+            _ => Expr::Binary(op, Node::new(x), Node::new(y)),
+        }
+    }
+
+    /// Returns the value if the expression is a constant.
+    pub fn constant(&self) -> Option<f64> {
+        match self {
+            Expr::Num(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// The number of operations between the root and the deepest leaf.
+    pub fn depth(&self) -> usize {
+        match self {
+            Expr::Num(_) | Expr::Sym(_) => 0,
+            Expr::Unary(_, x) => x.depth + 1,
+            Expr::Binary(_, x, y) => x.depth.max(y.depth) + 1,
+        }
+    }
+
+    /// Fails for trees deeper than [`MAX_DEPTH`].
+    fn checked(self) -> Result<Expr, ArithError> {
+        if self.depth() > MAX_DEPTH {
+            return Err(ArithError::Overflow);
+        }
+        Ok(self)
+    }
+
+    /// Tells whether `name` occurs in the expression.
+    pub fn contains(&self, name: &str) -> bool {
+        match self {
+            Expr::Num(_) => false,
+            Expr::Sym(sym) => &**sym == name,
+            Expr::Unary(_, x) => x.contains(name),
+            Expr::Binary(_, x, y) => x.contains(name) || y.contains(name),
+        }
+    }
+
+    /// Rebuilds the tree bottom up so that every node is simplified.
+    pub fn simplify(&self) -> Expr {
+        match self {
+            Expr::Num(_) | Expr::Sym(_) => self.clone(),
+            Expr::Unary(op, x) => Expr::unary(*op, x.simplify()),
+            Expr::Binary(op, x, y) => Expr::binary(*op, x.simplify(), y.simplify()),
+        }
+    }
+
+    /// Differentiates with respect to `name`, failing for `%` of it.
+    pub fn derivative(&self, name: &str) -> Result<Expr, ArithError> {
+        use self::BinaryOp::*;
+        let num = Expr::Num;
+        let (op, u, v) = match self {
+            Expr::Num(_) => return Ok(num(0.0)),
+            Expr::Sym(sym) => return Ok(num(if &**sym == name { 1.0 } else { 0.0 })),
+            Expr::Unary(op, u) => {
+                let du = u.derivative(name)?;
+                let u = u.expr.clone();
+                let outer = match op {
+                    UnaryOp::Neg => return Ok(Expr::unary(UnaryOp::Neg, du)),
+                    UnaryOp::Abs => Expr::binary(Div, u.clone(), self.clone()),
+                    UnaryOp::Sqrt => Expr::binary(Div, num(0.5), self.clone()),
+                    UnaryOp::Ln => Expr::binary(Div, num(1.0), u),
+                    UnaryOp::Exp => self.clone(),
+                    UnaryOp::Sin => Expr::unary(UnaryOp::Cos, u),
+                    UnaryOp::Cos => Expr::unary(UnaryOp::Neg, Expr::unary(UnaryOp::Sin, u)),
+                    UnaryOp::Tan => Expr::binary(
+                        Div,
+                        num(1.0),
+                        Expr::binary(Pow, Expr::unary(UnaryOp::Cos, u), num(2.0)),
+                    ),
+                };
+                return Ok(Expr::binary(Mul, outer, du));
+            }
+            Expr::Binary(op, u, v) => (*op, u.expr.clone(), v.expr.clone()),
+        };
+        let (du, dv) = (u.derivative(name)?, v.derivative(name)?);
+        Ok(match op {
+            Add => Expr::binary(Add, du, dv),
+            Sub => Expr::binary(Sub, du, dv),
+            Mul => Expr::binary(
+                Add,
+                Expr::binary(Mul, du, v.clone()),
+                Expr::binary(Mul, u, dv),
+            ),
+            Div => Expr::binary(
+                Div,
+                Expr::binary(
+                    Sub,
+                    Expr::binary(Mul, du, v.clone()),
+                    Expr::binary(Mul, u, dv),
+                ),
+                Expr::binary(Pow, v, num(2.0)),
+            ),
+            Rem if !v.contains(name) => du,
+            Rem => return Err(ArithError::NotRepresentable),
+            Pow if !v.contains(name) => Expr::binary(
+                Mul,
+                Expr::binary(
+                    Mul,
+                    v.clone(),
+                    Expr::binary(Pow, u, Expr::binary(Sub, v, num(1.0))),
+                ),
+                du,
+            ),
+            // d(u^v) = u^v * (v' ln u + v u' / u)
+            Pow => Expr::binary(
+                Mul,
+                self.clone(),
+                Expr::binary(
+                    Add,
+                    Expr::binary(Mul, dv, Expr::unary(UnaryOp::Ln, u.clone())),
+                    Expr::binary(Div, Expr::binary(Mul, v, du), u),
+                ),
+            ),
+        })
+    }
+
+    /// Writes the expression in RPN.
+    pub fn to_rpn(&self) -> String {
+        match self {
+            Expr::Num(x) => x.to_string(),
+            Expr::Sym(name) => name.to_string(),
+            Expr::Unary(op, x) => format!("{} {}", x.to_rpn(), op.name()),
+            Expr::Binary(op, x, y) => format!("{} {} {}", x.to_rpn(), y.to_rpn(), op.name()),
+        }
+    }
+
+    /// Writes the expression in infix with as few parentheses as needed.
+    pub fn to_infix(&self) -> String {
+        let rpn = self.to_rpn();
+        infix::to_infix(&rpn).unwrap_or(rpn)
+    }
+
+    /// Creates a table with the builtin operators building expressions, the
+    /// `d/dNAME` derivatives, and every other name as a symbol.
+    pub fn operators() -> Operators<Expr> {
+        let mut ops: Operators<Expr> = Operators::builtin();
+        for &op in UNARY_OPS {
+            ops.register_unary(op.name(), move |x| Expr::unary(op, x).checked());
+        }
+        ops.set_fallback(|name| {
+            if let Some(var) = name.strip_prefix("d/d").filter(|var| is_identifier(var)) {
+                let var: Rc<str> = var.into();
+                return Some(Operator::Unary(Rc::new(move |x: Expr| {
+                    x.derivative(&var)?.checked()
+                })));
+            }
+            if !is_identifier(name) || name == "i" {
+                return None;
+            }
+            let symbol = Expr::symbol(name);
+            Some(Operator::Nary(0, Rc::new(move |_| Ok(symbol.clone()))))
+        });
+        ops
+    }
+}
+
+/// Compares trees, taking shared subtrees as equal without walking them
+/// since `dup` makes them common.
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        let same = |x: &Rc<Node>, y: &Rc<Node>| Rc::ptr_eq(x, y) || x == y;
+        match (self, other) {
+            (Expr::Num(x), Expr::Num(y)) => x == y,
+            (Expr::Sym(x), Expr::Sym(y)) => x == y,
+            (Expr::Unary(op, x), Expr::Unary(other_op, y)) => op == other_op && same(x, y),
+            (Expr::Binary(op, x, y), Expr::Binary(other_op, z, w)) => {
+                op == other_op && same(x, z) && same(y, w)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Orders constants only.
+impl PartialOrd for Expr {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.constant()?.partial_cmp(&other.constant()?)
+    }
+}
+
+/// Prints infix, or RPN with the alternate flag.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            f.write_str(&self.to_rpn())
+        } else {
+            f.write_str(&self.to_infix())
+        }
+    }
+}
+
+impl Number for Expr {
+    fn parse(token: &str) -> Option<Self> {
+        token.parse().ok().map(Expr::Num)
+    }
+
+    fn from_i64(n: i64) -> Self {
+        Expr::Num(n as f64)
+    }
+
+    fn from_f64(x: f64) -> Option<Self> {
+        Some(Expr::Num(x))
+    }
+
+    /// The value of a constant, or NaN.
+    fn to_f64(&self) -> f64 {
+        self.constant().unwrap_or(f64::NAN)
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Expr::binary(BinaryOp::Add, self.clone(), rhs.clone()).checked()
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Expr::binary(BinaryOp::Sub, self.clone(), rhs.clone()).checked()
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Expr::binary(BinaryOp::Mul, self.clone(), rhs.clone()).checked()
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Expr::binary(BinaryOp::Div, self.clone(), rhs.clone()).checked()
+    }
+
+    fn rem(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Expr::binary(BinaryOp::Rem, self.clone(), rhs.clone()).checked()
+    }
+
+    fn neg(&self) -> Result<Self, ArithError> {
+        Expr::unary(UnaryOp::Neg, self.clone()).checked()
+    }
+
+    fn abs(&self) -> Result<Self, ArithError> {
+        Expr::unary(UnaryOp::Abs, self.clone()).checked()
+    }
+
+    fn pow(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Expr::binary(BinaryOp::Pow, self.clone(), rhs.clone()).checked()
+    }
+
+    /// Maps constants only, since `fun` cannot be written down.
+    fn map_f64<F>(&self, fun: F) -> Result<Self, ArithError>
+    where
+        F: Fn(f64) -> f64,
+    {
+        match self {
+            Expr::Num(x) => Ok(Expr::Num(fun(*x))),
+            _ => Err(ArithError::NotRepresentable),
+        }
+    }
+
+    fn to_source(&self) -> String {
+        self.to_rpn()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expr, MAX_DEPTH};
+    use crate::error::{ArithError, RpnError};
+    use crate::eval::Evaluator;
+
+    fn eval(exp: &str) -> Result<String, RpnError> {
+        Evaluator::with_operators(Expr::operators())
+            .eval(exp)
+            .map(|x| x.to_string())
+    }
+
+    #[test]
+    fn builds_trees_from_symbols() {
+        assert_eq!(eval("x 2 * 3 +"), Ok("2 * x + 3".to_string()));
+        assert_eq!(eval("x y + z *"), Ok("(x + y) * z".to_string()));
+        assert_eq!(eval("x sqrt neg 2 ^"), Ok("(-sqrt(x)) ^ 2".to_string()));
+        assert_eq!(eval("1 2 + 4 *"), Ok("12".to_string()));
+        let tree = Evaluator::with_operators(Expr::operators())
+            .eval("x 2 * 3 +")
+            .unwrap();
+        assert_eq!(format!("{:#}", tree), "2 x * 3 +");
+    }
+
+    #[test]
+    fn simplification() {
+        assert_eq!(eval("x 0 + 1 *"), Ok("x".to_string()));
+        assert_eq!(eval("0 x -"), Ok("-x".to_string()));
+        assert_eq!(eval("x x -"), Ok("0".to_string()));
+        assert_eq!(eval("x 0 *"), Ok("0".to_string()));
+        assert_eq!(eval("x 1 ^ y 0 ^ *"), Ok("x".to_string()));
+        assert_eq!(eval("x 3 * 2 *"), Ok("6 * x".to_string()));
+        assert_eq!(eval("x neg neg"), Ok("x".to_string()));
+        assert_eq!(eval("x exp ln"), Ok("x".to_string()));
+    }
+
+    #[test]
+    fn derivatives() {
+        assert_eq!(eval("x d/dx"), Ok("1".to_string()));
+        assert_eq!(eval("x 2 * 3 + d/dx"), Ok("2".to_string()));
+        assert_eq!(eval("x 3 ^ d/dx"), Ok("3 * x ^ 2".to_string()));
+        assert_eq!(eval("x y * d/dy"), Ok("x".to_string()));
+        assert_eq!(eval("x sin d/dx"), Ok("cos(x)".to_string()));
+        assert_eq!(
+            eval("x x * exp d/dx"),
+            Ok("exp(x * x) * (x + x)".to_string())
+        );
+        assert_eq!(eval("1 x / d/dx"), Ok("-1 / x ^ 2".to_string()));
+        assert_eq!(
+            eval("2 x ^ d/dx"),
+            Ok("0.6931471805599453 * 2 ^ x".to_string())
+        );
+        assert_eq!(eval("x 3 ^ d/dx d/dx"), Ok("6 * x".to_string()));
+        assert_eq!(
+            eval("5 x % d/dx"),
+            Err(RpnError::Arithmetic {
+                operator: "d/dx".to_string(),
+                error: ArithError::NotRepresentable,
+            })
+        );
+    }
+
+    #[test]
+    fn stack_words_and_variables_are_not_symbols() {
+        assert_eq!(eval("1 2 3 sum"), Ok("6".to_string()));
+        assert_eq!(eval("x y z count"), Ok("3".to_string()));
+        assert_eq!(eval("x y 1 roll - "), Ok("y - x".to_string()));
+        assert_eq!(
+            eval("x y 1 pick"),
+            Err(RpnError::LeftoverValues { count: 2 })
+        );
+        assert_eq!(eval("5 a ! a 2 *"), Ok("10".to_string()));
+        assert_eq!(eval("x a ! a a +"), Ok("x + x".to_string()));
+        assert_eq!(eval("b 2 *"), Ok("2 * b".to_string()));
+    }
+
+    #[test]
+    fn deep_trees_overflow() {
+        let chain = |n| format!("1{}", " x +".repeat(n));
+        assert_eq!(
+            eval(&chain(50_000)),
+            Err(RpnError::Arithmetic {
+                operator: "+".to_string(),
+                error: ArithError::Overflow,
+            })
+        );
+        let deep = Evaluator::with_operators(Expr::operators())
+            .eval(&chain(MAX_DEPTH))
+            .unwrap();
+        assert_eq!(deep.depth(), MAX_DEPTH);
+        assert_eq!(deep.simplify(), deep);
+        assert_eq!(deep.to_rpn().len(), chain(MAX_DEPTH).len());
+        assert!(deep.to_string().ends_with("+ x"));
+        assert_eq!(deep.derivative("x"), Ok(Expr::Num(MAX_DEPTH as f64)));
+        let sines = format!("x{}", " sin".repeat(MAX_DEPTH));
+        assert_eq!(
+            eval(&format!("{} d/dx", sines)),
+            Err(RpnError::Arithmetic {
+                operator: "d/dx".to_string(),
+                error: ArithError::Overflow,
+            })
+        );
+    }
+
+    #[test]
+    fn words_and_loops_still_work() {
+        let mut evaluator = Evaluator::with_operators(Expr::operators());
+        evaluator.run(": sq dup * ;").unwrap();
+        assert_eq!(evaluator.eval("x sq d/dx").unwrap().to_string(), "x + x");
+        assert_eq!(
+            evaluator.eval("x 4 1 do i * loop").unwrap().to_string(),
+            "6 * x"
+        );
+    }
+}
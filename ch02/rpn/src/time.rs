@@ -0,0 +1,833 @@
+//! Dates, times of day and durations for the `time` number type.
+//!
+//! Dates count days from 1970-01-01 in the proleptic Gregorian calendar and
+//! instants count seconds in UTC. Durations keep calendar months apart from
+//! seconds, so adding `1mo` to 2024-01-31 clamps to 2024-02-29 rather than
+//! adding a fixed number of days. Values print in ISO 8601.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::ArithError;
+use crate::number::Number;
+use crate::ops::Operators;
+
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+/// The first and last days dates may fall on, 0000-01-01 and 9999-12-31.
+const FIRST_DAY: i64 = days_from_civil(0, 1, 1);
+const LAST_DAY: i64 = days_from_civil(9999, 12, 31);
+
+const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// A length of time: a number of calendar months, whose length depends on
+/// where they are counted from, and a number of seconds. Days are always
+/// 86400 seconds, as there are no time zones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Duration {
+    months: i64,
+    seconds: f64,
+}
+
+impl Duration {
+    /// Creates a duration, returning `None` unless `seconds` is finite.
+    pub fn new(months: i64, seconds: f64) -> Option<Self> {
+        if seconds.is_finite() {
+            Some(Self { months, seconds })
+        } else {
+            None
+        }
+    }
+
+    pub fn months(&self) -> i64 {
+        self.months
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.seconds
+    }
+
+    fn neg(self) -> Self {
+        Self {
+            months: -self.months,
+            seconds: -self.seconds,
+        }
+    }
+
+    fn add(self, rhs: Self) -> Result<Self, ArithError> {
+        let months = self.months.checked_add(rhs.months);
+        months
+            .and_then(|months| Self::new(months, self.seconds + rhs.seconds))
+            .ok_or(ArithError::Overflow)
+    }
+
+    /// Multiplies by `k`, which must leave a whole number of months.
+    fn scale(self, k: f64) -> Result<Self, ArithError> {
+        let mut months = 0;
+        if self.months != 0 {
+            let scaled = self.months as f64 * k;
+            if scaled.fract() != 0.0 || scaled.abs() > i64::MAX as f64 {
+                return Err(ArithError::NotRepresentable);
+            }
+            months = scaled as i64;
+        }
+        Self::new(months, self.seconds * k).ok_or(ArithError::Overflow)
+    }
+
+    /// Parses `P1Y2M3DT4H5M6.5S` (with weeks as `P2W`) or a number with one
+    /// of the units `s`, `min`, `h`, `d`, `w`, `mo` and `y`, such as `1.5h`.
+    pub fn parse(token: &str) -> Option<Self> {
+        Self::parse_iso(token).or_else(|| Self::parse_short(token))
+    }
+
+    fn parse_iso(token: &str) -> Option<Self> {
+        let (negative, body) = match token.strip_prefix('-') {
+            Some(body) => (true, body),
+            None => (false, token),
+        };
+        let body = body.strip_prefix('P')?;
+        let (date, time) = match body.find('T') {
+            Some(i) => (&body[..i], Some(&body[i + 1..])),
+            None => (body, None),
+        };
+        let mut components = designated(date, &['Y', 'M', 'W', 'D'])?;
+        if let Some(time) = time {
+            let time = designated(time, &['H', 'M', 'S'])?;
+            if time.is_empty() {
+                return None;
+            }
+            // Minutes of the time part are told apart from months by case.
+            components.extend(time.into_iter().map(|(c, n)| (c.to_ascii_lowercase(), n)));
+        }
+        if components.is_empty() {
+            return None;
+        }
+        let mut duration = Self::new(0, 0.0)?;
+        for (designator, number) in components {
+            let part = match designator {
+                'Y' => Self::new(number.parse::<i64>().ok()?.checked_mul(12)?, 0.0),
+                'M' => Self::new(number.parse().ok()?, 0.0),
+                designator => {
+                    let unit = match designator {
+                        'W' => 7.0 * SECONDS_PER_DAY,
+                        'D' => SECONDS_PER_DAY,
+                        'h' => 3600.0,
+                        'm' => 60.0,
+                        _ => 1.0,
+                    };
+                    Self::new(0, number.parse::<f64>().ok()? * unit)
+                }
+            };
+            duration = duration.add(part?).ok()?;
+        }
+        Some(if negative { duration.neg() } else { duration })
+    }
+
+    fn parse_short(token: &str) -> Option<Self> {
+        let units: [(&str, i64, f64); 7] = [
+            ("min", 0, 60.0),
+            ("mo", 1, 0.0),
+            ("s", 0, 1.0),
+            ("h", 0, 3600.0),
+            ("d", 0, SECONDS_PER_DAY),
+            ("w", 0, 7.0 * SECONDS_PER_DAY),
+            ("y", 12, 0.0),
+        ];
+        units.iter().find_map(|&(suffix, months, seconds)| {
+            let number: f64 = token.strip_suffix(suffix)?.parse().ok()?;
+            if !number.is_finite() {
+                return None;
+            }
+            Self { months, seconds }.scale(number).ok()
+        })
+    }
+
+    /// Orders durations that measure the same way: with the same months, or
+    /// with no seconds. A month is no fixed number of days.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.months == other.months {
+            self.seconds.partial_cmp(&other.seconds)
+        } else if self.seconds == 0.0 && other.seconds == 0.0 {
+            self.months.partial_cmp(&other.months)
+        } else {
+            None
+        }
+    }
+}
+
+/// Splits `P`-style components such as `1Y2M` into their designators and
+/// numbers, which must come in the order of `designators`.
+fn designated<'a>(mut s: &'a str, designators: &[char]) -> Option<Vec<(char, &'a str)>> {
+    let mut components = Vec::new();
+    let mut next = 0;
+    while !s.is_empty() {
+        let i = s.find(|c: char| c.is_ascii_alphabetic())?;
+        let designator = s[i..].chars().next()?;
+        next += designators[next..].iter().position(|&d| d == designator)? + 1;
+        components.push((designator, &s[..i]));
+        s = &s[i + 1..];
+    }
+    Some(components)
+}
+
+/// Writes `P1Y2M3DT4H5M6.5S`, leaving out the parts that are zero, with a
+/// leading `-` when no part is positive.
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Whole microseconds, like instants, so that `1.1h` is `PT1H6M`.
+        let micros = (self.seconds * 1e6).round();
+        if self.months == 0 && micros == 0.0 {
+            return f.write_str("PT0S");
+        }
+        let (months, mut micros) = if self.months <= 0 && micros <= 0.0 {
+            f.write_str("-")?;
+            (-self.months, -micros)
+        } else {
+            (self.months, micros)
+        };
+        f.write_str("P")?;
+        let days = (micros / (SECONDS_PER_DAY * 1e6)).trunc();
+        micros -= days * SECONDS_PER_DAY * 1e6;
+        let hours = (micros / 3.6e9).trunc();
+        micros -= hours * 3.6e9;
+        let minutes = (micros / 6e7).trunc();
+        let rest = (micros - minutes * 6e7) / 1e6;
+        for (n, designator) in [(months / 12, 'Y'), (months % 12, 'M')] {
+            if n != 0 {
+                write!(f, "{}{}", n, designator)?;
+            }
+        }
+        if days != 0.0 {
+            write!(f, "{}D", days)?;
+        }
+        if hours != 0.0 || minutes != 0.0 || rest != 0.0 {
+            f.write_str("T")?;
+        }
+        for (n, designator) in [(hours, 'H'), (minutes, 'M'), (rest, 'S')] {
+            if n != 0.0 {
+                write!(f, "{}{}", n, designator)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A stack value of the `time` number type: a plain number, an ISO-8601
+/// date such as `2024-02-28`, a date and time such as `2024-02-28T14:30`,
+/// or a [`Duration`] such as `P1DT2H` or `1d`.
+///
+/// Adding a duration to a date counts whole months first, keeping the day of
+/// the month where it exists (`2024-01-31 1mo +` is `2024-02-29`), and then
+/// days and seconds. The difference of two dates is a duration. A time of
+/// day such as `14:30` is the duration since midnight, so
+/// `2024-02-28 14:30 +` is a date and time. Dates are in the years 0000 to
+/// 9999 of the Gregorian calendar and have no time zone.
+#[derive(Debug, Clone, Copy)]
+pub enum Time {
+    Number(f64),
+    /// A date, as days since 1970-01-01.
+    Date(i64),
+    /// A date and time of day, as seconds since 1970-01-01T00:00.
+    DateTime(f64),
+    Duration(Duration),
+}
+
+impl Time {
+    /// Creates the date `year-month-day`, returning `None` if there is no such day.
+    pub fn date(year: i64, month: i64, day: i64) -> Option<Self> {
+        let valid = (0..=9999).contains(&year)
+            && (1..=12).contains(&month)
+            && (1..=days_in_month(year, month)).contains(&day);
+        if valid {
+            Some(Time::Date(days_from_civil(year, month, day)))
+        } else {
+            None
+        }
+    }
+
+    /// Creates a table with the builtin operators and words for dates:
+    ///
+    /// - `seconds`, `minutes`, `hours`, `days`, `weeks`, `months` and
+    ///   `years` turn a number into a duration of so many units, and a
+    ///   duration into the number of units it lasts.
+    /// - `weekday` gives the ISO day of the week of a date, 1 for Monday to
+    ///   7 for Sunday, and `monday` to `sunday` the first such day on or
+    ///   after a date.
+    /// - `date` drops the time of day, and `today` and `now` push the
+    ///   current date and time in UTC.
+    pub fn operators() -> Operators<Time> {
+        let mut ops: Operators<Time> = Operators::builtin();
+        let units = [
+            ("seconds", 1.0),
+            ("minutes", 60.0),
+            ("hours", 3600.0),
+            ("days", SECONDS_PER_DAY),
+            ("weeks", 7.0 * SECONDS_PER_DAY),
+        ];
+        for (name, unit) in units {
+            ops.register_unary(name, move |x| x.in_seconds(unit));
+        }
+        ops.register_unary("months", |x| x.in_months(1));
+        ops.register_unary("years", |x| x.in_months(12));
+        ops.register_unary("weekday", |x| Ok(Time::Number(weekday(x.day()?) as f64)));
+        for (i, name) in WEEKDAYS.iter().enumerate() {
+            let target = i as i64 + 1;
+            ops.register_unary(name, move |x| {
+                let day = x.day()?;
+                checked_date(day + (target - weekday(day)).rem_euclid(7))
+            });
+        }
+        ops.register_unary("date", |x| Ok(Time::Date(x.day()?)));
+        ops.register_nary("today", 0, |_| Ok(Time::Date(now().div_euclid(86400))));
+        ops.register_nary("now", 0, |_| Ok(Time::DateTime(now() as f64)));
+        ops
+    }
+
+    /// The seconds since 1970-01-01T00:00 of a date or a date and time.
+    fn instant(&self) -> Option<f64> {
+        match *self {
+            Time::Date(day) => Some(day as f64 * SECONDS_PER_DAY),
+            Time::DateTime(seconds) => Some(seconds),
+            _ => None,
+        }
+    }
+
+    /// The day of a date or a date and time, as days since 1970-01-01.
+    fn day(&self) -> Result<i64, ArithError> {
+        match *self {
+            Time::Date(day) => Ok(day),
+            Time::DateTime(seconds) => Ok((seconds / SECONDS_PER_DAY).floor() as i64),
+            _ => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    /// Converts between numbers and durations counted in units of `unit` seconds.
+    fn in_seconds(&self, unit: f64) -> Result<Time, ArithError> {
+        match *self {
+            Time::Number(n) => duration(0, n * unit),
+            Time::Duration(d) if d.months == 0 => Ok(Time::Number(d.seconds / unit)),
+            Time::Duration(_) => Err(ArithError::NotRepresentable),
+            _ => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    /// Converts between numbers and durations counted in units of `unit` months.
+    fn in_months(&self, unit: i64) -> Result<Time, ArithError> {
+        match *self {
+            Time::Number(n) => Duration {
+                months: unit,
+                seconds: 0.0,
+            }
+            .scale(n)
+            .map(Time::Duration),
+            Time::Duration(d) if d.seconds == 0.0 => {
+                Ok(Time::Number(d.months as f64 / unit as f64))
+            }
+            Time::Duration(_) => Err(ArithError::NotRepresentable),
+            _ => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    /// Moves a date or a date and time by `d`. A date stays a date unless
+    /// `d` has a time of day.
+    fn shift(&self, d: Duration) -> Result<Time, ArithError> {
+        let day = self.day()?;
+        let time_of_day = self.instant().unwrap_or(0.0) - day as f64 * SECONDS_PER_DAY;
+        let (year, month, day_of_month) = civil_from_days(day);
+        let month = (year * 12 + month - 1)
+            .checked_add(d.months)
+            .filter(|month| (0..10000 * 12).contains(month))
+            .ok_or(ArithError::NotRepresentable)?;
+        let (year, month) = (month / 12, month % 12 + 1);
+        let day = days_from_civil(year, month, day_of_month.min(days_in_month(year, month)));
+        let seconds = time_of_day + d.seconds;
+        let days = (seconds / SECONDS_PER_DAY).floor();
+        if days.abs() > (LAST_DAY - FIRST_DAY) as f64 {
+            return Err(ArithError::NotRepresentable);
+        }
+        let day = day + days as i64;
+        if !(FIRST_DAY..=LAST_DAY).contains(&day) {
+            return Err(ArithError::NotRepresentable);
+        }
+        match self {
+            Time::Date(_) if d.seconds % SECONDS_PER_DAY == 0.0 => Ok(Time::Date(day)),
+            _ => Ok(Time::DateTime(
+                day as f64 * SECONDS_PER_DAY + (seconds - days * SECONDS_PER_DAY),
+            )),
+        }
+    }
+}
+
+fn duration(months: i64, seconds: f64) -> Result<Time, ArithError> {
+    Duration::new(months, seconds)
+        .map(Time::Duration)
+        .ok_or(ArithError::Overflow)
+}
+
+fn checked_date(day: i64) -> Result<Time, ArithError> {
+    if (FIRST_DAY..=LAST_DAY).contains(&day) {
+        Ok(Time::Date(day))
+    } else {
+        Err(ArithError::NotRepresentable)
+    }
+}
+
+/// The seconds since 1970-01-01T00:00 UTC.
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The days since 1970-01-01 of a Gregorian date, counting from March so
+/// that the leap day comes last.
+const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year, month and day of the date `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// The ISO day of the week, 1 for Monday to 7 for Sunday. 1970-01-01 was a Thursday.
+fn weekday(day: i64) -> i64 {
+    (day + 3).rem_euclid(7) + 1
+}
+
+/// Parses exactly `len` ASCII digits.
+fn digits(s: &str, len: usize) -> Option<i64> {
+    if s.len() == len && s.bytes().all(|b| b.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Parses `2024-02-28`.
+fn parse_date(token: &str) -> Option<i64> {
+    let bytes = token.as_bytes();
+    if bytes.len() != 10 || bytes[4] != b'-' || bytes[7] != b'-' {
+        return None;
+    }
+    let date = Time::date(
+        digits(&token[..4], 4)?,
+        digits(&token[5..7], 2)?,
+        digits(&token[8..], 2)?,
+    )?;
+    date.day().ok()
+}
+
+/// Parses a time of day, `14:30`, `14:30:15` or `14:30:15.25`, into seconds.
+fn parse_clock(token: &str) -> Option<f64> {
+    let mut parts = token.splitn(3, ':');
+    let hours = digits(parts.next()?, 2).filter(|&h| h < 24)?;
+    let minutes = digits(parts.next()?, 2).filter(|&m| m < 60)?;
+    let seconds = match parts.next() {
+        Some(seconds) => {
+            let (whole, fraction) = match seconds.find('.') {
+                Some(i) => (&seconds[..i], &seconds[i + 1..]),
+                None => (seconds, "0"),
+            };
+            digits(whole, 2).filter(|&s| s < 60)?;
+            if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            seconds.parse().ok()?
+        }
+        None => 0.0,
+    };
+    Some((hours * 3600 + minutes * 60) as f64 + seconds)
+}
+
+/// Dates and times are the same when they are the same instant.
+impl PartialEq for Time {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+/// Numbers, instants and comparable durations are ordered among themselves.
+impl PartialOrd for Time {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Time::Number(x), Time::Number(y)) => x.partial_cmp(y),
+            (Time::Duration(x), Time::Duration(y)) => x.partial_cmp(y),
+            (x, y) => x.instant()?.partial_cmp(&y.instant()?),
+        }
+    }
+}
+
+/// Prints dates as `2024-02-28`, dates and times as `2024-02-28T14:30:00`
+/// and durations as `P1DT2H`. The precision only applies to numbers.
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Time::Number(x) => fmt::Display::fmt(&x, f),
+            Time::Date(day) => {
+                let (year, month, day) = civil_from_days(day);
+                write!(f, "{:04}-{:02}-{:02}", year, month, day)
+            }
+            Time::DateTime(seconds) => {
+                // Whole microseconds, as instants have no finer resolution,
+                // rounded before the day is split off so they carry into it.
+                let total = (seconds * 1e6).round();
+                let day = (total / (SECONDS_PER_DAY * 1e6)).floor();
+                let micros = (total - day * SECONDS_PER_DAY * 1e6) as i64;
+                let seconds = (micros % 60_000_000) as f64 / 1e6;
+                let padding = if seconds < 10.0 { "0" } else { "" };
+                write!(
+                    f,
+                    "{}T{:02}:{:02}:{}{}",
+                    Time::Date(day as i64),
+                    micros / 3_600_000_000,
+                    micros / 60_000_000 % 60,
+                    padding,
+                    seconds
+                )
+            }
+            Time::Duration(d) => fmt::Display::fmt(&d, f),
+        }
+    }
+}
+
+impl Number for Time {
+    fn parse(token: &str) -> Option<Self> {
+        if let Ok(x) = token.parse() {
+            return Some(Time::Number(x));
+        }
+        if let Some(day) = parse_date(token) {
+            return Some(Time::Date(day));
+        }
+        if let Some((date, clock)) = token.split_once('T') {
+            if let (Some(day), Some(seconds)) = (parse_date(date), parse_clock(clock)) {
+                return Some(Time::DateTime(day as f64 * SECONDS_PER_DAY + seconds));
+            }
+        }
+        match parse_clock(token) {
+            Some(seconds) => Some(Time::Duration(Duration::new(0, seconds)?)),
+            None => Duration::parse(token).map(Time::Duration),
+        }
+    }
+
+    fn from_i64(n: i64) -> Self {
+        Time::Number(n as f64)
+    }
+
+    fn from_f64(x: f64) -> Option<Self> {
+        Some(Time::Number(x))
+    }
+
+    /// Dates and durations have no `f64` value and give NaN; convert
+    /// durations with `seconds` or `days` first.
+    fn to_f64(&self) -> f64 {
+        match *self {
+            Time::Number(x) => x,
+            _ => f64::NAN,
+        }
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, ArithError> {
+        match (*self, *rhs) {
+            (Time::Number(x), Time::Number(y)) => Ok(Time::Number(x + y)),
+            (Time::Duration(x), Time::Duration(y)) => x.add(y).map(Time::Duration),
+            (Time::Duration(d), t) | (t, Time::Duration(d)) => t.shift(d),
+            _ => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, ArithError> {
+        match (*self, *rhs) {
+            (Time::Number(x), Time::Number(y)) => Ok(Time::Number(x - y)),
+            (Time::Duration(x), Time::Duration(y)) => x.add(y.neg()).map(Time::Duration),
+            (t, Time::Duration(d)) => t.shift(d.neg()),
+            (x, y) => match (x.instant(), y.instant()) {
+                (Some(x), Some(y)) => duration(0, x - y),
+                _ => Err(ArithError::TypeMismatch),
+            },
+        }
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, ArithError> {
+        match (*self, *rhs) {
+            (Time::Number(x), Time::Number(y)) => Ok(Time::Number(x * y)),
+            (Time::Duration(d), Time::Number(k)) | (Time::Number(k), Time::Duration(d)) => {
+                d.scale(k).map(Time::Duration)
+            }
+            _ => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, ArithError> {
+        match (*self, *rhs) {
+            (Time::Number(x), Time::Number(y)) => Ok(Time::Number(x / y)),
+            (Time::Duration(_), Time::Number(0.0)) => Err(ArithError::DivisionByZero),
+            (Time::Duration(d), Time::Number(k)) => d.scale(1.0 / k).map(Time::Duration),
+            (Time::Duration(x), Time::Duration(y)) => {
+                let (x, y) = match (x.months, y.months) {
+                    (0, 0) => (x.seconds, y.seconds),
+                    _ if x.seconds == 0.0 && y.seconds == 0.0 => (x.months as f64, y.months as f64),
+                    _ => return Err(ArithError::NotRepresentable),
+                };
+                if y == 0.0 {
+                    return Err(ArithError::DivisionByZero);
+                }
+                Ok(Time::Number(x / y))
+            }
+            _ => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    fn rem(&self, rhs: &Self) -> Result<Self, ArithError> {
+        match (*self, *rhs) {
+            (Time::Number(x), Time::Number(y)) => Ok(Time::Number(x % y)),
+            (Time::Duration(x), Time::Duration(y)) if x.months == 0 && y.months == 0 => {
+                if y.seconds == 0.0 {
+                    return Err(ArithError::DivisionByZero);
+                }
+                duration(0, x.seconds % y.seconds)
+            }
+            (Time::Duration(_), Time::Duration(_)) => Err(ArithError::NotRepresentable),
+            _ => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    fn neg(&self) -> Result<Self, ArithError> {
+        match *self {
+            Time::Number(x) => Ok(Time::Number(-x)),
+            Time::Duration(d) => Ok(Time::Duration(d.neg())),
+            _ => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    fn abs(&self) -> Result<Self, ArithError> {
+        match *self {
+            Time::Number(x) => Ok(Time::Number(x.abs())),
+            Time::Duration(d) if d.months <= 0 && d.seconds <= 0.0 => Ok(Time::Duration(d.neg())),
+            Time::Duration(d) => Ok(Time::Duration(d)),
+            _ => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    /// Applies `fun` to numbers; dates and durations are a type mismatch.
+    fn map_f64<F>(&self, fun: F) -> Result<Self, ArithError>
+    where
+        F: Fn(f64) -> f64,
+    {
+        match *self {
+            Time::Number(x) => Ok(Time::Number(fun(x))),
+            _ => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match *self {
+            Time::Number(x) => x != 0.0,
+            Time::Duration(d) => d.months != 0 || d.seconds != 0.0,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{civil_from_days, days_from_civil, Duration, Time};
+    use crate::error::{ArithError, RpnError};
+    use crate::eval::Evaluator;
+    use crate::number::Number;
+
+    fn eval(exp: &str) -> Result<String, RpnError> {
+        Evaluator::with_operators(Time::operators())
+            .eval(exp)
+            .map(|value| value.to_string())
+    }
+
+    fn fails(exp: &str, operator: &str, error: ArithError) {
+        let expected = Err(RpnError::Arithmetic {
+            operator: operator.to_string(),
+            error,
+        });
+        assert_eq!(eval(exp), expected, "{}", exp);
+    }
+
+    #[test]
+    fn calendar_conversions_round_trip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        for day in (days_from_civil(0, 1, 1)..=days_from_civil(9999, 12, 31)).step_by(97) {
+            let (year, month, day_of_month) = civil_from_days(day);
+            assert_eq!(days_from_civil(year, month, day_of_month), day);
+        }
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(Time::parse("2.5"), Some(Time::Number(2.5)));
+        assert_eq!(Time::parse("2024-02-29"), Time::date(2024, 2, 29));
+        assert_eq!(Time::parse("2023-02-29"), None);
+        assert_eq!(Time::parse("2024-2-29"), None);
+        let noon = Time::parse("2024-02-29T12:00").unwrap();
+        assert_eq!(noon.to_string(), "2024-02-29T12:00:00");
+        let tenth = Time::parse("2024-02-29T12:00:00.1").unwrap();
+        assert_eq!(tenth.to_string(), "2024-02-29T12:00:00.1");
+        assert_eq!(
+            Time::parse("2024-02-29T12:00:07.5").unwrap().to_string(),
+            "2024-02-29T12:00:07.5"
+        );
+        assert_eq!(Time::parse("2024-02-29T24:00"), None);
+        assert_eq!(
+            Time::parse("2024-02-28T23:59:59.9999996")
+                .unwrap()
+                .to_string(),
+            "2024-02-29T00:00:00"
+        );
+        assert_eq!(
+            eval("2024-02-28 86399.9999996 seconds +"),
+            Ok("2024-02-29T00:00:00".to_string())
+        );
+        let duration = |token| match Time::parse(token) {
+            Some(Time::Duration(d)) => Some((d.months(), d.seconds())),
+            _ => None,
+        };
+        assert_eq!(duration("P1Y2M3DT4H5M6.5S"), Some((14, 273906.5)));
+        assert_eq!(duration("P2W"), Some((0, 1209600.0)));
+        assert_eq!(duration("-PT1M"), Some((0, -60.0)));
+        assert_eq!(duration("14:30"), Some((0, 52200.0)));
+        assert_eq!(duration("1.5h"), Some((0, 5400.0)));
+        assert_eq!(duration("90min"), Some((0, 5400.0)));
+        assert_eq!(duration("3mo"), Some((3, 0.0)));
+        assert_eq!(duration("2y"), Some((24, 0.0)));
+        for token in &[
+            "P", "PT", "P1H", "PT1D", "P1D2Y", "1.5mo", "infd", "d", "ms",
+        ] {
+            assert_eq!(Time::parse(token), None, "{}", token);
+        }
+    }
+
+    #[test]
+    fn durations_print_in_iso_8601() {
+        let print = |months, seconds| Duration::new(months, seconds).unwrap().to_string();
+        assert_eq!(print(0, 0.0), "PT0S");
+        assert_eq!(print(14, 273906.5), "P1Y2M3DT4H5M6.5S");
+        assert_eq!(print(0, -86400.0), "-P1D");
+        assert_eq!(print(1, -86400.0), "P1M-1D");
+        assert_eq!(print(0, 1e-9), "PT0S");
+        assert_eq!(print(0, -0.1 - 0.2), "-PT0.3S");
+        assert_eq!(eval("1.1h"), Ok("PT1H6M".to_string()));
+        assert_eq!(eval("0.1s 0.2s +"), Ok("PT0.3S".to_string()));
+        for token in &["P1Y2M3DT4H5M6.5S", "-P1D", "P1M-1D", "PT30M"] {
+            assert_eq!(Time::parse(token).unwrap().to_string(), *token);
+        }
+    }
+
+    #[test]
+    fn calendar_arithmetic() {
+        assert_eq!(eval("2024-02-28 1d +"), Ok("2024-02-29".to_string()));
+        assert_eq!(eval("2023-02-28 1d +"), Ok("2023-03-01".to_string()));
+        assert_eq!(eval("1d 1900-02-28 +"), Ok("1900-03-01".to_string()));
+        assert_eq!(eval("2000-02-28 P1D +"), Ok("2000-02-29".to_string()));
+        assert_eq!(eval("2024-01-31 1mo +"), Ok("2024-02-29".to_string()));
+        assert_eq!(eval("2024-02-29 1y +"), Ok("2025-02-28".to_string()));
+        assert_eq!(eval("2024-03-31 P1M -"), Ok("2024-02-29".to_string()));
+        assert_eq!(
+            eval("2024-02-28 14:30 +"),
+            Ok("2024-02-28T14:30:00".to_string())
+        );
+        assert_eq!(
+            eval("2024-02-28T23:00 2h +"),
+            Ok("2024-02-29T01:00:00".to_string())
+        );
+        assert_eq!(eval("2024-03-01 2024-02-28 -"), Ok("P2D".to_string()));
+        assert_eq!(eval("2025-03-01 2024-03-01 - days"), Ok("365".to_string()));
+        assert_eq!(
+            eval("2024-03-01T06:00 2024-02-28 - hours"),
+            Ok("54".to_string())
+        );
+        assert_eq!(eval("2024-02-28 2024-02-28T00:00 ="), Ok("1".to_string()));
+        assert_eq!(
+            eval("2024-02-28 2024-03-01 min"),
+            Ok("2024-02-28".to_string())
+        );
+        fails("9999-12-31 1d +", "+", ArithError::NotRepresentable);
+        fails("0000-01-01 1mo -", "-", ArithError::NotRepresentable);
+        fails("2024-02-28 1 +", "+", ArithError::TypeMismatch);
+        fails("2024-02-28 2024-02-28 +", "+", ArithError::TypeMismatch);
+    }
+
+    #[test]
+    fn duration_arithmetic() {
+        assert_eq!(eval("1h 30min +"), Ok("PT1H30M".to_string()));
+        assert_eq!(eval("1d 3 *"), Ok("P3D".to_string()));
+        assert_eq!(eval("1y 2 /"), Ok("P6M".to_string()));
+        assert_eq!(eval("1w 1d /"), Ok("7".to_string()));
+        assert_eq!(eval("1y 1mo /"), Ok("12".to_string()));
+        assert_eq!(eval("100min 1h %"), Ok("PT40M".to_string()));
+        assert_eq!(eval("-1d abs"), Ok("P1D".to_string()));
+        assert_eq!(eval("1d 23h >"), Ok("1".to_string()));
+        assert_eq!(eval("1mo 30d <"), Ok("0".to_string()));
+        fails("1mo 3 /", "/", ArithError::NotRepresentable);
+        fails("1mo 1d /", "/", ArithError::NotRepresentable);
+        fails("1d 0 /", "/", ArithError::DivisionByZero);
+        fails("1d 1d *", "*", ArithError::TypeMismatch);
+        fails("1d sqrt", "sqrt", ArithError::TypeMismatch);
+    }
+
+    #[test]
+    fn conversions_and_weekdays() {
+        assert_eq!(eval("90 minutes"), Ok("PT1H30M".to_string()));
+        assert_eq!(eval("PT1H30M minutes"), Ok("90".to_string()));
+        assert_eq!(eval("36h days"), Ok("1.5".to_string()));
+        assert_eq!(eval("18 months"), Ok("P1Y6M".to_string()));
+        assert_eq!(eval("P1Y6M years"), Ok("1.5".to_string()));
+        fails("1mo days", "days", ArithError::NotRepresentable);
+        fails("1.5 months", "months", ArithError::NotRepresentable);
+        fails("2024-02-28 days", "days", ArithError::TypeMismatch);
+        assert_eq!(eval("2024-02-29 weekday"), Ok("4".to_string()));
+        assert_eq!(eval("1970-01-01 weekday"), Ok("4".to_string()));
+        assert_eq!(
+            eval("2024-02-29T18:00 friday"),
+            Ok("2024-03-01".to_string())
+        );
+        assert_eq!(eval("2024-02-29 thursday"), Ok("2024-02-29".to_string()));
+        assert_eq!(eval("2024-02-29T18:00 date"), Ok("2024-02-29".to_string()));
+        assert!(eval("today now date -").is_ok());
+    }
+}
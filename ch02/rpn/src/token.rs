@@ -0,0 +1,136 @@
+/// A byte range `start..end` in the evaluated source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns the smallest span covering both spans.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// A whitespace-separated word of an RPN expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    pub span: Span,
+}
+
+/// Splits `exp` at whitespace, recording where each token is. The brackets
+/// of vectors are tokens of their own, so `[1 2]` is `[`, `1`, `2`, `]`.
+pub fn tokenize(exp: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut push = |s: usize, e: usize| {
+        tokens.push(Token {
+            text: &exp[s..e],
+            span: Span::new(s, e),
+        })
+    };
+    for (i, c) in exp.char_indices() {
+        let bracket = c == '[' || c == ']';
+        if c.is_whitespace() || bracket {
+            if let Some(s) = start.take() {
+                push(s, i);
+            }
+            if bracket {
+                push(i, i + 1);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        push(s, exp.len());
+    }
+    tokens
+}
+
+/// Renders the line of `source` containing `span` with `^` under the span:
+///
+/// ```text
+///   6.1 5.2 ? +
+///           ^
+/// ```
+///
+/// Lines are indented by two spaces and prefixed with their number when
+/// `source` has more than one line.
+pub fn render_caret(source: &str, span: Span) -> String {
+    let start = span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+    let line = &source[line_start..line_end];
+    let prefix = if source.trim_end_matches('\n').contains('\n') {
+        format!("{}: ", source[..line_start].matches('\n').count() + 1)
+    } else {
+        String::new()
+    };
+    let pad: String = prefix
+        .chars()
+        .map(|_| ' ')
+        .chain(
+            source[line_start..start]
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' }),
+        )
+        .collect();
+    let end = span.end.clamp(start, line_end);
+    let width = source[start..end].chars().count().max(1);
+    format!("  {}{}\n  {}{}", prefix, line, pad, "^".repeat(width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_caret, tokenize, Span};
+
+    #[test]
+    fn tokens_have_spans() {
+        let spans: Vec<(&str, Span)> = tokenize("  6.1\t5.2 ×  +\n")
+            .into_iter()
+            .map(|t| (t.text, t.span))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("6.1", Span::new(2, 5)),
+                ("5.2", Span::new(6, 9)),
+                ("×", Span::new(10, 12)),
+                ("+", Span::new(14, 15)),
+            ]
+        );
+        assert!(tokenize(" \n ").is_empty());
+        let texts: Vec<&str> = tokenize("[1 [2]]x").into_iter().map(|t| t.text).collect();
+        assert_eq!(texts, vec!["[", "1", "[", "2", "]", "]", "x"]);
+    }
+
+    #[test]
+    fn carets() {
+        assert_eq!(
+            render_caret("1 2 foo +", Span::new(4, 7)),
+            "  1 2 foo +\n      ^^^"
+        );
+        assert_eq!(render_caret("1 × +", Span::new(2, 4)), "  1 × +\n    ^");
+        assert_eq!(
+            render_caret(": sq dup * ;\n: cube dup sq * ;\n", Span::new(24, 26)),
+            "  2: : cube dup sq * ;\n                ^^"
+        );
+        assert_eq!(render_caret("1 +", Span::new(3, 3)), "  1 +\n     ^");
+    }
+}
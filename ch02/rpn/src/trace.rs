@@ -0,0 +1,184 @@
+use std::fmt;
+
+use crate::error::RpnError;
+use crate::number::Number;
+use crate::repl::format_stack;
+use crate::token::Span;
+
+/// What the evaluator did with a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Push,
+    /// Applied an operator taking `arity` operands.
+    Apply {
+        arity: usize,
+    },
+    StackWord,
+    Load,
+    Store,
+    /// Ran a user-defined word; the steps of its body follow one level deeper.
+    Call,
+    /// Ran `if`, `times` or `do`; the steps of the block follow.
+    Control,
+    Define,
+    Unknown,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Push => write!(f, "push"),
+            Action::Apply { arity: 1 } => write!(f, "apply1"),
+            Action::Apply { arity: 2 } => write!(f, "apply2"),
+            Action::Apply { .. } => write!(f, "applyN"),
+            Action::StackWord => write!(f, "stack"),
+            Action::Load => write!(f, "load"),
+            Action::Store => write!(f, "store"),
+            Action::Call => write!(f, "call"),
+            Action::Control => write!(f, "control"),
+            Action::Define => write!(f, "define"),
+            Action::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// One executed token with the stack around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep<N> {
+    pub token: String,
+    /// Where the token is in the traced expression.
+    pub span: Span,
+    pub action: Action,
+    /// How many user-defined words deep the token was run.
+    pub depth: usize,
+    pub before: Vec<N>,
+    /// The stack after the token, or when it failed.
+    pub after: Vec<N>,
+}
+
+/// The steps of a traced evaluation and how it ended.
+///
+/// A run stopped by a breakpoint ends with [`RpnError::Breakpoint`] and leaves
+/// the evaluator's stack as it was at that point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace<N> {
+    pub steps: Vec<TraceStep<N>>,
+    pub result: Result<(), RpnError>,
+}
+
+pub(crate) struct Tracer<N> {
+    pub(crate) steps: Vec<TraceStep<N>>,
+    pub(crate) breakpoint: Option<usize>,
+}
+
+/// Renders steps as a table with one row per step, indenting the tokens of
+/// word bodies by their depth.
+pub fn render_table<N: Number>(steps: &[TraceStep<N>]) -> String {
+    let rows: Vec<[String; 5]> = steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| {
+            [
+                (i + 1).to_string(),
+                format!("{}{}", "  ".repeat(step.depth), step.token),
+                step.action.to_string(),
+                format_stack(&step.before),
+                format_stack(&step.after),
+            ]
+        })
+        .collect();
+    let header = ["#", "token", "action", "before", "after"].map(String::from);
+    let mut widths = [0; 5];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_table, Action};
+    use crate::token::Span;
+    use crate::{Evaluator, RpnError};
+
+    #[test]
+    fn records_every_token() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        let trace = evaluator.trace("3 4 + sqrt x !", None);
+        assert_eq!(trace.result, Ok(()));
+        let summary: Vec<(&str, Action, Vec<f64>)> = trace
+            .steps
+            .iter()
+            .map(|s| (s.token.as_str(), s.action, s.after.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("3", Action::Push, vec![3.0]),
+                ("4", Action::Push, vec![3.0, 4.0]),
+                ("+", Action::Apply { arity: 2 }, vec![7.0]),
+                ("sqrt", Action::Apply { arity: 1 }, vec![7f64.sqrt()]),
+                ("x !", Action::Store, vec![]),
+            ]
+        );
+        assert_eq!(trace.steps[2].before, vec![3.0, 4.0]);
+        assert_eq!(trace.steps[4].span, Span::new(11, 12));
+    }
+
+    #[test]
+    fn word_bodies_are_nested() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        evaluator.define("sq", "dup *").unwrap();
+        let trace = evaluator.trace("3 sq", None);
+        let depths: Vec<(&str, usize)> = trace
+            .steps
+            .iter()
+            .map(|s| (s.token.as_str(), s.depth))
+            .collect();
+        assert_eq!(depths, vec![("3", 0), ("sq", 0), ("dup", 1), ("*", 1)]);
+        assert_eq!(trace.steps[1].after, vec![9.0]);
+    }
+
+    #[test]
+    fn stops_at_breakpoint() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        let trace = evaluator.trace("1 2 + 4 *", Some(3));
+        assert_eq!(trace.steps.len(), 3);
+        assert_eq!(trace.result, Err(RpnError::Breakpoint { step: 4 }));
+        assert_eq!(evaluator.stack(), &[3.0]);
+    }
+
+    #[test]
+    fn failing_step_is_recorded() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        let trace = evaluator.trace("1 + 2", None);
+        assert_eq!(trace.steps.len(), 2);
+        assert_eq!(trace.steps[1].after, vec![1.0]);
+        assert!(trace.result.is_err());
+    }
+
+    #[test]
+    fn table() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        let trace = evaluator.trace("1 2 +", None);
+        assert_eq!(
+            render_table(&trace.steps),
+            "#  token  action  before   after\n\
+             1  1      push    <0>      <1> 1\n\
+             2  2      push    <1> 1    <2> 1 2\n\
+             3  +      apply2  <2> 1 2  <1> 3\n"
+        );
+    }
+}
@@ -0,0 +1,608 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::error::ArithError;
+use crate::eval::Evaluator;
+use crate::number::Number;
+use crate::ops::Operator;
+
+/// The SI base units, in the order dimensions are printed.
+const BASE_UNITS: [&str; 7] = ["kg", "m", "s", "A", "K", "mol", "cd"];
+
+/// The exponents of the SI base units in a quantity, e.g. `kg*m/s^2` for force.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Dimension([i8; 7]);
+
+impl Dimension {
+    /// The dimension of the base unit `name`, such as `m` or `kg`.
+    pub fn base(name: &str) -> Option<Self> {
+        let index = BASE_UNITS.iter().position(|&base| base == name)?;
+        let mut exponents = [0; 7];
+        exponents[index] = 1;
+        Some(Dimension(exponents))
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        self.0 == [0; 7]
+    }
+
+    fn combine(self, rhs: Self, sign: i8) -> Option<Self> {
+        let mut exponents = self.0;
+        for (exponent, r) in exponents.iter_mut().zip(rhs.0.iter()) {
+            *exponent = exponent.checked_add(r.checked_mul(sign)?)?;
+        }
+        Some(Dimension(exponents))
+    }
+
+    fn powi(self, n: i8) -> Option<Self> {
+        let mut exponents = self.0;
+        for exponent in exponents.iter_mut() {
+            *exponent = exponent.checked_mul(n)?;
+        }
+        Some(Dimension(exponents))
+    }
+}
+
+/// Prints the dimension as base units, e.g. `kg*m^2/s^3`, or `s^-1` when
+/// there is nothing to divide.
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let term = |name: &str, exponent: i8| match exponent {
+            1 => name.to_string(),
+            _ => format!("{}^{}", name, exponent),
+        };
+        let terms = |positive: bool| -> Vec<String> {
+            BASE_UNITS
+                .iter()
+                .zip(self.0.iter())
+                .filter(|&(_, &e)| if positive { e > 0 } else { e < 0 })
+                .map(|(name, &e)| term(name, if positive { e } else { -e }))
+                .collect()
+        };
+        let (numerator, denominator) = (terms(true), terms(false));
+        if numerator.is_empty() {
+            let inverse: Vec<String> = BASE_UNITS
+                .iter()
+                .zip(self.0.iter())
+                .filter(|&(_, &e)| e != 0)
+                .map(|(name, &e)| term(name, e))
+                .collect();
+            return write!(f, "{}", inverse.join("*"));
+        }
+        write!(f, "{}", numerator.join("*"))?;
+        for term in denominator {
+            write!(f, "/{}", term)?;
+        }
+        Ok(())
+    }
+}
+
+/// A unit of measure: how many SI base units it is and of which dimension.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    factor: f64,
+    dimension: Dimension,
+    /// How the unit was written; `None` prints the dimension instead.
+    symbol: Option<Rc<str>>,
+}
+
+impl Unit {
+    /// Creates an unnamed unit worth `factor` times the SI units of `dimension`.
+    pub fn new(factor: f64, dimension: Dimension) -> Self {
+        Self {
+            factor,
+            dimension,
+            symbol: None,
+        }
+    }
+
+    fn base(dimension: Dimension) -> Self {
+        Self::new(1.0, dimension)
+    }
+
+    pub fn factor(&self) -> f64 {
+        self.factor
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        self.dimension
+    }
+
+    pub fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    fn named(self, symbol: &str) -> Self {
+        Self {
+            symbol: Some(symbol.into()),
+            ..self
+        }
+    }
+
+    fn combine(&self, rhs: &Unit, sign: i8) -> Option<Unit> {
+        let factor = if sign > 0 {
+            self.factor * rhs.factor
+        } else {
+            self.factor / rhs.factor
+        };
+        Some(Unit::new(
+            factor,
+            self.dimension.combine(rhs.dimension, sign)?,
+        ))
+    }
+
+    fn powi(&self, n: i8) -> Option<Unit> {
+        Some(Unit::new(
+            self.factor.powi(n.into()),
+            self.dimension.powi(n)?,
+        ))
+    }
+}
+
+/// The units literals and conversion words may use, by name.
+///
+/// Unit expressions combine names with `*`, `/` and integer powers, e.g.
+/// `m/s^2` or `kg*m^2/s^2`, and are read left to right.
+#[derive(Debug, Clone, Default)]
+pub struct Units {
+    table: HashMap<String, Unit>,
+}
+
+impl Units {
+    /// Creates a table without any units.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a table with the SI base units and common derived, prefixed
+    /// and imperial units.
+    pub fn builtin() -> Self {
+        let mut units = Self::new();
+        for name in BASE_UNITS.iter() {
+            let unit = Unit::base(Dimension::base(name).unwrap());
+            units.table.insert(name.to_string(), unit.named(name));
+        }
+        let derived = [
+            ("km", 1e3, "m"),
+            ("cm", 1e-2, "m"),
+            ("mm", 1e-3, "m"),
+            ("um", 1e-6, "m"),
+            ("nm", 1e-9, "m"),
+            ("in", 0.0254, "m"),
+            ("ft", 0.3048, "m"),
+            ("yd", 0.9144, "m"),
+            ("mi", 1609.344, "m"),
+            ("g", 1e-3, "kg"),
+            ("mg", 1e-6, "kg"),
+            ("t", 1e3, "kg"),
+            ("lb", 0.453_592_37, "kg"),
+            ("ms", 1e-3, "s"),
+            ("min", 60.0, "s"),
+            ("h", 3600.0, "s"),
+            ("d", 86400.0, "s"),
+            ("L", 1e-3, "m^3"),
+            ("Hz", 1.0, "s^-1"),
+            ("N", 1.0, "kg*m/s^2"),
+            ("kN", 1e3, "N"),
+            ("Pa", 1.0, "N/m^2"),
+            ("kPa", 1e3, "Pa"),
+            ("J", 1.0, "N*m"),
+            ("kJ", 1e3, "J"),
+            ("W", 1.0, "J/s"),
+            ("kW", 1e3, "W"),
+            ("C", 1.0, "A*s"),
+            ("V", 1.0, "W/A"),
+        ];
+        for &(name, factor, unit) in derived.iter() {
+            units.define(name, factor, unit);
+        }
+        units
+    }
+
+    /// Defines `name` as `factor` times the unit expression `unit`, e.g.
+    /// `define("ft", 0.3048, "m")`. Returns `false` if `unit` is unknown.
+    pub fn define(&mut self, name: &str, factor: f64, unit: &str) -> bool {
+        match self.parse(unit) {
+            Some(unit) => {
+                let unit = Unit::new(factor * unit.factor, unit.dimension).named(name);
+                self.table.insert(name.to_string(), unit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Unit> {
+        self.table.get(name)
+    }
+
+    /// Returns the unit names in alphabetical order.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.table.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Parses a unit expression such as `m/s^2`.
+    pub fn parse(&self, expr: &str) -> Option<Unit> {
+        let mut unit = Unit::base(Dimension::default());
+        let mut sign = 1;
+        let mut rest = expr;
+        loop {
+            let end = rest.find(['*', '/']).unwrap_or(rest.len());
+            let (term, tail) = rest.split_at(end);
+            let (name, exponent) = match term.find('^') {
+                Some(i) => (&term[..i], term[i + 1..].parse().ok()?),
+                None => (term, 1),
+            };
+            let factor = self.table.get(name)?.powi(exponent)?;
+            unit = unit.combine(&factor, sign)?;
+            match tail.chars().next() {
+                Some(op) => {
+                    sign = if op == '*' { 1 } else { -1 };
+                    rest = &tail[1..];
+                }
+                None => break,
+            }
+        }
+        Some(unit.named(expr))
+    }
+
+    /// Parses a literal: a number directly followed by a unit expression,
+    /// such as `9.8m/s^2`, or a plain number.
+    pub fn quantity(&self, token: &str) -> Option<Quantity> {
+        if let Ok(value) = token.parse() {
+            return Some(Quantity::dimensionless(value));
+        }
+        token
+            .char_indices()
+            .filter(|&(i, c)| i > 0 && c.is_alphabetic())
+            .find_map(|(i, _)| {
+                let value = token[..i].parse().ok()?;
+                Some(Quantity::new(value, self.parse(&token[i..])?))
+            })
+    }
+
+    /// Makes `evaluator` read literals with these units and understand
+    /// conversion words such as `km->m` between any two unit expressions.
+    pub fn install(self, evaluator: &mut Evaluator<Quantity>) {
+        let units = Rc::new(self);
+        let parser = Rc::clone(&units);
+        evaluator.set_parser(move |token| parser.quantity(token));
+        evaluator.operators_mut().set_fallback(move |name| {
+            let arrow = name.find("->")?;
+            let from = units.parse(&name[..arrow])?;
+            let to = units.parse(&name[arrow + 2..])?;
+            Some(Operator::Unary(Rc::new(move |x: Quantity| {
+                x.convert(&from, &to)
+            })))
+        });
+    }
+
+    /// Creates an evaluator with the builtin operators and these units.
+    pub fn evaluator(self) -> Evaluator<Quantity> {
+        let mut evaluator = Evaluator::new();
+        self.install(&mut evaluator);
+        evaluator
+    }
+}
+
+thread_local! {
+    static BUILTIN: Units = Units::builtin();
+}
+
+/// A number with a unit.
+///
+/// Adding or comparing quantities needs equal dimensions, while multiplying
+/// and dividing combine them. A result keeps the unit of its operands when
+/// they agree and is given in SI base units otherwise, so `3km 2km +` is
+/// `5 km` but `1km 500m +` is `1500 m`.
+///
+/// [`Number::parse`] knows the [`Units::builtin`] units; use
+/// [`Units::install`] for other tables and for conversion words.
+#[derive(Debug, Clone)]
+pub struct Quantity {
+    value: f64,
+    unit: Unit,
+}
+
+impl Quantity {
+    pub fn new(value: f64, unit: Unit) -> Self {
+        if unit.dimension.is_dimensionless() {
+            Self::dimensionless(value * unit.factor)
+        } else {
+            Self { value, unit }
+        }
+    }
+
+    pub fn dimensionless(value: f64) -> Self {
+        Self::base(value, Dimension::default())
+    }
+
+    fn base(value: f64, dimension: Dimension) -> Self {
+        Self {
+            value,
+            unit: Unit::base(dimension),
+        }
+    }
+
+    /// The value in [`Quantity::unit`].
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn unit(&self) -> &Unit {
+        &self.unit
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        self.unit.dimension
+    }
+
+    /// The value in SI base units.
+    pub fn si(&self) -> f64 {
+        self.value * self.unit.factor
+    }
+
+    /// Expresses the quantity in `to`. A plain number is taken to be in `from`.
+    pub fn convert(&self, from: &Unit, to: &Unit) -> Result<Self, ArithError> {
+        if from.dimension != to.dimension {
+            return Err(ArithError::IncompatibleUnits);
+        }
+        let si = if self.dimension().is_dimensionless() {
+            self.value * from.factor
+        } else if self.dimension() == from.dimension {
+            self.si()
+        } else {
+            return Err(ArithError::IncompatibleUnits);
+        };
+        Ok(Self::new(si / to.factor, to.clone()))
+    }
+
+    fn same_dimension(&self, rhs: &Self) -> Result<(), ArithError> {
+        if self.dimension() == rhs.dimension() {
+            Ok(())
+        } else {
+            Err(ArithError::IncompatibleUnits)
+        }
+    }
+
+    /// Applies `fun` to both values in the common unit, or in SI units.
+    fn additive<F>(&self, rhs: &Self, fun: F) -> Result<Self, ArithError>
+    where
+        F: Fn(f64, f64) -> f64,
+    {
+        self.same_dimension(rhs)?;
+        if self.unit == rhs.unit {
+            Ok(Self::new(fun(self.value, rhs.value), self.unit.clone()))
+        } else {
+            Ok(Self::base(fun(self.si(), rhs.si()), self.dimension()))
+        }
+    }
+
+    fn multiplicative(&self, rhs: &Self, sign: i8) -> Result<Self, ArithError> {
+        let value = if sign > 0 {
+            self.value * rhs.value
+        } else {
+            self.value / rhs.value
+        };
+        if rhs.dimension().is_dimensionless() {
+            return Ok(Self::new(value, self.unit.clone()));
+        }
+        if self.dimension().is_dimensionless() && sign > 0 {
+            return Ok(Self::new(value, rhs.unit.clone()));
+        }
+        let unit = self
+            .unit
+            .combine(&rhs.unit, sign)
+            .ok_or(ArithError::Overflow)?;
+        Ok(Self::base(value * unit.factor, unit.dimension))
+    }
+}
+
+impl PartialEq for Quantity {
+    fn eq(&self, other: &Self) -> bool {
+        self.dimension() == other.dimension() && self.si() == other.si()
+    }
+}
+
+/// Quantities of different dimensions are unordered.
+impl PartialOrd for Quantity {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.dimension() == other.dimension() {
+            self.si().partial_cmp(&other.si())
+        } else {
+            None
+        }
+    }
+}
+
+/// Prints the value, honouring the precision, followed by the unit.
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.value, f)?;
+        match self.unit.symbol() {
+            Some(symbol) => write!(f, " {}", symbol),
+            None if !self.dimension().is_dimensionless() => write!(f, " {}", self.dimension()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Number for Quantity {
+    fn parse(token: &str) -> Option<Self> {
+        BUILTIN.with(|units| units.quantity(token))
+    }
+
+    fn from_i64(n: i64) -> Self {
+        Self::dimensionless(n as f64)
+    }
+
+    /// Writes the unit right after the value, as literals are read.
+    fn to_source(&self) -> String {
+        self.to_string().replacen(' ', "", 1)
+    }
+
+    fn from_f64(x: f64) -> Option<Self> {
+        Some(Self::dimensionless(x))
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.si()
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.additive(rhs, |x, y| x + y)
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.additive(rhs, |x, y| x - y)
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.multiplicative(rhs, 1)
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.multiplicative(rhs, -1)
+    }
+
+    fn rem(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.additive(rhs, |x, y| x % y)
+    }
+
+    fn neg(&self) -> Result<Self, ArithError> {
+        Ok(Self::new(-self.value, self.unit.clone()))
+    }
+
+    fn abs(&self) -> Result<Self, ArithError> {
+        Ok(Self::new(self.value.abs(), self.unit.clone()))
+    }
+
+    /// Raises to a plain number, which must be a small integer unless the
+    /// base is a plain number too.
+    fn pow(&self, rhs: &Self) -> Result<Self, ArithError> {
+        if !rhs.dimension().is_dimensionless() {
+            return Err(ArithError::IncompatibleUnits);
+        }
+        if self.dimension().is_dimensionless() {
+            return Ok(Self::dimensionless(self.value.powf(rhs.value)));
+        }
+        let exponent = rhs.value;
+        if exponent.fract() != 0.0 || exponent.abs() > i8::MAX as f64 {
+            return Err(ArithError::IncompatibleUnits);
+        }
+        let unit = self.unit.powi(exponent as i8).ok_or(ArithError::Overflow)?;
+        Ok(Self::base(
+            self.value.powi(exponent as i32) * unit.factor,
+            unit.dimension,
+        ))
+    }
+
+    /// Only plain numbers go through functions such as `sqrt` or `sin`.
+    fn map_f64<F>(&self, fun: F) -> Result<Self, ArithError>
+    where
+        F: Fn(f64) -> f64,
+    {
+        if self.dimension().is_dimensionless() {
+            Ok(Self::dimensionless(fun(self.value)))
+        } else {
+            Err(ArithError::IncompatibleUnits)
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        self.value != 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dimension, Quantity, Units};
+    use crate::error::{ArithError, RpnError};
+    use crate::number::Number;
+
+    fn eval(exp: &str) -> Result<String, RpnError> {
+        Units::builtin()
+            .evaluator()
+            .eval(exp)
+            .map(|q| q.to_string())
+    }
+
+    #[test]
+    fn literals() {
+        let g = Quantity::parse("9.8m/s^2").unwrap();
+        assert_eq!(g.value(), 9.8);
+        assert_eq!(g.unit().symbol(), Some("m/s^2"));
+        assert_eq!(g.dimension().to_string(), "m/s^2");
+        assert_eq!(Quantity::parse("3kg").unwrap().to_string(), "3 kg");
+        assert_eq!(Quantity::parse("2.5e3m").unwrap().si(), 2500.0);
+        assert_eq!(Quantity::parse("1.5").unwrap().to_string(), "1.5");
+        assert_eq!(Quantity::parse("3furlong"), None);
+        assert_eq!(Quantity::parse("kg"), None);
+        assert_eq!(
+            Dimension::base("s").unwrap().powi(-1).unwrap().to_string(),
+            "s^-1"
+        );
+    }
+
+    #[test]
+    fn dimensions_are_checked() {
+        assert_eq!(
+            eval("1m 1s +"),
+            Err(RpnError::Arithmetic {
+                operator: "+".to_string(),
+                error: ArithError::IncompatibleUnits,
+            })
+        );
+        assert_eq!(eval("2m 3s *"), Ok("6 m*s".to_string()));
+        assert_eq!(eval("9.8m/s^2 2kg *"), Ok("19.6 kg*m/s^2".to_string()));
+        assert_eq!(eval("3km 2km +"), Ok("5 km".to_string()));
+        assert_eq!(eval("1km 500m +"), Ok("1500 m".to_string()));
+        assert_eq!(eval("3km 2 *"), Ok("6 km".to_string()));
+        assert_eq!(eval("10m 2s / 4s *"), Ok("20 m".to_string()));
+        assert_eq!(eval("3m 2 ^"), Ok("9 m^2".to_string()));
+        assert_eq!(eval("1km 1m /"), Ok("1000".to_string()));
+        assert_eq!(eval("2m 1m >"), Ok("1".to_string()));
+        assert_eq!(
+            eval("4m sqrt").unwrap_err().to_string(),
+            "sqrt: incompatible units"
+        );
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(eval("3 km->m"), Ok("3000 m".to_string()));
+        assert_eq!(eval("1500m m->km"), Ok("1.5 km".to_string()));
+        assert_eq!(eval("36km/h km/h->m/s"), Ok("10 m/s".to_string()));
+        assert_eq!(eval("1h 30min + s->min"), Ok("90 min".to_string()));
+        assert_eq!(
+            eval("1kg km->m").unwrap_err().to_string(),
+            "km->m: incompatible units"
+        );
+        assert_eq!(
+            eval("1 km->s").unwrap_err().to_string(),
+            "km->s: incompatible units"
+        );
+        assert!(matches!(
+            eval("1 km->parsec"),
+            Err(RpnError::UnknownToken { .. })
+        ));
+    }
+
+    #[test]
+    fn callers_extend_the_table() {
+        let mut units = Units::builtin();
+        assert!(units.define("furlong", 201.168, "m"));
+        assert!(units.define("fortnight", 14.0, "d"));
+        assert!(!units.define("parsec", 3.0857e16, "lightyear"));
+        let mut evaluator = units.evaluator();
+        let speed = evaluator
+            .eval("1furlong/fortnight furlong/fortnight->mm/min")
+            .unwrap();
+        assert_eq!(speed.unit().symbol(), Some("mm/min"));
+        assert!((speed.value() - 201_168.0 / (14.0 * 24.0 * 60.0)).abs() < 1e-9);
+    }
+}
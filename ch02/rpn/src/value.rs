@@ -0,0 +1,508 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::error::ArithError;
+use crate::number::Number;
+use crate::ops::Operators;
+
+/// A complex number with `f64` parts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    /// The modulus.
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// The angle to the positive real axis, in `-π..=π`.
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    fn from_polar(r: f64, theta: f64) -> Self {
+        Self::new(r * theta.cos(), r * theta.sin())
+    }
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+
+    fn div(self, rhs: Self) -> Self {
+        let norm = rhs.re * rhs.re + rhs.im * rhs.im;
+        let num = self.mul(rhs.conj());
+        Self::new(num.re / norm, num.im / norm)
+    }
+
+    /// The principal square root.
+    pub fn sqrt(self) -> Self {
+        Self::from_polar(self.abs().sqrt(), self.arg() / 2.0)
+    }
+
+    pub fn exp(self) -> Self {
+        Self::from_polar(self.re.exp(), self.im)
+    }
+
+    /// The principal natural logarithm.
+    pub fn ln(self) -> Self {
+        Self::new(self.abs().ln(), self.arg())
+    }
+
+    /// Raises to `rhs`, exactly by repeated multiplication when `rhs` is a
+    /// small integer.
+    pub fn pow(self, rhs: Self) -> Self {
+        if rhs.im == 0.0 && rhs.re.fract() == 0.0 && rhs.re.abs() <= u32::MAX as f64 {
+            let mut result = Self::new(1.0, 0.0);
+            let mut base = self;
+            let mut exp = rhs.re.abs() as u32;
+            while exp > 0 {
+                if exp & 1 == 1 {
+                    result = result.mul(base);
+                }
+                base = base.mul(base);
+                exp >>= 1;
+            }
+            return if rhs.re < 0.0 {
+                Self::new(1.0, 0.0).div(result)
+            } else {
+                result
+            };
+        }
+        if self.re == 0.0 && self.im == 0.0 {
+            return self;
+        }
+        rhs.mul(self.ln()).exp()
+    }
+}
+
+/// Prints `3+4i`, applying the precision to both parts.
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.im.is_sign_negative() { '-' } else { '+' };
+        match f.precision() {
+            Some(p) => write!(f, "{:.*}{}{:.*}i", p, self.re, sign, p, self.im.abs()),
+            None => write!(f, "{}{}{}i", self.re, sign, self.im.abs()),
+        }
+    }
+}
+
+/// A stack value of the `value` number type: a real, a complex number or a
+/// vector of values.
+///
+/// Arithmetic promotes reals to complex numbers and applies element by
+/// element to vectors, where a scalar operand is used with every element.
+/// Operands that do not fit, such as vectors of different lengths or the
+/// remainder of complex numbers, fail with [`ArithError::TypeMismatch`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Real(f64),
+    Complex(Complex),
+    Vector(Vec<Value>),
+}
+
+impl Value {
+    /// Creates a table with the builtin operators and `arg`, `conj` and
+    /// `dot`. `abs`, `sqrt`, `ln` and `exp` accept complex numbers and
+    /// ordering comparisons only reals.
+    pub fn operators() -> Operators<Value> {
+        let mut ops = Operators::builtin();
+        ops.register_unary("arg", |x: Value| {
+            x.map(&|x| Ok(Value::Real(x.complex()?.arg())))
+        });
+        ops.register_unary("conj", |x: Value| {
+            x.map(&|x| match x {
+                Value::Complex(z) => Ok(Value::Complex(z.conj())),
+                x => Ok(x.clone()),
+            })
+        });
+        ops.register_unary("sqrt", |x: Value| {
+            x.map(&|x| match x {
+                Value::Real(x) if *x >= 0.0 => Ok(Value::Real(x.sqrt())),
+                x => Ok(Value::Complex(x.complex()?.sqrt())),
+            })
+        });
+        ops.register_unary("ln", |x: Value| {
+            x.map(&|x| match x {
+                Value::Real(x) if *x >= 0.0 => Ok(Value::Real(x.ln())),
+                x => Ok(Value::Complex(x.complex()?.ln())),
+            })
+        });
+        ops.register_unary("exp", |x: Value| {
+            x.map(&|x| match x {
+                Value::Real(x) => Ok(Value::Real(x.exp())),
+                x => Ok(Value::Complex(x.complex()?.exp())),
+            })
+        });
+        ops.register_binary("dot", |x, y| match (&x, &y) {
+            (Value::Vector(xs), Value::Vector(ys)) if xs.len() == ys.len() => xs
+                .iter()
+                .zip(ys)
+                .try_fold(Value::Real(0.0), |sum, (x, y)| sum.add(&x.mul(y)?)),
+            _ => Err(ArithError::TypeMismatch),
+        });
+        ops.register_binary("<", |x, y| Ok(Value::from_bool(x.real()? < y.real()?)));
+        ops.register_binary("<=", |x, y| Ok(Value::from_bool(x.real()? <= y.real()?)));
+        ops.register_binary(">", |x, y| Ok(Value::from_bool(x.real()? > y.real()?)));
+        ops.register_binary(">=", |x, y| Ok(Value::from_bool(x.real()? >= y.real()?)));
+        ops.register_binary("min", |x, y| Ok(Value::Real(x.real()?.min(y.real()?))));
+        ops.register_binary("max", |x, y| Ok(Value::Real(x.real()?.max(y.real()?))));
+        ops
+    }
+
+    fn real(&self) -> Result<f64, ArithError> {
+        match self {
+            Value::Real(x) => Ok(*x),
+            _ => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    fn complex(&self) -> Result<Complex, ArithError> {
+        match self {
+            Value::Real(x) => Ok(Complex::new(*x, 0.0)),
+            Value::Complex(z) => Ok(*z),
+            Value::Vector(_) => Err(ArithError::TypeMismatch),
+        }
+    }
+
+    /// Applies `fun` to every scalar.
+    fn map(&self, fun: &dyn Fn(&Value) -> Result<Value, ArithError>) -> Result<Value, ArithError> {
+        match self {
+            Value::Vector(items) => items
+                .iter()
+                .map(|item| item.map(fun))
+                .collect::<Result<_, _>>()
+                .map(Value::Vector),
+            scalar => fun(scalar),
+        }
+    }
+
+    /// Applies `fun` to pairs of scalars, pairing a scalar with every
+    /// element of a vector.
+    fn zip(
+        &self,
+        rhs: &Value,
+        fun: &dyn Fn(&Value, &Value) -> Result<Value, ArithError>,
+    ) -> Result<Value, ArithError> {
+        let items: Result<Vec<Value>, ArithError> = match (self, rhs) {
+            (Value::Vector(xs), Value::Vector(ys)) if xs.len() != ys.len() => {
+                return Err(ArithError::TypeMismatch)
+            }
+            (Value::Vector(xs), Value::Vector(ys)) => {
+                xs.iter().zip(ys).map(|(x, y)| x.zip(y, fun)).collect()
+            }
+            (Value::Vector(xs), y) => xs.iter().map(|x| x.zip(y, fun)).collect(),
+            (x, Value::Vector(ys)) => ys.iter().map(|y| x.zip(y, fun)).collect(),
+            (x, y) => return fun(x, y),
+        };
+        items.map(Value::Vector)
+    }
+
+    /// Applies `real` to two reals and `complex` to anything else.
+    fn arith(
+        &self,
+        rhs: &Value,
+        real: fn(f64, f64) -> f64,
+        complex: fn(Complex, Complex) -> Complex,
+    ) -> Result<Value, ArithError> {
+        self.zip(rhs, &|x, y| match (x, y) {
+            (Value::Real(x), Value::Real(y)) => Ok(Value::Real(real(*x, *y))),
+            (x, y) => Ok(Value::Complex(complex(x.complex()?, y.complex()?))),
+        })
+    }
+}
+
+/// Parses `3+4i`, `-2.5i` or `1e3-1i`. A lone `i` is no number.
+fn parse_complex(token: &str) -> Option<Complex> {
+    let body = token.strip_suffix('i')?;
+    if body.is_empty() {
+        return None;
+    }
+    let split = body
+        .char_indices()
+        .rev()
+        .find(|&(i, c)| i > 0 && (c == '+' || c == '-') && !body[..i].ends_with(['e', 'E']))
+        .map(|(i, _)| i);
+    let (re, im) = match split {
+        Some(i) => (body[..i].parse().ok()?, &body[i..]),
+        None => (0.0, body),
+    };
+    let im = match im {
+        "+" => 1.0,
+        "-" => -1.0,
+        im => im.parse().ok()?,
+    };
+    Some(Complex::new(re, im))
+}
+
+/// Only reals are ordered.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Real(x), Value::Real(y)) => x.partial_cmp(y),
+            _ => None,
+        }
+    }
+}
+
+/// Prints vectors as `[1 2 3]`, applying the precision to every element.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Real(x) => fmt::Display::fmt(x, f),
+            Value::Complex(z) => fmt::Display::fmt(z, f),
+            Value::Vector(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    match f.precision() {
+                        Some(p) => write!(f, "{:.*}", p, item)?,
+                        None => write!(f, "{}", item)?,
+                    }
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+impl Number for Value {
+    fn parse(token: &str) -> Option<Self> {
+        match token.parse() {
+            Ok(x) => Some(Value::Real(x)),
+            Err(_) => parse_complex(token).map(Value::Complex),
+        }
+    }
+
+    fn from_i64(n: i64) -> Self {
+        Value::Real(n as f64)
+    }
+
+    fn from_f64(x: f64) -> Option<Self> {
+        Some(Value::Real(x))
+    }
+
+    /// Complex numbers and vectors have no `f64` value and give NaN.
+    fn to_f64(&self) -> f64 {
+        self.real().unwrap_or(f64::NAN)
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.arith(rhs, |x, y| x + y, Complex::add)
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.arith(rhs, |x, y| x - y, Complex::sub)
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.arith(rhs, |x, y| x * y, Complex::mul)
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.arith(rhs, |x, y| x / y, Complex::div)
+    }
+
+    fn rem(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.zip(rhs, &|x, y| Ok(Value::Real(x.real()? % y.real()?)))
+    }
+
+    fn neg(&self) -> Result<Self, ArithError> {
+        self.map(&|x| match x {
+            Value::Complex(z) => Ok(Value::Complex(Complex::new(-z.re, -z.im))),
+            x => Ok(Value::Real(-x.real()?)),
+        })
+    }
+
+    /// The absolute value of reals and the modulus of complex numbers.
+    fn abs(&self) -> Result<Self, ArithError> {
+        self.map(&|x| Ok(Value::Real(x.complex()?.abs())))
+    }
+
+    fn pow(&self, rhs: &Self) -> Result<Self, ArithError> {
+        self.arith(rhs, f64::powf, Complex::pow)
+    }
+
+    /// Applies `fun` to reals; complex numbers are a type mismatch.
+    fn map_f64<F>(&self, fun: F) -> Result<Self, ArithError>
+    where
+        F: Fn(f64) -> f64,
+    {
+        self.map(&|x| Ok(Value::Real(fun(x.real()?))))
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Real(x) => *x != 0.0,
+            Value::Complex(z) => z.re != 0.0 || z.im != 0.0,
+            Value::Vector(items) => !items.is_empty(),
+        }
+    }
+
+    fn from_vector(items: Vec<Self>) -> Option<Self> {
+        Some(Value::Vector(items))
+    }
+
+    fn to_vector(&self) -> Option<Vec<Self>> {
+        match self {
+            Value::Vector(items) => Some(items.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Complex, Value};
+    use crate::error::{ArithError, RpnError};
+    use crate::eval::Evaluator;
+    use crate::number::Number;
+
+    fn eval(exp: &str) -> Result<String, RpnError> {
+        let mut evaluator = Evaluator::with_operators(Value::operators());
+        evaluator.define("sq", "dup *").unwrap();
+        evaluator.eval(exp).map(|value| value.to_string())
+    }
+
+    fn type_mismatch(operator: &str) -> Result<String, RpnError> {
+        Err(RpnError::Arithmetic {
+            operator: operator.to_string(),
+            error: ArithError::TypeMismatch,
+        })
+    }
+
+    #[test]
+    fn complex_literals() {
+        let parse = |s| match Value::parse(s) {
+            Some(Value::Complex(z)) => Some((z.re, z.im)),
+            _ => None,
+        };
+        assert_eq!(parse("3+4i"), Some((3.0, 4.0)));
+        assert_eq!(parse("3-4i"), Some((3.0, -4.0)));
+        assert_eq!(parse("-2.5i"), Some((0.0, -2.5)));
+        assert_eq!(parse("1e3-i"), Some((1000.0, -1.0)));
+        assert_eq!(parse("1e-3+2e-3i"), Some((0.001, 0.002)));
+        assert_eq!(parse("i"), None);
+        assert_eq!(parse("pi"), None);
+        assert_eq!(Value::parse("2"), Some(Value::Real(2.0)));
+    }
+
+    #[test]
+    fn complex_arithmetic() {
+        assert_eq!(eval("3+4i abs"), Ok("5".to_string()));
+        assert_eq!(eval("3+4i conj"), Ok("3-4i".to_string()));
+        assert_eq!(eval("3+4i 3-4i *"), Ok("25+0i".to_string()));
+        assert_eq!(eval("1+2i 2 +"), Ok("3+2i".to_string()));
+        assert_eq!(eval("3+4i 2 ^"), Ok("-7+24i".to_string()));
+        let root = Evaluator::with_operators(Value::operators()).eval("-4 sqrt");
+        assert_eq!(
+            root.map(|z| format!("{:.3}", z)),
+            Ok("0.000+2.000i".to_string())
+        );
+        assert_eq!(eval("-1 arg"), Ok(std::f64::consts::PI.to_string()));
+        assert_eq!(Complex::new(0.0, 1.0).arg(), std::f64::consts::FRAC_PI_2);
+        assert_eq!(eval("1+i 2 %"), type_mismatch("%"));
+        assert_eq!(eval("1+i 2 <"), type_mismatch("<"));
+        assert_eq!(eval("1i sin"), type_mismatch("sin"));
+    }
+
+    #[test]
+    fn vectors() {
+        assert_eq!(eval("[1 2 3]"), Ok("[1 2 3]".to_string()));
+        assert_eq!(eval("[1 2 + [4 5]]"), Ok("[3 [4 5]]".to_string()));
+        assert_eq!(eval("[1 2 3] [4 5 6] +"), Ok("[5 7 9]".to_string()));
+        assert_eq!(eval("[1 2 3] 2 *"), Ok("[2 4 6]".to_string()));
+        assert_eq!(eval("[1 2 3] sum"), Ok("6".to_string()));
+        assert_eq!(eval("[1 2 3] [4 5 6] dot"), Ok("32".to_string()));
+        assert_eq!(eval("[1 2 3] 'sq map"), Ok("[1 4 9]".to_string()));
+        assert_eq!(eval("[1 -2 3+4i] 'abs map"), Ok("[1 2 5]".to_string()));
+        assert_eq!(eval("[1 2 3 4] mean"), Ok("2.5".to_string()));
+        assert_eq!(eval("[3 1 2] sort"), Ok("[1 2 3]".to_string()));
+        assert_eq!(eval("[] sum"), Ok("0".to_string()));
+        assert_eq!(eval("[] prod"), Ok("1".to_string()));
+        assert_eq!(eval("[] count"), Ok("0".to_string()));
+        assert_eq!(
+            eval("[] mean"),
+            Err(RpnError::StackUnderflow {
+                operator: "mean".to_string(),
+            })
+        );
+        assert_eq!(eval("[1 2] [1 2 3] +"), type_mismatch("+"));
+        assert_eq!(eval("[1 2] [1 2 3] dot"), type_mismatch("dot"));
+        assert_eq!(eval("1 2 3 sum"), Ok("6".to_string()));
+        assert_eq!(eval("1 2+i 3 sum"), Ok("6+1i".to_string()));
+        assert_eq!(eval("3 'sq map"), type_mismatch("map"));
+        assert_eq!(
+            eval("[1 2] 'drop map"),
+            Err(RpnError::StackUnderflow {
+                operator: "map".to_string(),
+            })
+        );
+        for exp in &[
+            "1 2 [ + ]",
+            "1 [ 2 + ] drop",
+            "5 [ drop 7 ]",
+            "1 [ 2 1 roll ]",
+            "1 [ 3 sum ]",
+        ] {
+            assert_eq!(
+                eval(exp),
+                Err(RpnError::StackUnderflow {
+                    operator: "[".to_string(),
+                }),
+                "{}",
+                exp
+            );
+        }
+        assert_eq!(eval("1 [ 2 dup * ] +"), Ok("[5]".to_string()));
+        assert_eq!(
+            eval("[1 2"),
+            Err(RpnError::UnmatchedControl {
+                word: "[".to_string(),
+                offset: 0,
+            })
+        );
+        assert_eq!(
+            eval("1 ]"),
+            Err(RpnError::UnmatchedControl {
+                word: "]".to_string(),
+                offset: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn other_types_have_no_vectors() {
+        let mut evaluator: Evaluator = Evaluator::new();
+        assert_eq!(
+            evaluator.eval("[1 2]"),
+            Err(RpnError::Arithmetic {
+                operator: "[".to_string(),
+                error: ArithError::NotRepresentable,
+            })
+        );
+    }
+}
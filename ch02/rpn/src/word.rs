@@ -0,0 +1,422 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::error::ArithError;
+use crate::eval::Evaluator;
+use crate::number::Number;
+use crate::ops::Operators;
+
+/// The width and signedness of a machine word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WordSize {
+    bits: u32,
+    signed: bool,
+}
+
+impl WordSize {
+    pub const I64: WordSize = WordSize {
+        bits: 64,
+        signed: true,
+    };
+
+    /// Returns the size of `bits`-bit words, which must be 8, 16, 32 or 64.
+    pub fn new(bits: u32, signed: bool) -> Option<Self> {
+        match bits {
+            8 | 16 | 32 | 64 => Some(Self { bits, signed }),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.signed
+    }
+
+    fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.bits)
+    }
+
+    /// Reads the low bits of `raw` as a number of this size.
+    fn value(&self, raw: u64) -> i128 {
+        let raw = raw & self.mask();
+        if self.signed && raw >> (self.bits - 1) == 1 {
+            raw as i128 - (1i128 << self.bits)
+        } else {
+            raw as i128
+        }
+    }
+}
+
+/// Prints `i8`, `u32` and so on.
+impl fmt::Display for WordSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", if self.signed { 'i' } else { 'u' }, self.bits)
+    }
+}
+
+/// A machine word for programmer mode: an integer of a fixed size whose
+/// arithmetic wraps around, printed in a chosen base.
+///
+/// Literals may be decimal, `0x`, `0o` or `0b` prefixed, or `BASErDIGITS`
+/// for any base from 2 to 36, with `_` between digits, e.g. `0xdead_beef`
+/// or `36rzz`. Values that do not fit are wrapped to the word size.
+///
+/// Decimal output shows the value, other bases show the bits, so an `i8`
+/// holding -1 prints as `-1` or `0xff`. When operands differ in size, the
+/// narrower one decides the size and base of the result, and on a tie the
+/// left one does.
+#[derive(Debug, Clone, Copy)]
+pub struct Word {
+    raw: u64,
+    size: WordSize,
+    base: u32,
+}
+
+impl Word {
+    /// Wraps `value` to `size`, to be printed in `base` (2 to 36).
+    pub fn new(value: i128, size: WordSize, base: u32) -> Self {
+        assert!((2..=36).contains(&base), "base out of range: {}", base);
+        Self {
+            raw: value as u64 & size.mask(),
+            size,
+            base,
+        }
+    }
+
+    pub fn value(&self) -> i128 {
+        self.size.value(self.raw)
+    }
+
+    /// The bits of the word, zero-extended.
+    pub fn bits(&self) -> u64 {
+        self.raw
+    }
+
+    pub fn size(&self) -> WordSize {
+        self.size
+    }
+
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    /// Converts to `size`, wrapping the value if it does not fit.
+    pub fn with_size(&self, size: WordSize) -> Self {
+        Self::new(self.value(), size, self.base)
+    }
+
+    pub fn with_base(&self, base: u32) -> Self {
+        Self::new(self.value(), self.size, base)
+    }
+
+    /// Parses a literal of `size`, to be printed in `base`.
+    pub fn parse_as(token: &str, size: WordSize, base: u32) -> Option<Self> {
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+        let (radix, digits) = match digits.get(..2) {
+            Some("0x") | Some("0X") => (16, &digits[2..]),
+            Some("0o") | Some("0O") => (8, &digits[2..]),
+            Some("0b") | Some("0B") => (2, &digits[2..]),
+            _ => match digits.find('r') {
+                Some(i) => (digits[..i].parse().ok()?, &digits[i + 1..]),
+                None => (10, digits),
+            },
+        };
+        if !(2..=36).contains(&radix)
+            || digits.is_empty()
+            || digits.starts_with(&['_', '+'][..])
+            || digits.ends_with('_')
+        {
+            return None;
+        }
+        let digits: String = digits.chars().filter(|&c| c != '_').collect();
+        let magnitude = u64::from_str_radix(&digits, radix).ok()? as i128;
+        let value = if negative { -magnitude } else { magnitude };
+        Some(Self::new(value, size, base))
+    }
+
+    /// Creates a table with the builtin operators, the bitwise `and`, `or`,
+    /// `xor`, `not`, `shl` and `shr`, the conversions `i8` to `u64`, and
+    /// `hex`, `dec`, `oct`, `bin` and `base` to choose how values print.
+    pub fn operators() -> Operators<Word> {
+        let mut ops: Operators<Word> = Operators::builtin();
+        ops.register_binary("and", |x, y| Ok(x.bitwise(&y, x.raw & y.raw)));
+        ops.register_binary("or", |x, y| Ok(x.bitwise(&y, x.raw | y.raw)));
+        ops.register_binary("xor", |x, y| Ok(x.bitwise(&y, x.raw ^ y.raw)));
+        ops.register_unary("not", |x| Ok(Word::new(!x.raw as i128, x.size, x.base)));
+        ops.register_binary("shl", |x, y| x.shift(&y, true));
+        ops.register_binary("shr", |x, y| x.shift(&y, false));
+        for &bits in &[8, 16, 32, 64] {
+            for &signed in &[true, false] {
+                let size = WordSize::new(bits, signed).unwrap();
+                ops.register_unary(&size.to_string(), move |x| Ok(x.with_size(size)));
+            }
+        }
+        for &(name, base) in &[("hex", 16), ("dec", 10), ("oct", 8), ("bin", 2)] {
+            ops.register_unary(name, move |x| Ok(x.with_base(base)));
+        }
+        ops.register_binary("base", |x, y| match y.value() {
+            base @ 2..=36 => Ok(x.with_base(base as u32)),
+            _ => Err(ArithError::NotRepresentable),
+        });
+        ops
+    }
+
+    /// Creates an evaluator reading literals as words of `size` printed in
+    /// `base`, with [`Word::operators`].
+    pub fn evaluator(size: WordSize, base: u32) -> Evaluator<Word> {
+        let mut evaluator = Evaluator::with_operators(Word::operators());
+        evaluator.set_parser(move |token| Word::parse_as(token, size, base));
+        evaluator
+    }
+
+    /// The size and base of a result of `self` and `rhs`.
+    fn mode(&self, rhs: &Self) -> (WordSize, u32) {
+        if rhs.size.bits < self.size.bits {
+            (rhs.size, rhs.base)
+        } else {
+            (self.size, self.base)
+        }
+    }
+
+    fn wrapping(&self, rhs: &Self, value: i128) -> Self {
+        let (size, base) = self.mode(rhs);
+        Self::new(value, size, base)
+    }
+
+    fn bitwise(&self, rhs: &Self, raw: u64) -> Self {
+        self.wrapping(rhs, raw as i128)
+    }
+
+    /// Shifts left, or right arithmetically for signed words and logically
+    /// for unsigned ones. Shifting by the word size or more leaves only the
+    /// sign.
+    fn shift(&self, rhs: &Self, left: bool) -> Result<Self, ArithError> {
+        let count = rhs.value();
+        if count < 0 {
+            return Err(ArithError::NotRepresentable);
+        }
+        let count = count.min(self.size.bits as i128) as u32;
+        let value = if left {
+            (self.raw as i128) << count
+        } else {
+            self.value() >> count
+        };
+        Ok(Self::new(value, self.size, self.base))
+    }
+}
+
+impl PartialEq for Word {
+    fn eq(&self, other: &Self) -> bool {
+        self.value() == other.value()
+    }
+}
+
+impl PartialOrd for Word {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.value().partial_cmp(&other.value())
+    }
+}
+
+/// Prints the value in decimal, otherwise the bits with a `0x`, `0o`, `0b`
+/// or `BASEr` prefix.
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.base {
+            10 => return write!(f, "{}", self.value()),
+            16 => return write!(f, "0x{:x}", self.raw),
+            8 => return write!(f, "0o{:o}", self.raw),
+            2 => return write!(f, "0b{:b}", self.raw),
+            _ => {}
+        }
+        let mut digits = Vec::new();
+        let mut raw = self.raw;
+        loop {
+            digits.push(std::char::from_digit((raw % self.base as u64) as u32, self.base).unwrap());
+            raw /= self.base as u64;
+            if raw == 0 {
+                break;
+            }
+        }
+        let digits: String = digits.into_iter().rev().collect();
+        write!(f, "{}r{}", self.base, digits)
+    }
+}
+
+impl Number for Word {
+    fn parse(token: &str) -> Option<Self> {
+        Self::parse_as(token, WordSize::I64, 10)
+    }
+
+    fn from_i64(n: i64) -> Self {
+        Self::new(n.into(), WordSize::I64, 10)
+    }
+
+    fn from_f64(x: f64) -> Option<Self> {
+        if x.fract() == 0.0 && x >= i64::MIN as f64 && x < u64::MAX as f64 {
+            Some(Self::new(x as i128, WordSize::I64, 10))
+        } else {
+            None
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.value() as f64
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(self.wrapping(rhs, self.value() + rhs.value()))
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(self.wrapping(rhs, self.value() - rhs.value()))
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, ArithError> {
+        Ok(self.wrapping(rhs, self.value().wrapping_mul(rhs.value())))
+    }
+
+    /// Divides rounding towards zero.
+    fn div(&self, rhs: &Self) -> Result<Self, ArithError> {
+        if rhs.raw == 0 {
+            return Err(ArithError::DivisionByZero);
+        }
+        Ok(self.wrapping(rhs, self.value() / rhs.value()))
+    }
+
+    fn rem(&self, rhs: &Self) -> Result<Self, ArithError> {
+        if rhs.raw == 0 {
+            return Err(ArithError::DivisionByZero);
+        }
+        Ok(self.wrapping(rhs, self.value() % rhs.value()))
+    }
+
+    fn neg(&self) -> Result<Self, ArithError> {
+        Ok(Self::new(-self.value(), self.size, self.base))
+    }
+
+    fn abs(&self) -> Result<Self, ArithError> {
+        Ok(Self::new(self.value().abs(), self.size, self.base))
+    }
+
+    /// Raises to a non-negative power, wrapping around.
+    fn pow(&self, rhs: &Self) -> Result<Self, ArithError> {
+        let exp = u32::try_from(rhs.value()).map_err(|_| ArithError::NotRepresentable)?;
+        let raw = self.raw.wrapping_pow(exp);
+        Ok(self.wrapping(rhs, raw as i128))
+    }
+
+    fn map_f64<F>(&self, fun: F) -> Result<Self, ArithError>
+    where
+        F: Fn(f64) -> f64,
+    {
+        let x = fun(self.to_f64());
+        let size = self.size;
+        let fits = x.fract() == 0.0 && size.value(x as i128 as u64) as f64 == x;
+        if fits {
+            Ok(Self::new(x as i128, size, self.base))
+        } else {
+            Err(ArithError::NotRepresentable)
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        self.raw != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Word, WordSize};
+    use crate::error::{ArithError, RpnError};
+    use crate::number::Number;
+
+    fn eval(bits: u32, signed: bool, exp: &str) -> Result<String, RpnError> {
+        let size = WordSize::new(bits, signed).unwrap();
+        Word::evaluator(size, 10).eval(exp).map(|w| w.to_string())
+    }
+
+    #[test]
+    fn literals() {
+        let parse = |s| Word::parse(s).map(|w| w.value());
+        assert_eq!(parse("0xff"), Some(255));
+        assert_eq!(parse("0o17"), Some(15));
+        assert_eq!(parse("0b1010_1010"), Some(170));
+        assert_eq!(parse("-0x10"), Some(-16));
+        assert_eq!(parse("36rzz"), Some(1295));
+        assert_eq!(parse("0xffff_ffff_ffff_ffff"), Some(-1));
+        assert_eq!(parse("0x"), None);
+        assert_eq!(parse("0xg"), None);
+        assert_eq!(parse("1.5"), None);
+        assert_eq!(parse("37r1"), None);
+        assert_eq!(parse("0x+1"), None);
+        assert_eq!(parse("x"), None);
+    }
+
+    #[test]
+    fn wraparound() {
+        assert_eq!(eval(8, false, "255 1 +"), Ok("0".to_string()));
+        assert_eq!(eval(8, true, "127 1 +"), Ok("-128".to_string()));
+        assert_eq!(eval(8, true, "0xff"), Ok("-1".to_string()));
+        assert_eq!(eval(16, false, "0 1 -"), Ok("65535".to_string()));
+        assert_eq!(eval(32, true, "2 31 ^"), Ok("-2147483648".to_string()));
+        assert_eq!(
+            eval(64, true, "0x7fff_ffff_ffff_ffff 1 +"),
+            Ok(i64::MIN.to_string())
+        );
+        assert_eq!(eval(8, true, "-128 -1 /"), Ok("-128".to_string()));
+        assert_eq!(eval(8, false, "200 100 >"), Ok("1".to_string()));
+        assert_eq!(eval(8, true, "200 100 >"), Ok("0".to_string()));
+        assert_eq!(
+            eval(8, false, "1 0 /"),
+            Err(RpnError::Arithmetic {
+                operator: "/".to_string(),
+                error: ArithError::DivisionByZero,
+            })
+        );
+    }
+
+    #[test]
+    fn bitwise() {
+        assert_eq!(
+            eval(8, false, "0b1100 0b1010 and bin"),
+            Ok("0b1000".to_string())
+        );
+        assert_eq!(eval(8, false, "0b1100 0b1010 or"), Ok("14".to_string()));
+        assert_eq!(eval(8, false, "0b1100 0b1010 xor"), Ok("6".to_string()));
+        assert_eq!(eval(8, false, "0 not hex"), Ok("0xff".to_string()));
+        assert_eq!(eval(8, true, "0 not"), Ok("-1".to_string()));
+        assert_eq!(eval(8, false, "1 7 shl"), Ok("128".to_string()));
+        assert_eq!(eval(8, false, "1 8 shl"), Ok("0".to_string()));
+        assert_eq!(eval(8, false, "0x80 7 shr"), Ok("1".to_string()));
+        assert_eq!(eval(8, true, "0x80 7 shr"), Ok("-1".to_string()));
+        assert_eq!(eval(8, true, "-64 100 shr"), Ok("-1".to_string()));
+    }
+
+    #[test]
+    fn sizes_and_bases() {
+        assert_eq!(eval(32, true, "-1 u8"), Ok("255".to_string()));
+        assert_eq!(eval(32, true, "300 u8 i8"), Ok("44".to_string()));
+        assert_eq!(eval(64, true, "-1 i16 hex"), Ok("0xffff".to_string()));
+        assert_eq!(eval(64, true, "8 oct"), Ok("0o10".to_string()));
+        assert_eq!(eval(64, true, "1295 36 base"), Ok("36rzz".to_string()));
+        assert_eq!(eval(64, true, "1295 3 base"), Ok("3r1202222".to_string()));
+        assert_eq!(eval(8, false, "1000 i64 255 +"), Ok("231".to_string()));
+        let hex = Word::evaluator(WordSize::new(16, false).unwrap(), 16);
+        let mut hex = hex;
+        assert_eq!(
+            hex.eval("0xfff0 0x20 +").map(|w| w.to_string()),
+            Ok("0x10".to_string())
+        );
+        assert_eq!(
+            hex.eval("10 7 5 do i loop + +").map(|w| w.to_string()),
+            Ok("0x15".to_string())
+        );
+    }
+}
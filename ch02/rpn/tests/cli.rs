@@ -0,0 +1,216 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn rpn(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rpn"))
+        .args(args)
+        .env_remove("RPN_FORMAT")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn evaluates_arguments() {
+    let output = rpn(&["6.1 5.2 4.3 * + 3.4 2.5 / 1.6 * -", "3 4 +"], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "26.2840\n7.0000\n");
+}
+
+#[test]
+fn precision_and_number_type() {
+    let output = rpn(&["-p", "2", "2 3 /"], "");
+    assert_eq!(stdout(&output), "0.67\n");
+    let output = rpn(&["-n", "rational", "2 3 /"], "");
+    assert_eq!(stdout(&output), "2/3\n");
+    let output = rpn(&["-n", "decimal", "-p", "6", "1 8 /"], "");
+    assert_eq!(stdout(&output), "0.125000\n");
+    for (number, exp, code) in [
+        ("decimal", "1e4000000000", 2),
+        ("decimal", "1e-400000000 1 +", 2),
+        ("rational", "2 1000000000 ^", 1),
+    ] {
+        let output = rpn(&["-n", number, exp], "");
+        assert_eq!(output.status.code(), Some(code), "{} {}", number, exp);
+    }
+    let output = rpn(&["-n", "units", "9.8m/s^2 2kg *", "90min h->min"], "");
+    assert_eq!(stdout(&output), "19.6000 kg*m/s^2\n90.0000 min\n");
+    let output = rpn(&["-n", "value", "-p", "0", "[1 2] 3+4i *"], "");
+    assert_eq!(stdout(&output), "[3+4i 6+8i]\n");
+    let output = rpn(
+        &[
+            "-n",
+            "interval",
+            "6.1±0.05 5.2±0.05 4.3 * + 3.4 2.5 / 1.6±0.05 * -",
+        ],
+        "",
+    );
+    assert_eq!(stdout(&output), "26.2840±0.3331\n");
+    let output = rpn(&["-n", "interval", "-p", "1", "0.045±0.06"], "");
+    assert_eq!(stdout(&output), "0.0±0.2\n");
+}
+
+#[test]
+fn dates_and_durations() {
+    let output = rpn(
+        &[
+            "-n",
+            "time",
+            "2024-02-28 1d +",
+            "2024-03-01 2024-02-28 - days",
+            "2024-01-31 1mo + weekday",
+            "2024-02-28 2 +",
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "2024-02-29\n2\n4\n");
+}
+
+#[test]
+fn number_formats() {
+    let output = rpn(
+        &[
+            "--format",
+            "comma:group",
+            "1.000,5 6,1 *",
+            "6,1 2 /",
+            "1.234 1 +",
+        ],
+        "",
+    );
+    assert_eq!(stdout(&output), "6.103,0500\n3,0500\n1.235,0000\n");
+    let output = rpn(&["--format", "comma:group", "6.1 2 /"], "");
+    assert_eq!(output.status.code(), Some(2));
+    let output = rpn(&["--format", "eng:sig3", "-n", "units", "4700m 3 *"], "");
+    assert_eq!(stdout(&output), "14.1e3 m\n");
+    let output = rpn(
+        &["-f", "sci", "-p", "2", "-n", "interval", "1,6±0,05 1000 *"],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(2));
+    let output = Command::new(env!("CARGO_BIN_EXE_rpn"))
+        .args(["-n", "rational", "1,5 2 *", "2 3 /"])
+        .env("RPN_FORMAT", "comma:sig3")
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&output), "3,00\n0,667\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_rpn"))
+        .arg("1 2 +")
+        .env("RPN_FORMAT", "roman")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(64));
+}
+
+#[test]
+fn symbolic_mode() {
+    let output = rpn(&["-n", "symbolic", "x 2 * 3 +", "x 3 ^ x * d/dx"], "");
+    assert_eq!(stdout(&output), "2 * x + 3\n3 * x ^ 2 * x + x ^ 3\n");
+    let output = rpn(&["-n", "symbolic", "--rpn-output", "x y + z * d/dz"], "");
+    assert_eq!(stdout(&output), "x y +\n");
+}
+
+#[test]
+fn programmer_mode() {
+    let output = rpn(
+        &["-w", "8", "-u", "--base", "16", "0xff 1 +", "0b1010 3 shl"],
+        "",
+    );
+    assert_eq!(stdout(&output), "0x0\n0x50\n");
+    let output = rpn(&["-w", "8", "127 1 +", "0x0f not"], "");
+    assert_eq!(stdout(&output), "-128\n-16\n");
+    let output = rpn(&["-n", "word", "1 63 shl 1 -"], "");
+    assert_eq!(stdout(&output), "9223372036854775807\n");
+    assert_eq!(rpn(&["-w", "12", "1"], "").status.code(), Some(64));
+}
+
+#[test]
+fn evaluates_piped_lines() {
+    let output = rpn(&["-n", "i64"], "1 2 +\n\n# comment\n: sq dup * ;\n5 sq\n");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "3\n25\n");
+}
+
+#[test]
+fn evaluates_files() {
+    let path = std::env::temp_dir().join(format!("rpn-cli-{}.rpn", std::process::id()));
+    std::fs::write(&path, "2 3 *\n1 0 /\n").unwrap();
+    let output = rpn(&["-n", "i64", "-f", path.to_str().unwrap()], "");
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(stdout(&output), "6\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(":2: /: division by zero"));
+}
+
+#[test]
+fn sessions_are_resumed() {
+    let path = std::env::temp_dir().join(format!("rpn-cli-{}.session", std::process::id()));
+    let path = path.to_str().unwrap();
+    let output = rpn(&["-i", "-s", path], "1 2\n: sq dup * ;\n5 x !\nquit\n");
+    assert!(output.status.success());
+    let output = rpn(&["-i", "-s", path], "sq x +\n");
+    std::fs::remove_file(path).unwrap();
+    assert!(stdout(&output).contains("> <2> 1 9\n"));
+}
+
+#[test]
+fn serves_json_requests() {
+    let requests = concat!(
+        "{\"id\":1,\"session\":\"s\",\"expr\":\"3 4 +\"}\n",
+        "{\"id\":2,\"session\":\"s\",\"expr\":\"x *\",\"vars\":{\"x\":2}}\n",
+        "{\"id\":3,\"expr\":\"sq\"}\n",
+    );
+    let output = rpn(&["--serve", "-n", "i64"], requests);
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        concat!(
+            "{\"id\":1,\"ok\":true,\"result\":7,\"stack\":[7]}\n",
+            "{\"id\":2,\"ok\":true,\"result\":14,\"stack\":[14]}\n",
+            "{\"id\":3,\"ok\":false,\"error\":{\"kind\":\"unknown_token\",",
+            "\"message\":\"Unknown operator: sq (at byte 0)\",\"span\":[0,2]},\"stack\":[]}\n",
+        )
+    );
+}
+
+#[test]
+fn exit_codes() {
+    assert_eq!(rpn(&["1 +"], "").status.code(), Some(1));
+    assert_eq!(rpn(&["1 2 ?"], "").status.code(), Some(2));
+    assert_eq!(rpn(&["--bogus"], "").status.code(), Some(64));
+    assert_eq!(
+        rpn(&["-f", "/nonexistent/formulas.rpn"], "").status.code(),
+        Some(66)
+    );
+    assert_eq!(rpn(&["-3 abs"], "").status.code(), Some(0));
+}
+
+#[test]
+fn errors_mark_the_offending_token() {
+    let output = rpn(&["1 2 foo +"], "");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "rpn: Unknown operator: foo (at byte 4)\n  1 2 foo +\n      ^^^\n"
+    );
+}
+
+#[test]
+fn converts_infix() {
+    let output = rpn(&["--to-rpn", "(1 + 2) * 3"], "");
+    assert_eq!(stdout(&output), "1 2 + 3 *\n");
+    assert_eq!(rpn(&["--to-rpn", "(1"], "").status.code(), Some(2));
+}
@@ -0,0 +1,369 @@
+//! Property tests: random expression trees are rendered to RPN and the
+//! evaluator is checked against evaluating the tree directly, and random
+//! token soup is checked not to panic. Failures print the seed to replay
+//! and a counterexample shrunk by [`minimize`]. Set `RPN_PROPTEST_CASES` to
+//! run more cases than the default.
+
+use std::panic;
+
+use rpn::number::{Decimal, Rational};
+use rpn::{infix, symbolic, Evaluator, Interval, Time, Units, Value, Word, WordSize};
+
+fn cases() -> u64 {
+    std::env::var("RPN_PROPTEST_CASES")
+        .ok()
+        .and_then(|cases| cases.parse().ok())
+        .unwrap_or(1000)
+}
+
+/// A small xorshift generator, so runs are reproducible from their seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+}
+
+/// Repeatedly replaces `value` with the first of its `smaller` candidates
+/// that still `fails`, until none does.
+fn minimize<T>(mut value: T, smaller: impl Fn(&T) -> Vec<T>, fails: impl Fn(&T) -> bool) -> T {
+    while let Some(next) = smaller(&value)
+        .into_iter()
+        .find(|candidate| fails(candidate))
+    {
+        value = next;
+    }
+    value
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Num(f64),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+const UNARY: &[&str] = &["neg", "abs", "sqrt"];
+const BINARY: &[&str] = &["+", "-", "*", "/", "^", "min", "max"];
+
+impl Expr {
+    fn generate(rng: &mut Rng, depth: usize) -> Expr {
+        match rng.below(if depth == 0 { 1 } else { 4 }) {
+            0 => {
+                let n = rng.below(20) as f64;
+                Expr::Num(if rng.below(4) == 0 { n / 2.0 } else { n })
+            }
+            1 => Expr::Unary(
+                UNARY[rng.below(UNARY.len())],
+                Box::new(Expr::generate(rng, depth - 1)),
+            ),
+            _ => Expr::Binary(
+                BINARY[rng.below(BINARY.len())],
+                Box::new(Expr::generate(rng, depth - 1)),
+                Box::new(Expr::generate(rng, depth - 1)),
+            ),
+        }
+    }
+
+    /// Simpler trees to try when shrinking: subtrees, and numbers nearer zero.
+    fn shrink(&self) -> Vec<Expr> {
+        match self {
+            Expr::Num(x) => [0.0, x.trunc()]
+                .iter()
+                .filter(|&&y| y != *x)
+                .map(|&y| Expr::Num(y))
+                .collect(),
+            Expr::Unary(op, x) => {
+                let mut smaller = vec![(**x).clone()];
+                smaller.extend(x.shrink().into_iter().map(|x| Expr::Unary(op, Box::new(x))));
+                smaller
+            }
+            Expr::Binary(op, x, y) => {
+                let mut smaller = vec![(**x).clone(), (**y).clone()];
+                smaller.extend(
+                    x.shrink()
+                        .into_iter()
+                        .map(|x| Expr::Binary(op, Box::new(x), y.clone())),
+                );
+                smaller.extend(
+                    y.shrink()
+                        .into_iter()
+                        .map(|y| Expr::Binary(op, x.clone(), Box::new(y))),
+                );
+                smaller
+            }
+        }
+    }
+
+    fn to_rpn(&self) -> String {
+        match self {
+            Expr::Num(x) => x.to_string(),
+            Expr::Unary(op, x) => format!("{} {}", x.to_rpn(), op),
+            Expr::Binary(op, x, y) => format!("{} {} {}", x.to_rpn(), y.to_rpn(), op),
+        }
+    }
+
+    /// Evaluates with the semantics the builtin `f64` operators document.
+    fn eval(&self) -> f64 {
+        match self {
+            Expr::Num(x) => *x,
+            Expr::Unary(op, x) => {
+                let x = x.eval();
+                match *op {
+                    "neg" => -x,
+                    "abs" => x.abs(),
+                    _ => x.sqrt(),
+                }
+            }
+            Expr::Binary(op, x, y) => {
+                let (x, y) = (x.eval(), y.eval());
+                match *op {
+                    "+" => x + y,
+                    "-" => x - y,
+                    "*" => x * y,
+                    "/" => x / y,
+                    "^" => x.powf(y),
+                    "min" => {
+                        if y < x {
+                            y
+                        } else {
+                            x
+                        }
+                    }
+                    _ => {
+                        if y > x {
+                            y
+                        } else {
+                            x
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn same(x: f64, y: f64) -> bool {
+    x == y || (x.is_nan() && y.is_nan())
+}
+
+/// Checks that evaluating and compiling `tree` give what the tree does.
+fn check_evaluation(tree: &Expr) -> Result<(), String> {
+    let exp = tree.to_rpn();
+    let expected = tree.eval();
+    let mut evaluator: Evaluator = Evaluator::new();
+    let ans = evaluator
+        .eval(&exp)
+        .map_err(|e| format!("{} failed: {}", exp, e))?;
+    if !same(ans, expected) {
+        return Err(format!("{} gave {} instead of {}", exp, ans, expected));
+    }
+    let program = evaluator
+        .compile(&exp)
+        .map_err(|e| format!("compiling {} failed: {}", exp, e))?;
+    match program.run(evaluator.env()) {
+        Ok(ans) if same(ans, expected) => Ok(()),
+        Ok(ans) => Err(format!("compiled {} gave {}", exp, ans)),
+        Err(e) => Err(format!("compiled {} failed: {}", exp, e)),
+    }
+}
+
+/// Checks that `tree` keeps its value through infix and back.
+fn check_infix_round_trip(tree: &Expr) -> Result<(), String> {
+    let exp = tree.to_rpn();
+    let infix = infix::to_infix(&exp).map_err(|e| format!("{}: {}", exp, e))?;
+    let back = infix::to_rpn(&infix).map_err(|e| format!("{}: {}", infix, e))?;
+    match rpn::rpn(&back) {
+        Ok(ans) if same(ans, tree.eval()) => Ok(()),
+        _ => Err(format!("{} became {} and then {}", exp, infix, back)),
+    }
+}
+
+/// Runs `check` on a random tree per case and reports the smallest failing
+/// tree it shrinks to.
+fn check_trees(check: fn(&Expr) -> Result<(), String>) {
+    for seed in 0..cases() {
+        let tree = Expr::generate(&mut Rng::new(seed), 5);
+        if check(&tree).is_err() {
+            let tree = minimize(tree, Expr::shrink, |tree| check(tree).is_err());
+            panic!("seed {}: {}", seed, check(&tree).unwrap_err());
+        }
+    }
+}
+
+#[test]
+fn evaluator_matches_tree_evaluation() {
+    check_trees(check_evaluation);
+}
+
+#[test]
+fn infix_round_trip_keeps_the_value() {
+    check_trees(check_infix_round_trip);
+}
+
+const SOUP: &[&str] = &[
+    "0",
+    "1",
+    "2",
+    "3",
+    "-1",
+    "0.5",
+    "9",
+    "1e308",
+    "3+4i",
+    "2km",
+    "5s",
+    "+",
+    "-",
+    "*",
+    "/",
+    "%",
+    "^",
+    "neg",
+    "abs",
+    "sqrt",
+    "ln",
+    "min",
+    "<",
+    "=",
+    "dup",
+    "drop",
+    "swap",
+    "clear",
+    "sum",
+    "mean",
+    "median",
+    "stddev",
+    "sort",
+    "count",
+    "roll",
+    "pick",
+    "if",
+    "else",
+    "then",
+    "times",
+    "do",
+    "loop",
+    "i",
+    ":",
+    ";",
+    "f",
+    "x",
+    "!",
+    "@",
+    "[",
+    "]",
+    "'f",
+    "'neg",
+    "map",
+    "km->m",
+    "dot",
+    "conj",
+    "0xff",
+    "0b1",
+    "and",
+    "not",
+    "shl",
+    "shr",
+    "u8",
+    "i16",
+    "hex",
+    "1±0.5",
+    "0±1",
+    "rad",
+    "±",
+    "2024-02-29",
+    "1d",
+    "P1M",
+    "14:30",
+    "days",
+    "months",
+    "weekday",
+    "friday",
+];
+
+#[test]
+fn deep_nesting_fails_cleanly() {
+    let n = 10_000;
+    let ifs = format!("1 {}1 {}", "1 if ".repeat(n), "then ".repeat(n));
+    let vectors = format!("{}{}", "[ ".repeat(n), "] ".repeat(n));
+    let nesting_limit = |result: Result<_, rpn::RpnError>| match result {
+        Err(e) => e.kind() == "nesting_limit",
+        Ok(_) => false,
+    };
+    assert!(nesting_limit(Evaluator::<f64>::new().eval(&ifs).map(drop)));
+    assert!(nesting_limit(
+        Evaluator::<f64>::new().compile(&ifs).map(drop)
+    ));
+    let mut values = Evaluator::with_operators(Value::operators());
+    assert!(nesting_limit(values.eval(&vectors).map(drop)));
+    assert!(Evaluator::<f64>::new().compile(&vectors).is_err());
+}
+
+fn soup(rng: &mut Rng) -> Vec<&'static str> {
+    let len = rng.below(16);
+    (0..len).map(|_| rng.pick(SOUP)).collect()
+}
+
+fn eval_everywhere(exp: &str) {
+    let _ = Evaluator::<f64>::new().eval(exp);
+    let _ = Evaluator::<i64>::new().eval(exp);
+    let _ = Evaluator::<Rational>::new().eval(exp);
+    let _ = Evaluator::<Decimal>::new().eval(exp);
+    let _ = Evaluator::<Value>::with_operators(Value::operators()).eval(exp);
+    let _ = Units::builtin().evaluator().eval(exp);
+    let _ = Evaluator::with_operators(Interval::operators()).eval(exp);
+    let _ = Evaluator::with_operators(symbolic::Expr::operators()).eval(exp);
+    let _ = Evaluator::with_operators(Time::operators()).eval(exp);
+    let _ = Word::evaluator(WordSize::new(8, true).unwrap(), 2).eval(exp);
+    let _ = Evaluator::<f64>::new().compile(exp);
+    let _ = Evaluator::<f64>::new().trace(exp, Some(8));
+    let _ = infix::to_infix(exp);
+    let _ = infix::to_rpn(exp);
+}
+
+fn panics(tokens: &[&str]) -> bool {
+    let exp = tokens.join(" ");
+    panic::catch_unwind(|| eval_everywhere(&exp)).is_err()
+}
+
+#[test]
+fn random_tokens_never_panic() {
+    for seed in 0..cases() {
+        let tokens = soup(&mut Rng::new(seed));
+        if panics(&tokens) {
+            // The first panic has been reported; keep the shrinking quiet.
+            let hook = panic::take_hook();
+            panic::set_hook(Box::new(|_| {}));
+            let smaller = |tokens: &Vec<&'static str>| {
+                (0..tokens.len())
+                    .map(|i| [&tokens[..i], &tokens[i + 1..]].concat())
+                    .collect()
+            };
+            let shrunk = minimize(tokens.clone(), smaller, |tokens| panics(tokens));
+            panic::set_hook(hook);
+            panic!(
+                "seed {}: `{}` panics, as does `{}`",
+                seed,
+                shrunk.join(" "),
+                tokens.join(" ")
+            );
+        }
+    }
+}